@@ -0,0 +1,91 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fasthtml::foreign::Namespace;
+use fasthtml::tokenizer::{InitialState, Tokenizer};
+use fasthtml::tokens::{Flags, TagID, Token};
+
+const ARTICLE: &str = include_str!("corpus/article.html");
+const REPORT: &str = include_str!("corpus/report.html");
+const SPA: &str = include_str!("corpus/spa.html");
+
+/// Inputs that stress a single state or transition, generated rather than vendored.
+fn pathological() -> Vec<(&'static str, String)> {
+    vec![
+        // One huge run of text with no markup at all
+        ("plain-text", "All work and no play makes Jack a dull boy. ".repeat(20_000)),
+        // Text that keeps looking like it's about to become a tag
+        ("less-than-signs", "a < b && c <= d; ".repeat(20_000)),
+        // Ampersands everywhere, both real references and not
+        ("ampersands", "Tom &amp; Jerry &copy; R&D &#169; &#x2014; & ".repeat(10_000)),
+        // Very deep nesting of short tags
+        ("deep-nesting", format!("{}{}", "<div><span>".repeat(20_000), "</span></div>".repeat(20_000))),
+        // Tags with lots of attributes
+        ("many-attributes", {
+            let attributes: String = (0..200).map(|i| format!(" data-a{}=\"{}\"", i, i)).collect();
+            format!("<div{}></div>", attributes).repeat(200)
+        }),
+        // Comments full of almost-closing sequences
+        ("comments", "<!-- a -- b --!- c <!- d - -->".repeat(20_000)),
+        // An unterminated comment swallowing the rest of the document
+        ("unterminated-comment", format!("<!--{}", "<p>never closed</p>".repeat(20_000))),
+    ]
+}
+
+/// Tokenizes the way the tree builder does, switching to the text states after the start tags
+/// whose contents aren't markup, so script bodies get measured as script and not as tags.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::new(html);
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_token() {
+        if token.flags & Flags::Close as u8 == 0 && token.namespace() == Namespace::Html {
+            let state = match token.token_id {
+                TagID::Title | TagID::TextArea => Some(InitialState::Rcdata),
+                TagID::Style | TagID::Xmp | TagID::IFrame | TagID::NoEmbed | TagID::NoFrames => Some(InitialState::Rawtext),
+                TagID::Script => Some(InitialState::ScriptData),
+                TagID::PlainText => Some(InitialState::Plaintext),
+                _ => None,
+            };
+            if let Some(state) = state {
+                tokenizer.set_state(state);
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn inputs() -> Vec<(&'static str, String)> {
+    let mut inputs = vec![
+        ("article", ARTICLE.to_string()),
+        ("report", REPORT.to_string()),
+        ("spa", SPA.to_string()),
+    ];
+    inputs.extend(pathological());
+    inputs
+}
+
+/// Reports MB/s.
+fn bench_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize-bytes");
+    for (name, html) in inputs() {
+        group.throughput(Throughput::Bytes(html.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), html.as_str(), |b, html| {
+            b.iter(|| tokenize(html))
+        });
+    }
+    group.finish();
+}
+
+/// Reports tokens/s.
+fn bench_tokens(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize-tokens");
+    for (name, html) in inputs() {
+        group.throughput(Throughput::Elements(tokenize(&html).len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), html.as_str(), |b, html| {
+            b.iter(|| tokenize(html))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_bytes, bench_tokens);
+criterion_main!(benches);
@@ -0,0 +1,450 @@
+// The document tree. Nodes live in one arena and refer to each other by index, and keep the
+// tokens they were made from, so names, attributes and text are still spans of the input and
+// only get decoded when asked for.
+
+use std::borrow::Cow;
+use std::ops::Index;
+use std::sync::OnceLock;
+
+use crate::errors;
+use crate::foreign::Namespace;
+use crate::quirks::QuirksMode;
+use crate::tokens::{self, TagID, Token};
+use crate::tree_builder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Element {
+    pub tag:       TagID,
+    pub namespace: Namespace,
+    pub token:     Token,          // The start tag, with an empty span for elements the parser made up
+    pub merged:    Vec<Token>,     // Later start tags whose new attributes were added (a second <body>)
+    pub contents:  Option<NodeId>, // The template contents fragment, for <template> elements
+}
+
+impl Element {
+    pub fn new(tag: TagID, namespace: Namespace, token: Token) -> Self {
+        Element {
+            tag,
+            namespace,
+            token,
+            merged: Vec::new(),
+            contents: None,
+        }
+    }
+
+    /// The element's name, adjusted for its namespace (see `Token::name`).
+    pub fn name<'a>(&self, raw_html: &'a str) -> Cow<'a, str> {
+        if self.namespace == Namespace::Html && self.tag != TagID::Undef {
+            Cow::Borrowed(self.tag.name())
+        } else {
+            self.token.name(raw_html)
+        }
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = AttributeRef<'_>> {
+        std::iter::once(&self.token).chain(self.merged.iter()).flat_map(|token| {
+            token.attributes.iter().flat_map(|attributes| attributes.iter()).map(move |attribute| {
+                AttributeRef { token, attribute }
+            })
+        })
+    }
+
+    /// The decoded value of the attribute called `name` (compared case-insensitively), if the
+    /// element has one.
+    pub fn attribute<'a>(&self, raw_html: &'a str, name: &str) -> Option<Cow<'a, str>> {
+        self.attributes()
+            .find(|attribute| attribute.name(raw_html).eq_ignore_ascii_case(name))
+            .map(|attribute| attribute.value(raw_html))
+    }
+
+    pub fn has_attribute(&self, raw_html: &str, name: &str) -> bool {
+        self.attributes().any(|attribute| attribute.name(raw_html).eq_ignore_ascii_case(name))
+    }
+}
+
+/// An attribute along with the start tag it was written in, which its offsets are relative to.
+#[derive(Clone, Copy, Debug)]
+pub struct AttributeRef<'e> {
+    pub token:     &'e Token,
+    pub attribute: &'e tokens::Attribute,
+}
+
+impl<'e> AttributeRef<'e> {
+    pub fn name<'a>(&self, raw_html: &'a str) -> &'a str {
+        self.attribute.name(self.token, raw_html)
+    }
+
+    pub fn qualified_name<'a>(&self, raw_html: &'a str) -> tokens::QualifiedName<'a> {
+        self.attribute.qualified_name(self.token, raw_html)
+    }
+
+    pub fn value<'a>(&self, raw_html: &'a str) -> Cow<'a, str> {
+        self.attribute.value(self.token, raw_html)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum NodeData {
+    Document,
+    TemplateContents(NodeId), // The fragment holding a template's contents, with the template
+    Doctype(Token),
+    Element(Element),
+    Text(Vec<Token>), // Text tokens that ended up next to each other, in order
+    Comment(Token),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub parent:       Option<NodeId>,
+    pub first_child:  Option<NodeId>,
+    pub last_child:   Option<NodeId>,
+    pub prev_sibling: Option<NodeId>,
+    pub next_sibling: Option<NodeId>,
+    pub data:         NodeData,
+}
+
+impl Node {
+    fn new(data: NodeData) -> Self {
+        Node {
+            parent: None,
+            first_child: None,
+            last_child: None,
+            prev_sibling: None,
+            next_sibling: None,
+            data,
+        }
+    }
+
+    pub fn as_element(&self) -> Option<&Element> {
+        match &self.data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    pub fn is_element(&self, tag: TagID) -> bool {
+        self.as_element().is_some_and(|element| element.tag == tag && element.namespace == Namespace::Html)
+    }
+}
+
+pub struct Document<'a> {
+    raw_html:        &'a str,
+    nodes:           Vec<Node>,
+    pub errors:      Vec<errors::ParseError>,
+    pub quirks_mode: QuirksMode, // Set from the doctype, or quirks when there's none
+    order:           OnceLock<Vec<u32>>, // Built when first asked for, and dropped on any change
+}
+
+impl<'a> Document<'a> {
+    /// An empty document, with just its root node.
+    pub fn new(raw_html: &'a str) -> Self {
+        Document {
+            raw_html,
+            nodes: vec![Node::new(NodeData::Document)],
+            errors: Vec::new(),
+            quirks_mode: QuirksMode::NoQuirks,
+            order: OnceLock::new(),
+        }
+    }
+
+    /// Tokenizes and parses `raw_html` into a tree.
+    pub fn parse(raw_html: &'a str) -> Self {
+        tree_builder::parse(raw_html)
+    }
+
+    /// Parses a snippet the way a `context` element's `innerHTML` would be, so a `<td>` stays
+    /// a cell in a `TagID::Tr` context. The snippet's nodes are the children of the root.
+    pub fn parse_fragment(raw_html: &'a str, context: TagID) -> Self {
+        tree_builder::parse_fragment(raw_html, context)
+    }
+
+    pub fn raw_html(&self) -> &'a str {
+        self.raw_html
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.order.take();
+        &mut self.nodes[id.index()]
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&Element> {
+        self.node(id).as_element()
+    }
+
+    /// Adds a node to the arena without putting it anywhere in the tree.
+    pub fn create_node(&mut self, data: NodeData) -> NodeId {
+        self.order.take();
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(Node::new(data));
+        id
+    }
+
+    /// Takes `id` (and everything under it) out of the tree. It stays in the arena.
+    pub fn detach(&mut self, id: NodeId) {
+        let node = self.node(id);
+        let (parent, prev, next) = (node.parent, node.prev_sibling, node.next_sibling);
+        match prev {
+            Some(prev) => self.node_mut(prev).next_sibling = next,
+            None => if let Some(parent) = parent { self.node_mut(parent).first_child = next },
+        }
+        match next {
+            Some(next) => self.node_mut(next).prev_sibling = prev,
+            None => if let Some(parent) = parent { self.node_mut(parent).last_child = prev },
+        }
+        let node = self.node_mut(id);
+        node.parent = None;
+        node.prev_sibling = None;
+        node.next_sibling = None;
+    }
+
+    /// Makes `child` the last child of `parent`, moving it if it was already in the tree.
+    pub fn append(&mut self, parent: NodeId, child: NodeId) {
+        self.detach(child);
+        let last = self.node(parent).last_child;
+        {
+            let node = self.node_mut(child);
+            node.parent = Some(parent);
+            node.prev_sibling = last;
+        }
+        match last {
+            Some(last) => self.node_mut(last).next_sibling = Some(child),
+            None => self.node_mut(parent).first_child = Some(child),
+        }
+        self.node_mut(parent).last_child = Some(child);
+    }
+
+    /// Puts `child` right before `sibling`, moving it if it was already in the tree.
+    pub fn insert_before(&mut self, sibling: NodeId, child: NodeId) {
+        self.detach(child);
+        let (parent, prev) = {
+            let node = self.node(sibling);
+            (node.parent, node.prev_sibling)
+        };
+        {
+            let node = self.node_mut(child);
+            node.parent = parent;
+            node.prev_sibling = prev;
+            node.next_sibling = Some(sibling);
+        }
+        self.node_mut(sibling).prev_sibling = Some(child);
+        match prev {
+            Some(prev) => self.node_mut(prev).next_sibling = Some(child),
+            None => if let Some(parent) = parent { self.node_mut(parent).first_child = Some(child) },
+        }
+    }
+
+    /// Moves every child of `from` to the end of `to`.
+    pub fn reparent_children(&mut self, from: NodeId, to: NodeId) {
+        while let Some(child) = self.node(from).first_child {
+            self.append(to, child);
+        }
+    }
+
+    /// Adds text to the end of `parent`, into its last child if that's already a text node.
+    pub fn append_text(&mut self, parent: NodeId, text: Token) {
+        if let Some(last) = self.node(parent).last_child {
+            if let NodeData::Text(tokens) = &mut self.node_mut(last).data {
+                tokens.push(text);
+                return;
+            }
+        }
+        let node = self.create_node(NodeData::Text(vec![text]));
+        self.append(parent, node);
+    }
+
+    /// Adds text right before `sibling`, into the text node before it if there is one.
+    pub fn insert_text_before(&mut self, sibling: NodeId, text: Token) {
+        if let Some(prev) = self.node(sibling).prev_sibling {
+            if let NodeData::Text(tokens) = &mut self.node_mut(prev).data {
+                tokens.push(text);
+                return;
+            }
+        }
+        let node = self.create_node(NodeData::Text(vec![text]));
+        self.insert_before(sibling, node);
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn children(&self, id: NodeId) -> Children<'_, 'a> {
+        Children {
+            document: self,
+            next: self.node(id).first_child,
+        }
+    }
+
+    /// The fragment a template's contents were parsed into. It isn't one of the template's
+    /// children, so traversals don't go into it unless asked to.
+    pub fn template_contents(&self, id: NodeId) -> Option<NodeId> {
+        self.element(id).and_then(|element| element.contents)
+    }
+
+    /// `id` and every node under it, in document order. Template contents are inert and left
+    /// out.
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_, 'a> {
+        Descendants {
+            document: self,
+            root: id,
+            next: Some(id),
+            templates: false,
+        }
+    }
+
+    /// Like `descendants`, but goes into template contents as well, right after each template.
+    pub fn descendants_with_templates(&self, id: NodeId) -> Descendants<'_, 'a> {
+        Descendants {
+            document: self,
+            root: id,
+            next: Some(id),
+            templates: true,
+        }
+    }
+
+    /// The position of every node in document order, indexed by `NodeId::index`, with template
+    /// contents included and `u32::MAX` for nodes that aren't in the tree.
+    pub(crate) fn document_order(&self) -> &[u32] {
+        self.order.get_or_init(|| {
+            let mut order = vec![u32::MAX; self.len()];
+            for (position, id) in self.descendants_with_templates(self.root()).enumerate() {
+                order[id.index()] = position as u32;
+            }
+            order
+        })
+    }
+
+    /// The parent of `id`, its parent, and so on up to the root.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_, 'a> {
+        Ancestors {
+            document: self,
+            next: self.node(id).parent,
+        }
+    }
+
+    /// The decoded text of a text or comment node, or of every text node under `id`.
+    pub fn text(&self, id: NodeId) -> String {
+        let mut text = String::new();
+        for node in self.descendants(id) {
+            match &self.node(node).data {
+                NodeData::Text(tokens) => {
+                    for token in tokens {
+                        text.push_str(&token.text(self.raw_html));
+                    }
+                }
+                NodeData::Comment(token) if node == id => text.push_str(&token.text(self.raw_html)),
+                _ => {}
+            }
+        }
+        text
+    }
+}
+
+impl Index<NodeId> for Document<'_> {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        self.node(id)
+    }
+}
+
+pub struct Children<'d, 'a> {
+    document: &'d Document<'a>,
+    next:     Option<NodeId>,
+}
+
+impl Iterator for Children<'_, '_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.node(id).next_sibling;
+        Some(id)
+    }
+}
+
+pub struct Descendants<'d, 'a> {
+    document:  &'d Document<'a>,
+    root:      NodeId,
+    next:      Option<NodeId>,
+    templates: bool,
+}
+
+impl Iterator for Descendants<'_, '_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        let node = self.document.node(id);
+        let contents = self.templates.then(|| self.document.template_contents(id)).flatten();
+        self.next = match contents.or(node.first_child) {
+            Some(child) => Some(child),
+            None => {
+                // Go back up until there's a next sibling, without leaving the subtree. Template
+                // contents lead back up to their template.
+                let mut current = id;
+                loop {
+                    if current == self.root {
+                        break None;
+                    }
+                    let node = self.document.node(current);
+                    if let Some(sibling) = node.next_sibling {
+                        break Some(sibling);
+                    }
+                    match (node.parent, &node.data) {
+                        (Some(parent), _) => current = parent,
+                        (None, NodeData::TemplateContents(template)) if self.templates => {
+                            // Then on to the template's own children, if something gave it any
+                            match self.document.node(*template).first_child {
+                                Some(child) => break Some(child),
+                                None => current = *template,
+                            }
+                        }
+                        (None, _) => break None,
+                    }
+                }
+            }
+        };
+        Some(id)
+    }
+}
+
+pub struct Ancestors<'d, 'a> {
+    document: &'d Document<'a>,
+    next:     Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_, '_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.document.node(id).parent;
+        Some(id)
+    }
+}
@@ -0,0 +1,133 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NestedComment,
+    NoncharacterCharacterReference,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    // Tree construction errors. The spec only names a few of them, the rest are named after
+    // the token that was out of place.
+    MissingDoctype,
+    UnexpectedDoctype,
+    UnexpectedStartTag,
+    UnexpectedEndTag,
+    UnexpectedCharacter,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    EofWithOpenElements,
+}
+
+impl ErrorKind {
+    /// The error code used by the WHATWG spec (and html5lib-tests) for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ErrorKind::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ErrorKind::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ErrorKind::AbsenceOfDigitsInNumericCharacterReference => "absence-of-digits-in-numeric-character-reference",
+            ErrorKind::CdataInHtmlContent => "cdata-in-html-content",
+            ErrorKind::CharacterReferenceOutsideUnicodeRange => "character-reference-outside-unicode-range",
+            ErrorKind::ControlCharacterReference => "control-character-reference",
+            ErrorKind::DuplicateAttribute => "duplicate-attribute",
+            ErrorKind::EndTagWithAttributes => "end-tag-with-attributes",
+            ErrorKind::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ErrorKind::EofBeforeTagName => "eof-before-tag-name",
+            ErrorKind::EofInCdata => "eof-in-cdata",
+            ErrorKind::EofInComment => "eof-in-comment",
+            ErrorKind::EofInDoctype => "eof-in-doctype",
+            ErrorKind::EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            ErrorKind::EofInTag => "eof-in-tag",
+            ErrorKind::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ErrorKind::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ErrorKind::InvalidCharacterSequenceAfterDoctypeName => "invalid-character-sequence-after-doctype-name",
+            ErrorKind::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ErrorKind::MissingAttributeValue => "missing-attribute-value",
+            ErrorKind::MissingDoctypeName => "missing-doctype-name",
+            ErrorKind::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ErrorKind::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ErrorKind::MissingEndTagName => "missing-end-tag-name",
+            ErrorKind::MissingQuoteBeforeDoctypePublicIdentifier => "missing-quote-before-doctype-public-identifier",
+            ErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier => "missing-quote-before-doctype-system-identifier",
+            ErrorKind::MissingSemicolonAfterCharacterReference => "missing-semicolon-after-character-reference",
+            ErrorKind::MissingWhitespaceAfterDoctypePublicKeyword => "missing-whitespace-after-doctype-public-keyword",
+            ErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword => "missing-whitespace-after-doctype-system-keyword",
+            ErrorKind::MissingWhitespaceBeforeDoctypeName => "missing-whitespace-before-doctype-name",
+            ErrorKind::MissingWhitespaceBetweenAttributes => "missing-whitespace-between-attributes",
+            ErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => "missing-whitespace-between-doctype-public-and-system-identifiers",
+            ErrorKind::NestedComment => "nested-comment",
+            ErrorKind::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ErrorKind::NullCharacterReference => "null-character-reference",
+            ErrorKind::SurrogateCharacterReference => "surrogate-character-reference",
+            ErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier => "unexpected-character-after-doctype-system-identifier",
+            ErrorKind::UnexpectedCharacterInAttributeName => "unexpected-character-in-attribute-name",
+            ErrorKind::UnexpectedCharacterInUnquotedAttributeValue => "unexpected-character-in-unquoted-attribute-value",
+            ErrorKind::UnexpectedEqualsSignBeforeAttributeName => "unexpected-equals-sign-before-attribute-name",
+            ErrorKind::UnexpectedNullCharacter => "unexpected-null-character",
+            ErrorKind::UnexpectedQuestionMarkInsteadOfTagName => "unexpected-question-mark-instead-of-tag-name",
+            ErrorKind::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ErrorKind::UnknownNamedCharacterReference => "unknown-named-character-reference",
+            ErrorKind::MissingDoctype => "missing-doctype",
+            ErrorKind::UnexpectedDoctype => "unexpected-doctype",
+            ErrorKind::UnexpectedStartTag => "unexpected-start-tag",
+            ErrorKind::UnexpectedEndTag => "unexpected-end-tag",
+            ErrorKind::UnexpectedCharacter => "unexpected-character",
+            ErrorKind::NonVoidHtmlElementStartTagWithTrailingSolidus => "non-void-html-element-start-tag-with-trailing-solidus",
+            ErrorKind::EofWithOpenElements => "eof-with-open-elements",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub kind:     ErrorKind,
+    pub position: u32,
+}
+
+impl ParseError {
+    pub fn new(kind: ErrorKind, position: u32) -> Self {
+        ParseError {
+            kind,
+            position,
+        }
+    }
+}
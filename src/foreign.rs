@@ -0,0 +1,307 @@
+// Namespaces, and the name adjustments the spec makes for elements and attributes in SVG and
+// MathML ("foreign content").
+
+use phf::phf_map;
+
+use crate::tokens::TagID;
+use crate::tree_builder::is_void;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+impl Namespace {
+    pub fn url(self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::Xmlns => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+/// The case-adjusted name of an SVG element, given its lowercase name, if it has one.
+pub fn adjust_svg_tag_name(name: &[u8]) -> Option<&'static str> {
+    SVG_TAG_NAMES.get(name).copied()
+}
+
+/// The case-adjusted name of an attribute on an SVG element, given its lowercase name.
+pub fn adjust_svg_attribute_name(name: &[u8]) -> Option<&'static str> {
+    SVG_ATTRIBUTE_NAMES.get(name).copied()
+}
+
+/// The case-adjusted name of an attribute on a MathML element, given its lowercase name.
+pub fn adjust_mathml_attribute_name(name: &[u8]) -> Option<&'static str> {
+    match name {
+        b"definitionurl" => Some("definitionURL"),
+        _ => None,
+    }
+}
+
+/// The prefix, local name and namespace of an attribute on a foreign element that the spec
+/// puts in a namespace (`xlink:href`, `xml:lang`, `xmlns`...), given its lowercase name.
+pub fn adjust_foreign_attribute(name: &[u8]) -> Option<(Option<&'static str>, &'static str, Namespace)> {
+    FOREIGN_ATTRIBUTES.get(name).copied()
+}
+
+/// Start tags that close every foreign element and go back to HTML, such as a `<p>` in the
+/// middle of an `<svg>`. A `<font>` only does so with a color, face or size attribute.
+pub fn is_breakout_tag(tag: TagID) -> bool {
+    matches!(tag, TagID::B | TagID::Big | TagID::Blockquote | TagID::Body | TagID::Br | TagID::Center |
+                  TagID::Code | TagID::DD | TagID::Div | TagID::Dl | TagID::Dt | TagID::Em | TagID::Embed |
+                  TagID::H1 | TagID::H2 | TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6 | TagID::Head |
+                  TagID::Hr | TagID::I | TagID::Img | TagID::Li | TagID::Listing | TagID::Menu |
+                  TagID::Meta | TagID::Nobr | TagID::Ol | TagID::Paragraph | TagID::Pre | TagID::Ruby |
+                  TagID::S | TagID::Small | TagID::Span | TagID::Strong | TagID::Strike | TagID::Sub |
+                  TagID::Sup | TagID::Table | TagID::Tt | TagID::U | TagID::Ul | TagID::Var)
+}
+
+/// Foreign elements whose content is parsed as HTML. `annotation-xml` only is one with an
+/// `encoding` of "text/html" or "application/xhtml+xml", which is up to the caller to check.
+pub fn is_html_integration_point(namespace: Namespace, tag: TagID) -> bool {
+    match namespace {
+        Namespace::Svg => matches!(tag, TagID::ForeignObject | TagID::Desc | TagID::Title),
+        _ => false,
+    }
+}
+
+pub fn is_mathml_text_integration_point(namespace: Namespace, tag: TagID) -> bool {
+    namespace == Namespace::MathMl && matches!(tag, TagID::Mi | TagID::Mo | TagID::Mn | TagID::Ms | TagID::MText)
+}
+
+struct Scope {
+    tag:          TagID,
+    name:         (u32, u32), // Span of the tag name, to tell unknown elements apart
+    namespace:    Namespace,
+    content:      Namespace,
+    text_content: bool,      // Content of a MathML text integration point
+    open_html:    u32,       // HTML elements open inside it, for integration points
+}
+
+/// Follows just enough of tree construction to tell which namespace each tag is in: the
+/// foreign elements that are open, integration points, and HTML tags breaking out of foreign
+/// content. HTML elements are never tracked, so it can't see every misnested case the tree
+/// builder would, but it's right for any markup that is well-formed around its SVG and MathML.
+pub(crate) struct NamespaceTracker {
+    scopes: Vec<Scope>,
+}
+
+impl NamespaceTracker {
+    pub fn new() -> Self {
+        NamespaceTracker {
+            scopes: Vec::new(),
+        }
+    }
+
+    /// The namespace that content is parsed in at this point.
+    pub fn content(&self) -> Namespace {
+        self.scopes.last().map_or(Namespace::Html, |scope| scope.content)
+    }
+
+    /// Whether the adjusted current node is a foreign element: the innermost one that's open,
+    /// with no HTML element opened inside it since. HTML end tags are only counted, so this
+    /// can be wrong when the HTML in an integration point is misnested.
+    pub fn in_foreign_element(&self) -> bool {
+        self.scopes.last().is_some_and(|scope| scope.open_html == 0)
+    }
+
+    fn break_out(&mut self) {
+        while self.content() != Namespace::Html {
+            self.scopes.pop();
+        }
+    }
+
+    /// Returns the namespace of a start tag, and opens it if it's a foreign element.
+    /// `breakout` and `html_annotation` tell whether the attributes make a `<font>` a
+    /// breakout tag or an `<annotation-xml>` an HTML integration point.
+    pub fn start_tag(&mut self, tag: TagID, name: (u32, u32), self_closing: bool, breakout: bool,
+                     html_annotation: bool) -> Namespace {
+        let (content, text_content, parent) = match self.scopes.last() {
+            Some(scope) => (scope.content, scope.text_content, scope.tag),
+            None => (Namespace::Html, false, TagID::Html),
+        };
+        let namespace = match content {
+            Namespace::Html if text_content && matches!(tag, TagID::MGlyph | TagID::MAlignMark) => Namespace::MathMl,
+            Namespace::Html => match tag {
+                TagID::Svg => Namespace::Svg,
+                TagID::Math => Namespace::MathMl,
+                _ => {
+                    if let Some(scope) = self.scopes.last_mut().filter(|_| !is_void(tag)) {
+                        scope.open_html += 1;
+                    }
+                    return Namespace::Html;
+                }
+            },
+            _ if breakout || is_breakout_tag(tag) => {
+                self.break_out();
+                return self.start_tag(tag, name, self_closing, false, html_annotation);
+            }
+            Namespace::MathMl if tag == TagID::Svg && parent == TagID::AnnotationXml => Namespace::Svg,
+            foreign => foreign,
+        };
+
+        if !self_closing {
+            let html_content = is_html_integration_point(namespace, tag) ||
+                               (namespace == Namespace::MathMl && tag == TagID::AnnotationXml && html_annotation);
+            let text_content = is_mathml_text_integration_point(namespace, tag);
+            self.scopes.push(Scope {
+                tag,
+                name,
+                namespace,
+                content: if html_content || text_content { Namespace::Html } else { namespace },
+                text_content,
+                open_html: 0,
+            });
+        }
+        namespace
+    }
+
+    /// Returns the namespace of an end tag, and closes the foreign element it matches, if
+    /// any. `same_name` compares the name of an open element with the end tag's.
+    pub fn end_tag(&mut self, tag: TagID, same_name: impl Fn((u32, u32)) -> bool) -> Namespace {
+        if self.content() != Namespace::Html && matches!(tag, TagID::Paragraph | TagID::Br) {
+            self.break_out();
+            return Namespace::Html;
+        }
+        let open = self.scopes.iter().rposition(|scope| {
+            scope.tag == tag && (tag != TagID::Undef || same_name(scope.name))
+        });
+        match open {
+            Some(index) => {
+                let namespace = self.scopes[index].namespace;
+                self.scopes.truncate(index);
+                namespace
+            }
+            None => {
+                if let Some(scope) = self.scopes.last_mut().filter(|scope| scope.content == Namespace::Html) {
+                    scope.open_html = scope.open_html.saturating_sub(1);
+                }
+                self.content()
+            }
+        }
+    }
+}
+
+static SVG_TAG_NAMES: phf::Map<&'static [u8], &'static str> = phf_map! {
+    b"altglyph" => "altGlyph",
+    b"altglyphdef" => "altGlyphDef",
+    b"altglyphitem" => "altGlyphItem",
+    b"animatecolor" => "animateColor",
+    b"animatemotion" => "animateMotion",
+    b"animatetransform" => "animateTransform",
+    b"clippath" => "clipPath",
+    b"feblend" => "feBlend",
+    b"fecolormatrix" => "feColorMatrix",
+    b"fecomponenttransfer" => "feComponentTransfer",
+    b"fecomposite" => "feComposite",
+    b"feconvolvematrix" => "feConvolveMatrix",
+    b"fediffuselighting" => "feDiffuseLighting",
+    b"fedisplacementmap" => "feDisplacementMap",
+    b"fedistantlight" => "feDistantLight",
+    b"fedropshadow" => "feDropShadow",
+    b"feflood" => "feFlood",
+    b"fefunca" => "feFuncA",
+    b"fefuncb" => "feFuncB",
+    b"fefuncg" => "feFuncG",
+    b"fefuncr" => "feFuncR",
+    b"fegaussianblur" => "feGaussianBlur",
+    b"feimage" => "feImage",
+    b"femerge" => "feMerge",
+    b"femergenode" => "feMergeNode",
+    b"femorphology" => "feMorphology",
+    b"feoffset" => "feOffset",
+    b"fepointlight" => "fePointLight",
+    b"fespecularlighting" => "feSpecularLighting",
+    b"fespotlight" => "feSpotLight",
+    b"fetile" => "feTile",
+    b"feturbulence" => "feTurbulence",
+    b"foreignobject" => "foreignObject",
+    b"glyphref" => "glyphRef",
+    b"lineargradient" => "linearGradient",
+    b"radialgradient" => "radialGradient",
+    b"textpath" => "textPath",
+};
+
+static SVG_ATTRIBUTE_NAMES: phf::Map<&'static [u8], &'static str> = phf_map! {
+    b"attributename" => "attributeName",
+    b"attributetype" => "attributeType",
+    b"basefrequency" => "baseFrequency",
+    b"baseprofile" => "baseProfile",
+    b"calcmode" => "calcMode",
+    b"clippathunits" => "clipPathUnits",
+    b"diffuseconstant" => "diffuseConstant",
+    b"edgemode" => "edgeMode",
+    b"filterunits" => "filterUnits",
+    b"glyphref" => "glyphRef",
+    b"gradienttransform" => "gradientTransform",
+    b"gradientunits" => "gradientUnits",
+    b"kernelmatrix" => "kernelMatrix",
+    b"kernelunitlength" => "kernelUnitLength",
+    b"keypoints" => "keyPoints",
+    b"keysplines" => "keySplines",
+    b"keytimes" => "keyTimes",
+    b"lengthadjust" => "lengthAdjust",
+    b"limitingconeangle" => "limitingConeAngle",
+    b"markerheight" => "markerHeight",
+    b"markerunits" => "markerUnits",
+    b"markerwidth" => "markerWidth",
+    b"maskcontentunits" => "maskContentUnits",
+    b"maskunits" => "maskUnits",
+    b"numoctaves" => "numOctaves",
+    b"pathlength" => "pathLength",
+    b"patterncontentunits" => "patternContentUnits",
+    b"patterntransform" => "patternTransform",
+    b"patternunits" => "patternUnits",
+    b"pointsatx" => "pointsAtX",
+    b"pointsaty" => "pointsAtY",
+    b"pointsatz" => "pointsAtZ",
+    b"preservealpha" => "preserveAlpha",
+    b"preserveaspectratio" => "preserveAspectRatio",
+    b"primitiveunits" => "primitiveUnits",
+    b"refx" => "refX",
+    b"refy" => "refY",
+    b"repeatcount" => "repeatCount",
+    b"repeatdur" => "repeatDur",
+    b"requiredextensions" => "requiredExtensions",
+    b"requiredfeatures" => "requiredFeatures",
+    b"specularconstant" => "specularConstant",
+    b"specularexponent" => "specularExponent",
+    b"spreadmethod" => "spreadMethod",
+    b"startoffset" => "startOffset",
+    b"stddeviation" => "stdDeviation",
+    b"stitchtiles" => "stitchTiles",
+    b"surfacescale" => "surfaceScale",
+    b"systemlanguage" => "systemLanguage",
+    b"tablevalues" => "tableValues",
+    b"targetx" => "targetX",
+    b"targety" => "targetY",
+    b"textlength" => "textLength",
+    b"viewbox" => "viewBox",
+    b"viewtarget" => "viewTarget",
+    b"xchannelselector" => "xChannelSelector",
+    b"ychannelselector" => "yChannelSelector",
+    b"zoomandpan" => "zoomAndPan",
+};
+
+static FOREIGN_ATTRIBUTES: phf::Map<&'static [u8], (Option<&'static str>, &'static str, Namespace)> = phf_map! {
+    b"xlink:actuate" => (Some("xlink"), "actuate", Namespace::XLink),
+    b"xlink:arcrole" => (Some("xlink"), "arcrole", Namespace::XLink),
+    b"xlink:href" => (Some("xlink"), "href", Namespace::XLink),
+    b"xlink:role" => (Some("xlink"), "role", Namespace::XLink),
+    b"xlink:show" => (Some("xlink"), "show", Namespace::XLink),
+    b"xlink:title" => (Some("xlink"), "title", Namespace::XLink),
+    b"xlink:type" => (Some("xlink"), "type", Namespace::XLink),
+    b"xml:lang" => (Some("xml"), "lang", Namespace::Xml),
+    b"xml:space" => (Some("xml"), "space", Namespace::Xml),
+    b"xmlns" => (None, "xmlns", Namespace::Xmlns),
+    b"xmlns:xlink" => (Some("xmlns"), "xlink", Namespace::Xmlns),
+};
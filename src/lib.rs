@@ -0,0 +1,17 @@
+pub mod dom;
+pub mod entities;
+pub mod errors;
+pub mod foreign;
+pub mod lines;
+pub mod quirks;
+pub mod rewriter;
+pub mod sanitizer;
+mod scan;
+pub mod selectors;
+pub mod serialize;
+pub mod stream_select;
+pub mod tokenizer;
+pub mod tokens;
+pub mod trace;
+pub mod tree_builder;
+pub mod xpath;
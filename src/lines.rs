@@ -0,0 +1,137 @@
+// Turns byte offsets (token and attribute spans, parse error positions) into line and column
+// numbers. The tokenizer itself only ever deals in offsets, so the index is built separately,
+// once per document, and only by callers that need it.
+
+use std::ops::Range;
+
+use crate::scan;
+use crate::tokens;
+
+/// A 1-based line and column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineCol {
+    pub line:   u32,
+    pub column: u32,
+}
+
+// How far apart, in bytes, the UTF-16 offsets are recorded
+const CHECKPOINT_SPACING: usize = 64;
+
+pub struct LineIndex<'a> {
+    raw_html:    &'a str,
+    line_starts: Vec<usize>,
+    checkpoints: Vec<(usize, usize)>, // Byte and UTF-16 offsets of a character every so often, none for ASCII input
+}
+
+impl<'a> LineIndex<'a> {
+    /// Finds where every line of `raw_html` starts. A line ends at "\n", "\r\n" or a lone "\r",
+    /// the same newlines the spec normalizes. Unless the input is ASCII, UTF-16 offsets are
+    /// recorded along the way too, so UTF-16 columns don't take counting a whole line.
+    pub fn new(raw_html: &'a str) -> Self {
+        let bytes = raw_html.as_bytes();
+        let mut line_starts = vec![0];
+        let mut position = scan::find_any(bytes, 0, [b'\n', b'\r']);
+        while position < bytes.len() {
+            if bytes[position] == b'\r' && bytes.get(position + 1) == Some(&b'\n') {
+                position += 1;
+            }
+            position += 1;
+            line_starts.push(position);
+            position = scan::find_any(bytes, position, [b'\n', b'\r']);
+        }
+        let mut checkpoints = Vec::new();
+        if !raw_html.is_ascii() {
+            let mut units = 0;
+            for (position, character) in raw_html.char_indices() {
+                if position >= checkpoints.len() * CHECKPOINT_SPACING {
+                    checkpoints.push((position, units));
+                }
+                units += character.len_utf16();
+            }
+        }
+        LineIndex {
+            raw_html,
+            line_starts,
+            checkpoints,
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Offsets past the end are taken as the end, and offsets inside a character as the start
+    /// of that character, so that every offset has a position.
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.raw_html.len());
+        while !self.raw_html.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// The UTF-16 offset of `offset`, which is on a character boundary.
+    fn utf16_offset(&self, offset: usize) -> usize {
+        if self.checkpoints.is_empty() {
+            return offset;
+        }
+        let (position, units) = self.checkpoints[self.checkpoints.partition_point(|&(position, _)| position <= offset) - 1];
+        units + self.raw_html[position..offset].encode_utf16().count()
+    }
+
+    /// The line and column of `offset`, with the column counted in bytes.
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let offset = self.clamp(offset);
+        let line = self.line_of(offset);
+        LineCol {
+            line:   line as u32 + 1,
+            column: (offset - self.line_starts[line]) as u32 + 1,
+        }
+    }
+
+    /// The line and column of `offset`, with the column counted in UTF-16 code units (what
+    /// LSP and most editors expect).
+    pub fn line_col_utf16(&self, offset: usize) -> LineCol {
+        let offset = self.clamp(offset);
+        let line = self.line_of(offset);
+        LineCol {
+            line:   line as u32 + 1,
+            column: (self.utf16_offset(offset) - self.utf16_offset(self.line_starts[line])) as u32 + 1,
+        }
+    }
+
+    /// Where `token` starts and ends, with byte columns.
+    pub fn token(&self, token: &tokens::Token) -> Range<LineCol> {
+        self.line_col(token.start as usize)..self.line_col(token.end as usize)
+    }
+
+    /// Where `token` starts and ends, with UTF-16 columns.
+    pub fn token_utf16(&self, token: &tokens::Token) -> Range<LineCol> {
+        self.line_col_utf16(token.start as usize)..self.line_col_utf16(token.end as usize)
+    }
+
+    /// Where `attribute`'s name starts and its value (if any) ends, with byte columns.
+    pub fn attribute(&self, token: &tokens::Token, attribute: &tokens::Attribute) -> Range<LineCol> {
+        let span = attribute_span(token, attribute);
+        self.line_col(span.start)..self.line_col(span.end)
+    }
+
+    /// Where `attribute`'s name starts and its value (if any) ends, with UTF-16 columns.
+    pub fn attribute_utf16(&self, token: &tokens::Token, attribute: &tokens::Attribute) -> Range<LineCol> {
+        let span = attribute_span(token, attribute);
+        self.line_col_utf16(span.start)..self.line_col_utf16(span.end)
+    }
+}
+
+fn attribute_span(token: &tokens::Token, attribute: &tokens::Attribute) -> Range<usize> {
+    let name = attribute.name_span(token);
+    let end = match attribute.quoting {
+        tokens::Quoting::Missing => name.end,
+        _ => attribute.value_span(token).end,
+    };
+    name.start..end
+}
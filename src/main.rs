@@ -1,14 +1,49 @@
-mod tokenizer;
-mod tokens;
+use fasthtml::dom::{Document, NodeData, NodeId};
+use fasthtml::tokenizer::Tokenizer;
+use fasthtml::trace;
 
-use tokens::Token;
-use tokenizer::Tokenizer;
+fn print_tree(document: &Document, id: NodeId, depth: usize) {
+    let raw_html = document.raw_html();
+    let indent = "  ".repeat(depth);
+    match &document[id].data {
+        NodeData::Document => println!("{}#document", indent),
+        NodeData::TemplateContents(_) => println!("{}#contents", indent),
+        NodeData::Doctype(token) => println!("{}<!DOCTYPE {}>", indent, token.text(raw_html)),
+        NodeData::Element(element) => {
+            print!("{}<{}", indent, element.name(raw_html));
+            for attribute in element.attributes() {
+                print!(" {}=\"{}\"", attribute.name(raw_html), attribute.value(raw_html));
+            }
+            println!(">");
+        }
+        NodeData::Text(_) => println!("{}{:?}", indent, document.text(id)),
+        NodeData::Comment(_) => println!("{}<!-- {} -->", indent, document.text(id)),
+    }
+    if let Some(contents) = document.template_contents(id) {
+        print_tree(document, contents, depth + 1);
+    }
+    for child in document.children(id) {
+        print_tree(document, child, depth + 1);
+    }
+}
 
 fn main() {
     let html = "<h1><p><body hello><h1><h1><h1><h1 id=\"guide-service\" id-two='hello' id-three=wow>";
-    let mut tokenizer = Tokenizer::new(html);
-    tokenizer.tokenize();
-    for token in tokenizer.tokens {
+    if std::env::args().any(|arg| arg == "--tree") {
+        let document = Document::parse(html);
+        print_tree(&document, document.root(), 0);
+        return;
+    }
+    let tokens = if std::env::args().any(|arg| arg == "--trace") {
+        let mut tokenizer = Tokenizer::with_observer(html, trace::PrintTrace);
+        tokenizer.tokenize();
+        tokenizer.tokens
+    } else {
+        let mut tokenizer = Tokenizer::new(html);
+        tokenizer.tokenize();
+        tokenizer.tokens
+    };
+    for token in tokens {
         token.print_self(html.as_bytes());
     }
 }
@@ -0,0 +1,115 @@
+// Which rendering mode a doctype puts the document in. Only a few tree construction rules
+// depend on it, but it's what decides how browsers lay the document out.
+
+use crate::tokens::{Flags, Token};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// Public identifiers that put a document in quirks mode when its doctype's starts with one
+/// of them (compared case-insensitively).
+static QUIRKY_PUBLIC_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+static QUIRKY_PUBLIC_IDENTIFIERS: &[&str] = &[
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+static QUIRKY_SYSTEM_IDENTIFIER: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+// HTML 4.01 Frameset and Transitional are quirky without a system identifier and only limited
+// quirky with one, the XHTML 1.0 ones are always limited quirky
+static HTML_401_PREFIXES: &[&str] = &["-//w3c//dtd html 4.01 frameset//", "-//w3c//dtd html 4.01 transitional//"];
+static XHTML_10_PREFIXES: &[&str] = &["-//w3c//dtd xhtml 1.0 frameset//", "-//w3c//dtd xhtml 1.0 transitional//"];
+
+fn starts_with_any(identifier: &str, prefixes: &[&str]) -> bool {
+    prefixes.iter().any(|prefix| {
+        identifier.len() >= prefix.len() && identifier.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    })
+}
+
+/// The mode a doctype token puts the document in.
+pub fn from_doctype(doctype: &Token, raw_html: &str) -> QuirksMode {
+    let (public, system) = doctype.doctype_identifiers(raw_html);
+    if doctype.flags & Flags::ForceQuirks as u8 != 0 || !doctype.text(raw_html).eq_ignore_ascii_case("html") {
+        return QuirksMode::Quirks;
+    }
+    if let Some(public) = &public {
+        if QUIRKY_PUBLIC_IDENTIFIERS.iter().any(|identifier| public.eq_ignore_ascii_case(identifier)) ||
+           starts_with_any(public, QUIRKY_PUBLIC_PREFIXES) ||
+           (system.is_none() && starts_with_any(public, HTML_401_PREFIXES)) {
+            return QuirksMode::Quirks;
+        }
+    }
+    if system.as_deref().is_some_and(|system| system.eq_ignore_ascii_case(QUIRKY_SYSTEM_IDENTIFIER)) {
+        return QuirksMode::Quirks;
+    }
+    if let Some(public) = &public {
+        if starts_with_any(public, XHTML_10_PREFIXES) || (system.is_some() && starts_with_any(public, HTML_401_PREFIXES)) {
+            return QuirksMode::LimitedQuirks;
+        }
+    }
+    QuirksMode::NoQuirks
+}
@@ -0,0 +1,479 @@
+// Rewriting HTML as it's tokenized, in the manner of lol_html: handlers registered for selectors
+// get to change the elements and text they match, and everything they leave alone is copied
+// from the input as it is. No tree is built. The open elements are tracked the way
+// `stream_select` does it, so handlers take the same selector subset, and elements end where it
+// says they do.
+
+use std::borrow::Cow;
+
+use crate::foreign::Namespace;
+use crate::selectors::SelectorError;
+use crate::serialize::{escape_attribute, escape_text};
+use crate::stream_select::{tag_end, text_state, OpenElement, OpenElements, StreamSelector};
+use crate::tokenizer::Tokenizer;
+use crate::tokens::{Flags, Quoting, TagID, Token};
+
+/// How content given to a handle is inserted: as markup, or as text that gets escaped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentType {
+    Html,
+    Text,
+}
+
+fn content(content: &str, content_type: ContentType) -> Cow<'_, str> {
+    match content_type {
+        ContentType::Html => Cow::Borrowed(content),
+        ContentType::Text => {
+            let mut escaped = String::new();
+            escape_text(content, &mut escaped);
+            Cow::Owned(escaped)
+        }
+    }
+}
+
+type ElementHandler<'h> = Box<dyn FnMut(&mut ElementHandle) + 'h>;
+type TextHandler<'h> = Box<dyn FnMut(&mut TextChunk) + 'h>;
+
+/// Selector-keyed handlers, which are called in the order they were added.
+#[derive(Default)]
+pub struct Rewriter<'h> {
+    element_handlers: Vec<(StreamSelector, ElementHandler<'h>)>,
+    text_handlers:    Vec<(StreamSelector, TextHandler<'h>)>,
+}
+
+impl<'h> Rewriter<'h> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `handler` with the start tag of every element `selectors` matches.
+    pub fn on_element(&mut self, selectors: &str, handler: impl FnMut(&mut ElementHandle) + 'h)
+                      -> Result<(), SelectorError> {
+        self.element_handlers.push((StreamSelector::parse(selectors)?, Box::new(handler)));
+        Ok(())
+    }
+
+    /// Calls `handler` with every chunk of text inside an element `selectors` matches, at any
+    /// depth.
+    pub fn on_text(&mut self, selectors: &str, handler: impl FnMut(&mut TextChunk) + 'h)
+                   -> Result<(), SelectorError> {
+        self.text_handlers.push((StreamSelector::parse(selectors)?, Box::new(handler)));
+        Ok(())
+    }
+
+    pub fn rewrite(&mut self, raw_html: &str) -> String {
+        let mut out = String::with_capacity(raw_html.len());
+        self.rewrite_with(raw_html, |chunk| out.push_str(chunk));
+        out
+    }
+
+    /// Rewrites `raw_html`, handing the output to `sink` in chunks as it goes.
+    pub fn rewrite_with(&mut self, raw_html: &str, sink: impl FnMut(&str)) {
+        let text_handlers = self.text_handlers.len();
+        let mut run = Run {
+            rewriter:    self,
+            raw_html,
+            tokenizer:   Tokenizer::new(raw_html),
+            elements:    OpenElements::default(),
+            states:      Vec::new(),
+            text_depths: vec![0; text_handlers],
+            emitted:     0,
+            suppressed:  None,
+            sink,
+        };
+        while let Some(token) = run.tokenizer.next_token() {
+            run.process(token);
+        }
+        run.flush(raw_html.len());
+    }
+}
+
+/// What's left to do for an open element when it ends.
+#[derive(Default)]
+struct ElementState {
+    append:        String,
+    after:         String,
+    drop_end_tag:  bool,
+    text_handlers: Vec<usize>, // The text handlers it matched
+}
+
+struct Run<'r, 'h, 'a, S: FnMut(&str)> {
+    rewriter:    &'r mut Rewriter<'h>,
+    raw_html:    &'a str,
+    tokenizer:   Tokenizer<'a>,
+    elements:    OpenElements,
+    states:      Vec<ElementState>, // One for each open element
+    text_depths: Vec<usize>,        // How many open elements each text handler matched
+    emitted:     usize,             // How much of the input has been copied or replaced
+    suppressed:  Option<usize>,     // The depth of the element whose content is left out, inside it
+    sink:        S,
+}
+
+impl<S: FnMut(&str)> Run<'_, '_, '_, S> {
+    /// Copies the input up to `to`, unless it's in content that's left out.
+    fn flush(&mut self, to: usize) {
+        if self.suppressed.is_none() && to > self.emitted {
+            (self.sink)(&self.raw_html[self.emitted..to]);
+            self.emitted = to;
+        }
+    }
+
+    /// Skips the input up to `to`.
+    fn skip(&mut self, to: usize) {
+        if self.suppressed.is_none() {
+            self.emitted = self.emitted.max(to);
+        }
+    }
+
+    fn write(&mut self, content: &str) {
+        if self.suppressed.is_none() && !content.is_empty() {
+            (self.sink)(content);
+        }
+    }
+
+    fn process(&mut self, token: Token) {
+        let raw_html = self.raw_html;
+        match token.token_id {
+            TagID::Text => self.text(&token),
+            TagID::EndOfFile => {
+                let closed = self.elements.close_all();
+                self.close(closed, raw_html.len(), raw_html.len());
+            }
+            TagID::EmComment | TagID::EmDoctype | TagID::EmTrivia => {}
+            _ if token.flags & Flags::Close as u8 != 0 => {
+                if let Some(closed) = self.elements.close(raw_html, &token) {
+                    self.close(closed, token.start as usize - 2, tag_end(raw_html, &token));
+                }
+            }
+            _ => self.start_tag(token),
+        }
+    }
+
+    fn text(&mut self, token: &Token) {
+        if self.suppressed.is_some() || self.text_depths.iter().all(|&depth| depth == 0) {
+            return;
+        }
+        let (start, end) = (token.start as usize, token.end as usize);
+        let mut chunk = TextChunk {
+            raw:         &self.raw_html[start..end],
+            text:        token.text(self.raw_html),
+            before:      String::new(),
+            after:       String::new(),
+            replacement: None,
+        };
+        for (index, (_, handler)) in self.rewriter.text_handlers.iter_mut().enumerate() {
+            if self.text_depths[index] > 0 {
+                handler(&mut chunk);
+            }
+        }
+        self.flush(start);
+        self.write(&chunk.before);
+        if let Some(replacement) = &chunk.replacement {
+            self.write(replacement);
+            self.skip(end);
+        }
+        self.flush(end);
+        self.write(&chunk.after);
+    }
+
+    fn start_tag(&mut self, token: Token) {
+        let raw_html = self.raw_html;
+        let start = token.start as usize - 1;
+        let closed = self.elements.close_implied(&token);
+        self.close(closed, start, start);
+        let end = tag_end(raw_html, &token);
+
+        let mut state = ElementState::default();
+        let mut handle = ElementHandle::new(raw_html, &token);
+        if self.suppressed.is_none() {
+            let ancestors = &self.elements.stack;
+            for (selector, handler) in self.rewriter.element_handlers.iter_mut() {
+                if selector.matches_start_tag(raw_html, &token, ancestors) {
+                    handler(&mut handle);
+                }
+            }
+            state.text_handlers = self.rewriter.text_handlers.iter().enumerate()
+                                      .filter(|(_, (selector, _))| selector.matches_start_tag(raw_html, &token, ancestors))
+                                      .map(|(index, _)| index)
+                                      .collect();
+        }
+        let (before, after, prepend, append) = (std::mem::take(&mut handle.before), std::mem::take(&mut handle.after),
+                                                std::mem::take(&mut handle.prepend), std::mem::take(&mut handle.append));
+        let (replacement, inner, unwrap) = (handle.replacement.take(), handle.inner.take(), handle.unwrap);
+        let start_tag = handle.start_tag();
+        if let Some(state) = text_state(&token) {
+            self.tokenizer.set_state(state);
+        }
+        let opened = self.elements.open(token, start, false);
+
+        self.flush(start);
+        self.write(&before);
+        let leaves_out_content = match replacement {
+            Some(replacement) => {
+                self.write(&replacement);
+                self.skip(end);
+                state.drop_end_tag = true;
+                true
+            }
+            None => {
+                match start_tag {
+                    _ if unwrap => self.skip(end),
+                    Some(start_tag) => {
+                        self.write(&start_tag);
+                        self.skip(end);
+                    }
+                    None => self.flush(end),
+                }
+                self.write(&prepend);
+                if let Some(inner) = &inner {
+                    self.write(inner);
+                }
+                state.append = append;
+                state.drop_end_tag = unwrap;
+                inner.is_some()
+            }
+        };
+        if !opened {
+            self.write(&after);
+            return;
+        }
+        state.after = after;
+        for &index in &state.text_handlers {
+            self.text_depths[index] += 1;
+        }
+        self.states.push(state);
+        if leaves_out_content && self.suppressed.is_none() {
+            self.suppressed = Some(self.states.len());
+        }
+    }
+
+    /// Ends the elements that were closed, innermost first. Their content ends at `inner_end`,
+    /// and so do they, except for the outermost one, which ends at `outer_end`, after its end
+    /// tag.
+    fn close(&mut self, closed: Vec<OpenElement>, inner_end: usize, outer_end: usize) {
+        let count = closed.len();
+        for index in 0..count {
+            let state = self.states.pop().unwrap();
+            for &handler in &state.text_handlers {
+                self.text_depths[handler] -= 1;
+            }
+            if self.suppressed == Some(self.states.len() + 1) {
+                self.suppressed = None;
+                self.emitted = inner_end;
+            }
+            let element_end = if index + 1 == count { outer_end } else { inner_end };
+            self.flush(inner_end);
+            self.write(&state.append);
+            if state.drop_end_tag {
+                self.skip(element_end);
+            } else {
+                self.flush(element_end);
+            }
+            self.write(&state.after);
+        }
+    }
+}
+
+/// An element a handler was called for, as of its start tag. Changes take effect once every
+/// handler has had it.
+pub struct ElementHandle<'t, 'a> {
+    raw_html:    &'a str,
+    token:       &'t Token,
+    changes:     Vec<(String, Option<String>)>, // New values of attributes, None for removed ones
+    before:      String,
+    after:       String,
+    prepend:     String,
+    append:      String,
+    inner:       Option<String>,
+    replacement: Option<String>,
+    unwrap:      bool,
+}
+
+impl<'t, 'a> ElementHandle<'t, 'a> {
+    fn new(raw_html: &'a str, token: &'t Token) -> Self {
+        ElementHandle {
+            raw_html,
+            token,
+            changes: Vec::new(),
+            before: String::new(),
+            after: String::new(),
+            prepend: String::new(),
+            append: String::new(),
+            inner: None,
+            replacement: None,
+            unwrap: false,
+        }
+    }
+
+    pub fn tag(&self) -> TagID {
+        self.token.token_id
+    }
+
+    pub fn name(&self) -> Cow<'a, str> {
+        self.token.name(self.raw_html)
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.token.namespace()
+    }
+
+    /// The value of the attribute `name`, with the changes made so far.
+    pub fn attribute(&self, name: &str) -> Option<Cow<'_, str>> {
+        if let Some((_, value)) = self.changes.iter().find(|(changed, _)| changed.eq_ignore_ascii_case(name)) {
+            return value.as_deref().map(Cow::Borrowed);
+        }
+        let token = self.token;
+        token.attributes.as_deref()?.iter()
+             .find(|attribute| attribute.name(token, self.raw_html).eq_ignore_ascii_case(name))
+             .map(|attribute| attribute.value(token, self.raw_html))
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attribute(name).is_some()
+    }
+
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.change(name, Some(value.to_string()));
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) {
+        self.change(name, None);
+    }
+
+    fn change(&mut self, name: &str, value: Option<String>) {
+        match self.changes.iter_mut().find(|(changed, _)| changed.eq_ignore_ascii_case(name)) {
+            Some(change) => change.1 = value,
+            None => self.changes.push((name.to_string(), value)),
+        }
+    }
+
+    /// Inserts content right before the start tag.
+    pub fn before(&mut self, content_: &str, content_type: ContentType) {
+        self.before.push_str(&content(content_, content_type));
+    }
+
+    /// Inserts content right after the element, or after its start tag for void elements.
+    pub fn after(&mut self, content_: &str, content_type: ContentType) {
+        self.after.insert_str(0, &content(content_, content_type));
+    }
+
+    /// Inserts content at the start of the element's content.
+    pub fn prepend(&mut self, content_: &str, content_type: ContentType) {
+        self.prepend.insert_str(0, &content(content_, content_type));
+    }
+
+    /// Inserts content at the end of the element's content.
+    pub fn append(&mut self, content_: &str, content_type: ContentType) {
+        self.append.push_str(&content(content_, content_type));
+    }
+
+    /// Replaces the element's content. Handlers aren't called for anything in the old content.
+    pub fn set_inner_content(&mut self, content_: &str, content_type: ContentType) {
+        self.inner = Some(content(content_, content_type).into_owned());
+    }
+
+    /// Replaces the whole element, tags included.
+    pub fn replace(&mut self, content_: &str, content_type: ContentType) {
+        self.replacement = Some(content(content_, content_type).into_owned());
+    }
+
+    pub fn remove(&mut self) {
+        self.replacement = Some(String::new());
+    }
+
+    /// Removes the start and end tags, and keeps what's between them.
+    pub fn remove_and_keep_content(&mut self) {
+        self.unwrap = true;
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.replacement.is_some()
+    }
+
+    /// The start tag with the attribute changes, or None if there are none. Attributes that
+    /// didn't change are copied as they were written.
+    fn start_tag(&self) -> Option<String> {
+        if self.changes.is_empty() {
+            return None;
+        }
+        let (raw_html, token) = (self.raw_html, self.token);
+        let mut changes: Vec<&(String, Option<String>)> = self.changes.iter().collect();
+        let mut tag = format!("<{}", &raw_html[token.start as usize..token.end as usize]);
+        let push_attribute = |tag: &mut String, name: &str, value: &str| {
+            tag.push(' ');
+            tag.push_str(name);
+            tag.push_str("=\"");
+            escape_attribute(value, tag);
+            tag.push('"');
+        };
+        for attribute in token.attributes.iter().flat_map(|attributes| attributes.iter()) {
+            let name = attribute.name(token, raw_html);
+            match changes.iter().position(|(changed, _)| changed.eq_ignore_ascii_case(name)) {
+                Some(index) => {
+                    if let Some(value) = &changes.remove(index).1 {
+                        push_attribute(&mut tag, name, value);
+                    }
+                }
+                None => {
+                    let end = match attribute.quoting {
+                        Quoting::DoubleQuoted | Quoting::SingleQuoted => attribute.value_span(token).end + 1,
+                        Quoting::Unquoted => attribute.value_span(token).end,
+                        Quoting::Missing => attribute.name_span(token).end,
+                    };
+                    tag.push(' ');
+                    tag.push_str(&raw_html[attribute.name_span(token).start..end]);
+                }
+            }
+        }
+        for (name, value) in changes {
+            if let Some(value) = value {
+                push_attribute(&mut tag, name, value);
+            }
+        }
+        // With a space, so an unquoted value before it doesn't take the slash
+        tag.push_str(if token.flags & Flags::CloseSelf as u8 != 0 { " />" } else { ">" });
+        Some(tag)
+    }
+}
+
+/// A run of text inside an element a text handler was registered for. The tokenizer can split
+/// a text into more than one chunk.
+pub struct TextChunk<'a> {
+    raw:         &'a str,
+    text:        Cow<'a, str>,
+    before:      String,
+    after:       String,
+    replacement: Option<String>,
+}
+
+impl<'a> TextChunk<'a> {
+    /// The text as it was written, references and all.
+    pub fn as_raw(&self) -> &'a str {
+        self.raw
+    }
+
+    /// The text with its references decoded.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn before(&mut self, content_: &str, content_type: ContentType) {
+        self.before.push_str(&content(content_, content_type));
+    }
+
+    pub fn after(&mut self, content_: &str, content_type: ContentType) {
+        self.after.insert_str(0, &content(content_, content_type));
+    }
+
+    pub fn replace(&mut self, content_: &str, content_type: ContentType) {
+        self.replacement = Some(content(content_, content_type).into_owned());
+    }
+
+    pub fn remove(&mut self) {
+        self.replacement = Some(String::new());
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.replacement.is_some()
+    }
+}
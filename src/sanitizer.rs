@@ -0,0 +1,372 @@
+// Cleaning untrusted HTML for rendering, with a policy built the way ammonia builds one. The input
+// is parsed as a fragment of a <body>, and the tree is written back out with only the elements
+// and attributes the policy allows. Elements that aren't allowed are replaced by their content,
+// except for the ones whose content has to go with them.
+//
+// Some things are never let through, whatever the policy says: <script> and <noscript> (which
+// browsers parse as raw text when scripting is on, unlike this parser), elements outside the
+// HTML namespace, "on*" event handler attributes, and "javascript:" and "vbscript:" URLs.
+// Attribute values are written with '<' and '>' escaped as well, so nothing in them can look
+// like markup to a parser that disagrees with this one.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::foreign::Namespace;
+use crate::serialize::{escape_text, has_raw_text, push_comment};
+use crate::tokens::TagID;
+use crate::tree_builder::is_void;
+
+/// Attributes whose value is a URL, and so has to pass the scheme filter.
+const URL_ATTRIBUTES: &[&str] = &["action", "background", "cite", "codebase", "data", "formaction", "href", "icon",
+                                  "longdesc", "manifest", "poster", "src"];
+
+const FORBIDDEN_SCHEMES: &[&str] = &["javascript", "vbscript"];
+
+/// Cleans `html` with the default policy.
+pub fn clean(html: &str) -> String {
+    Sanitizer::new().clean(html)
+}
+
+/// A sanitizing policy. `new` starts from a policy that allows common formatting markup and
+/// links, `empty` from one that allows nothing but text.
+#[derive(Clone, Debug)]
+pub struct Sanitizer {
+    tags:               HashSet<TagID>,
+    clean_content_tags: HashSet<TagID>, // Removed along with their content
+    tag_attributes:     HashMap<TagID, HashSet<String>>,
+    generic_attributes: HashSet<String>, // Allowed on every allowed element
+    url_schemes:        HashSet<String>,
+    url_relative:       bool,
+    link_rel:           Option<String>, // Set on links, in place of the rel they had
+    strip_comments:     bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn names(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        use TagID::*;
+        let tags = [A, Abbr, Acronym, Area, Article, Aside, B, Bdi, Bdo, Blockquote, Br, Caption, Center, Cite,
+                    Code, Col, ColGroup, Data, DD, Del, Details, Dfn, Div, Dl, Dt, Em, FigCaption, Figure, Footer,
+                    H1, H2, H3, H4, H5, H6, Header, Hgroup, Hr, I, Img, Ins, Kbd, Li, Map, Mark, Nav, Ol,
+                    Paragraph, Pre, Q, Rp, Rt, Rtc, Ruby, S, Samp, Small, Span, Strike, Strong, Sub, Summary, Sup,
+                    Table, Tbody, Td, TFoot, Th, Thead, Time, Tr, Tt, U, Ul, Var, Wbr];
+        let table_cell = ["align", "char", "charoff", "colspan", "headers", "rowspan"];
+        let table_part = ["align", "char", "charoff"];
+        let tag_attributes = [
+            (A, names(&["href", "hreflang"])),
+            (Bdo, names(&["dir"])),
+            (Blockquote, names(&["cite"])),
+            (Col, names(&["align", "char", "charoff", "span"])),
+            (ColGroup, names(&["align", "char", "charoff", "span"])),
+            (Del, names(&["cite", "datetime"])),
+            (Hr, names(&["align", "size", "width"])),
+            (Img, names(&["align", "alt", "height", "src", "width"])),
+            (Ins, names(&["cite", "datetime"])),
+            (Ol, names(&["start"])),
+            (Q, names(&["cite"])),
+            (Table, names(&["align", "char", "charoff", "summary"])),
+            (Tbody, names(&table_part)),
+            (Td, names(&table_cell)),
+            (TFoot, names(&table_part)),
+            (Th, names(&table_cell)),
+            (Thead, names(&table_part)),
+            (Tr, names(&table_part)),
+        ];
+        let url_schemes = ["bitcoin", "ftp", "ftps", "geo", "http", "https", "im", "irc", "ircs", "magnet",
+                           "mailto", "mms", "mx", "news", "nntp", "openpgp4fpr", "sip", "sms", "smsto", "ssh",
+                           "tel", "url", "webcal", "wtai", "xmpp"];
+        Sanitizer {
+            tags:               tags.into_iter().collect(),
+            clean_content_tags: [Script, Style].into_iter().collect(),
+            tag_attributes:     tag_attributes.into_iter().collect(),
+            generic_attributes: names(&["lang", "title"]),
+            url_schemes:        names(&url_schemes),
+            url_relative:       true,
+            link_rel:           Some("noopener noreferrer".to_string()),
+            strip_comments:     true,
+        }
+    }
+
+    pub fn empty() -> Self {
+        Sanitizer {
+            tags:               HashSet::new(),
+            clean_content_tags: HashSet::new(),
+            tag_attributes:     HashMap::new(),
+            generic_attributes: HashSet::new(),
+            url_schemes:        HashSet::new(),
+            url_relative:       false,
+            link_rel:           None,
+            strip_comments:     true,
+        }
+    }
+
+    /// Sets the elements that are kept. Allowing <style> or another element whose text is raw
+    /// keeps that text as it is; a style sheet can still load URLs.
+    pub fn tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        self.tags = tags.into_iter().collect();
+        self
+    }
+
+    pub fn add_tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        self.tags.extend(tags);
+        self
+    }
+
+    pub fn rm_tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        for tag in tags {
+            self.tags.remove(&tag);
+        }
+        self
+    }
+
+    /// Sets the elements that are removed with everything in them, rather than replaced by their
+    /// content. They're removed even if they're in `tags` too.
+    pub fn clean_content_tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        self.clean_content_tags = tags.into_iter().collect();
+        self
+    }
+
+    pub fn add_clean_content_tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        self.clean_content_tags.extend(tags);
+        self
+    }
+
+    pub fn rm_clean_content_tags(&mut self, tags: impl IntoIterator<Item = TagID>) -> &mut Self {
+        for tag in tags {
+            self.clean_content_tags.remove(&tag);
+        }
+        self
+    }
+
+    /// Allows attributes on one element, along with the generic ones.
+    pub fn add_tag_attributes<'n>(&mut self, tag: TagID, attributes: impl IntoIterator<Item = &'n str>) -> &mut Self {
+        let allowed = self.tag_attributes.entry(tag).or_default();
+        allowed.extend(attributes.into_iter().map(|name| name.to_ascii_lowercase()));
+        self
+    }
+
+    pub fn rm_tag_attributes<'n>(&mut self, tag: TagID, attributes: impl IntoIterator<Item = &'n str>) -> &mut Self {
+        if let Some(allowed) = self.tag_attributes.get_mut(&tag) {
+            for name in attributes {
+                allowed.remove(&name.to_ascii_lowercase());
+            }
+        }
+        self
+    }
+
+    /// Sets the attributes allowed on every element that's kept.
+    pub fn generic_attributes<'n>(&mut self, attributes: impl IntoIterator<Item = &'n str>) -> &mut Self {
+        self.generic_attributes = attributes.into_iter().map(|name| name.to_ascii_lowercase()).collect();
+        self
+    }
+
+    pub fn add_generic_attributes<'n>(&mut self, attributes: impl IntoIterator<Item = &'n str>) -> &mut Self {
+        self.generic_attributes.extend(attributes.into_iter().map(|name| name.to_ascii_lowercase()));
+        self
+    }
+
+    pub fn rm_generic_attributes<'n>(&mut self, attributes: impl IntoIterator<Item = &'n str>) -> &mut Self {
+        for name in attributes {
+            self.generic_attributes.remove(&name.to_ascii_lowercase());
+        }
+        self
+    }
+
+    /// Sets the schemes allowed in URL attributes. An attribute with a URL of another scheme is
+    /// removed.
+    pub fn url_schemes<'s>(&mut self, schemes: impl IntoIterator<Item = &'s str>) -> &mut Self {
+        self.url_schemes = schemes.into_iter().map(|scheme| scheme.to_ascii_lowercase()).collect();
+        self
+    }
+
+    pub fn add_url_schemes<'s>(&mut self, schemes: impl IntoIterator<Item = &'s str>) -> &mut Self {
+        self.url_schemes.extend(schemes.into_iter().map(|scheme| scheme.to_ascii_lowercase()));
+        self
+    }
+
+    pub fn rm_url_schemes<'s>(&mut self, schemes: impl IntoIterator<Item = &'s str>) -> &mut Self {
+        for scheme in schemes {
+            self.url_schemes.remove(&scheme.to_ascii_lowercase());
+        }
+        self
+    }
+
+    /// Whether URLs without a scheme are allowed.
+    pub fn url_relative(&mut self, allowed: bool) -> &mut Self {
+        self.url_relative = allowed;
+        self
+    }
+
+    /// The rel given to every <a> with an href, or None to leave rel to the attribute policy.
+    pub fn link_rel(&mut self, rel: Option<&str>) -> &mut Self {
+        self.link_rel = rel.map(str::to_string);
+        self
+    }
+
+    pub fn strip_comments(&mut self, strip: bool) -> &mut Self {
+        self.strip_comments = strip;
+        self
+    }
+
+    /// Cleans `html`, a fragment of a document's body.
+    pub fn clean(&self, html: &str) -> String {
+        let document = Document::parse_fragment(html, TagID::Body);
+        let mut out = String::with_capacity(html.len());
+        for child in document.children(document.root()) {
+            self.push_node(&document, child, false, &mut out);
+        }
+        out
+    }
+
+    fn push_node(&self, document: &Document, node: NodeId, raw_text: bool, out: &mut String) {
+        let raw_html = document.raw_html();
+        match &document[node].data {
+            NodeData::Element(element) => {
+                if self.removes_content(element) {
+                    return;
+                }
+                if !self.keeps(element) {
+                    // A template's contents aren't its children, so they go with it
+                    for child in document.children(node) {
+                        self.push_node(document, child, false, out);
+                    }
+                    return;
+                }
+                let name = element.name(raw_html);
+                out.push('<');
+                out.push_str(&name);
+                self.push_attributes(element, raw_html, out);
+                out.push('>');
+                if is_void(element.tag) {
+                    return;
+                }
+                // The same newline the serializer adds, for the one the parser drops
+                if matches!(element.tag, TagID::Pre | TagID::TextArea | TagID::Listing) {
+                    let first = document.children(node).next();
+                    if first.is_some_and(|first| matches!(document[first].data, NodeData::Text(_)) &&
+                                                 document.text(first).starts_with('\n')) {
+                        out.push('\n');
+                    }
+                }
+                let parent = document.template_contents(node).unwrap_or(node);
+                for child in document.children(parent) {
+                    self.push_node(document, child, has_raw_text(element.tag), out);
+                }
+                out.push_str("</");
+                out.push_str(&name);
+                out.push('>');
+            }
+            NodeData::Text(_) => {
+                let text = document.text(node);
+                if raw_text {
+                    out.push_str(&text);
+                } else {
+                    escape_text(&text, out);
+                }
+            }
+            NodeData::Comment(token) => {
+                if !self.strip_comments {
+                    push_comment(&token.text(raw_html), out);
+                }
+            }
+            NodeData::Doctype(_) => {}
+            NodeData::Document | NodeData::TemplateContents(_) => {
+                for child in document.children(node) {
+                    self.push_node(document, child, false, out);
+                }
+            }
+        }
+    }
+
+    fn removes_content(&self, element: &Element) -> bool {
+        matches!(element.tag, TagID::Script | TagID::NoScript) || self.clean_content_tags.contains(&element.tag)
+    }
+
+    fn keeps(&self, element: &Element) -> bool {
+        element.namespace == Namespace::Html && self.tags.contains(&element.tag)
+    }
+
+    fn push_attributes(&self, element: &Element, raw_html: &str, out: &mut String) {
+        let tag_attributes = self.tag_attributes.get(&element.tag);
+        let sets_rel = element.tag == TagID::A && self.link_rel.is_some();
+        let mut has_href = false;
+        for attribute in element.attributes() {
+            let qualified_name = attribute.qualified_name(raw_html);
+            let name = &*qualified_name.local;
+            if qualified_name.prefix.is_some() || name.starts_with("on") || (sets_rel && name == "rel") {
+                continue;
+            }
+            if !self.generic_attributes.contains(name) && !tag_attributes.is_some_and(|allowed| allowed.contains(name)) {
+                continue;
+            }
+            let value = attribute.value(raw_html);
+            let allowed = match name {
+                "srcset" => value.split(',').all(|candidate| {
+                    let url = candidate.split_ascii_whitespace().next().unwrap_or("");
+                    url.is_empty() || self.allows_url(url)
+                }),
+                _ if URL_ATTRIBUTES.contains(&name) => self.allows_url(&value),
+                _ => true,
+            };
+            if allowed {
+                has_href |= name == "href";
+                push_attribute(name, &value, out);
+            }
+        }
+        if let Some(rel) = self.link_rel.as_deref().filter(|_| sets_rel && has_href) {
+            push_attribute("rel", rel, out);
+        }
+    }
+
+    /// Whether `url` has an allowed scheme, or none when relative URLs are allowed. Browsers
+    /// ignore control characters and spaces around a URL, and tabs and newlines in it, so the
+    /// scheme is looked for without them.
+    fn allows_url(&self, url: &str) -> bool {
+        let url: String = url.trim_matches(|c: char| c <= ' ').chars().filter(|c| !matches!(c, '\t' | '\n' | '\r')).collect();
+        match scheme(&url) {
+            Some(scheme) => {
+                let scheme = scheme.to_ascii_lowercase();
+                !FORBIDDEN_SCHEMES.contains(&scheme.as_str()) && self.url_schemes.contains(&scheme)
+            }
+            None => self.url_relative,
+        }
+    }
+}
+
+/// The scheme of a URL, if it has one: what's before the first ':', when that comes before any
+/// '/', '?' or '#' and is a valid scheme.
+fn scheme(url: &str) -> Option<&str> {
+    let end = url.find([':', '/', '?', '#'])?;
+    let scheme = &url[..end];
+    let valid = url[end..].starts_with(':') &&
+                scheme.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+                scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+fn push_attribute(name: &str, value: &str, out: &mut String) {
+    out.push(' ');
+    out.push_str(name);
+    out.push_str("=\"");
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '"' => out.push_str("&quot;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
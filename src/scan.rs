@@ -0,0 +1,149 @@
+// Bulk scanning for the next "interesting" byte of a state, so that runs of plain text
+// don't go through the state machine one byte at a time.
+//
+// x86_64 always has SSE2, and AVX2 is used when the CPU has it: checked at run time, or not at
+// all when the crate is compiled with it enabled (e.g. `-C target-cpu=native`). Everything else
+// falls back to a plain loop.
+
+/// Returns the index of the first byte at or after `from` that is one of `needles`, or
+/// `haystack.len()` if there is none.
+#[inline(always)]
+pub fn find_any<const N: usize>(haystack: &[u8], from: usize, needles: [u8; N]) -> usize {
+    if from >= haystack.len() {
+        return haystack.len();
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    {
+        unsafe { find_any_avx2(haystack, from, needles) }
+    }
+    #[cfg(all(target_arch = "x86_64", not(target_feature = "avx2")))]
+    {
+        // std detects the CPU's features once and caches them, so this is a load and a test
+        if is_x86_feature_detected!("avx2") {
+            unsafe { find_any_avx2(haystack, from, needles) }
+        } else {
+            unsafe { find_any_sse2(haystack, from, needles) }
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        find_any_fallback(haystack, from, needles)
+    }
+}
+
+#[inline(always)]
+fn find_any_fallback<const N: usize>(haystack: &[u8], from: usize, needles: [u8; N]) -> usize {
+    match haystack[from..].iter().position(|byte| needles.contains(byte)) {
+        Some(offset) => from + offset,
+        None => haystack.len(),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn find_any_sse2<const N: usize>(haystack: &[u8], from: usize, needles: [u8; N]) -> usize {
+    use std::arch::x86_64::*;
+
+    let splats = needles.map(|needle| _mm_set1_epi8(needle as i8));
+    let mut position = from;
+    while position + 16 <= haystack.len() {
+        let chunk = _mm_loadu_si128(haystack.as_ptr().add(position) as *const __m128i);
+        let mut matches = _mm_setzero_si128();
+        for splat in splats.iter() {
+            matches = _mm_or_si128(matches, _mm_cmpeq_epi8(chunk, *splat));
+        }
+        let mask = _mm_movemask_epi8(matches) as u32;
+        if mask != 0 {
+            return position + mask.trailing_zeros() as usize;
+        }
+        position += 16;
+    }
+    find_any_fallback(haystack, position, needles)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn find_any_avx2<const N: usize>(haystack: &[u8], from: usize, needles: [u8; N]) -> usize {
+    use std::arch::x86_64::*;
+
+    let splats = needles.map(|needle| _mm256_set1_epi8(needle as i8));
+    let mut position = from;
+    while position + 32 <= haystack.len() {
+        let chunk = _mm256_loadu_si256(haystack.as_ptr().add(position) as *const __m256i);
+        let mut matches = _mm256_setzero_si256();
+        for splat in splats.iter() {
+            matches = _mm256_or_si256(matches, _mm256_cmpeq_epi8(chunk, *splat));
+        }
+        let mask = _mm256_movemask_epi8(matches) as u32;
+        if mask != 0 {
+            return position + mask.trailing_zeros() as usize;
+        }
+        position += 32;
+    }
+    find_any_sse2(haystack, position, needles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes around the needles, high ones included so signed comparisons would show
+    const FILLER: &[u8] = b"abcdefgh\x7f\x80\xfe";
+
+    /// Compares `scan` with a naive scan over haystacks of every length up to three 32-byte
+    /// chunks, with no needle and with one at every offset, from every starting point.
+    fn check<const N: usize>(scan: impl Fn(&[u8], usize, [u8; N]) -> usize, needles: [u8; N]) {
+        for length in 0..=96 {
+            let filler: Vec<u8> = (0..length).map(|index| FILLER[index % FILLER.len()]).collect();
+            let mut haystacks = vec![filler.clone()];
+            for position in 0..length {
+                let mut haystack = filler.clone();
+                haystack[position] = needles[position % N];
+                haystacks.push(haystack);
+            }
+            for haystack in &haystacks {
+                for from in 0..length {
+                    let expected = (from..length).find(|&index| needles.contains(&haystack[index])).unwrap_or(length);
+                    assert_eq!(scan(haystack, from, needles), expected, "{:?} from {}", haystack, from);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        check(find_any_fallback, [b'<']);
+        check(find_any_fallback, [b'&', b'<', b'\0']);
+        check(find_any_fallback, [b'"', b'&', 0x80, 0xff]);
+    }
+
+    #[test]
+    fn dispatched() {
+        check(find_any, [b'<']);
+        check(find_any, [b'&', b'<', b'\0']);
+        check(find_any, [b'"', b'&', 0x80, 0xff]);
+        assert_eq!(find_any(b"abc", 3, [b'a']), 3);
+        assert_eq!(find_any(b"abc", 4, [b'a']), 3);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sse2() {
+        check(|haystack, from, needles| unsafe { find_any_sse2(haystack, from, needles) }, [b'<']);
+        check(|haystack, from, needles| unsafe { find_any_sse2(haystack, from, needles) }, [b'&', b'<', b'\0']);
+        check(|haystack, from, needles| unsafe { find_any_sse2(haystack, from, needles) }, [b'"', b'&', 0x80, 0xff]);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn avx2() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        check(|haystack, from, needles| unsafe { find_any_avx2(haystack, from, needles) }, [b'<']);
+        check(|haystack, from, needles| unsafe { find_any_avx2(haystack, from, needles) }, [b'&', b'<', b'\0']);
+        check(|haystack, from, needles| unsafe { find_any_avx2(haystack, from, needles) }, [b'"', b'&', 0x80, 0xff]);
+    }
+}
@@ -0,0 +1,713 @@
+// CSS selectors over the document tree: parsing a selector list, and matching it against
+// elements right to left, the way browsers do. Type selectors are resolved to a `TagID` when
+// they're parsed, so most tag comparisons don't look at the input at all.
+
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::quirks::QuirksMode;
+use crate::tokens::{TagID, ASCII_TO_TAG_ID};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectorErrorKind {
+    EmptySelector,
+    UnexpectedEnd,
+    UnexpectedCharacter,
+    UnknownPseudoClass,
+    UnsupportedPseudoElement,
+    InvalidNth,
+    NotStreamable, // Valid, but outside the subset a stream can be matched against
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectorError {
+    pub kind:     SelectorErrorKind,
+    pub position: usize,
+}
+
+impl SelectorError {
+    pub fn new(kind: SelectorErrorKind, position: usize) -> Self {
+        SelectorError {
+            kind,
+            position,
+        }
+    }
+}
+
+/// A comma-separated list of selectors, which matches an element if any of them does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectorList {
+    pub(crate) selectors: Vec<Selector>,
+}
+
+/// Compound selectors joined by combinators. `combinators[i]` sits between `compounds[i]` and
+/// `compounds[i + 1]`, so the last compound is the one the matched element has to match.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Selector {
+    pub(crate) compounds:   Vec<Compound>,
+    pub(crate) combinators: Vec<Combinator>,
+}
+
+/// A selector in `:has()`, which starts with a combinator relating it to the element `:has()`
+/// is on.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RelativeSelector {
+    pub(crate) combinator: Combinator,
+    pub(crate) selector:   Selector,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+pub(crate) type Compound = Vec<Simple>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Simple {
+    Type(TagID, String), // The name is only compared for tags without an id
+    Id(String),
+    Class(String),
+    Attribute(AttributeSelector),
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    NthChild(Nth),
+    NthLastChild(Nth),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    Empty,
+    Root,
+    Not(SelectorList),
+    Is(SelectorList), // :where() too, they only differ in specificity
+    Has(Vec<RelativeSelector>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AttributeSelector {
+    pub(crate) name:             String,
+    pub(crate) operator:         Option<AttributeOperator>,
+    pub(crate) value:            String,
+    pub(crate) case_insensitive: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AttributeOperator {
+    Equals,     // [a=v]
+    Includes,   // [a~=v], one of the whitespace-separated words
+    DashMatch,  // [a|=v], v or v followed by "-"
+    Prefix,     // [a^=v]
+    Suffix,     // [a$=v]
+    Substring,  // [a*=v]
+}
+
+/// The `An+B` of `:nth-child()` and friends, which matches the 1-based positions `A*n + B`
+/// for some n >= 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Nth {
+    pub(crate) a: i32,
+    pub(crate) b: i32,
+}
+
+impl Nth {
+    pub(crate) fn matches(self, position: usize) -> bool {
+        let offset = position as i64 - self.b as i64;
+        match self.a {
+            0 => offset == 0,
+            a => offset % a as i64 == 0 && offset / a as i64 >= 0,
+        }
+    }
+}
+
+/// All the elements under the root that match `selectors`, in document order. Template
+/// contents aren't searched.
+pub fn select(document: &Document, selectors: &str) -> Result<Vec<NodeId>, SelectorError> {
+    Ok(SelectorList::parse(selectors)?.select(document))
+}
+
+impl SelectorList {
+    pub fn parse(selectors: &str) -> Result<Self, SelectorError> {
+        Self::parse_with(selectors, false)
+    }
+
+    /// Parses selectors that only use type, class, id and attribute selectors, and the
+    /// descendant and child combinators, which is all a token stream can be matched against.
+    pub(crate) fn parse_streaming(selectors: &str) -> Result<Self, SelectorError> {
+        Self::parse_with(selectors, true)
+    }
+
+    fn parse_with(selectors: &str, streaming: bool) -> Result<Self, SelectorError> {
+        let mut parser = Parser { input: selectors, position: 0, streaming };
+        let list = parser.selector_list()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(list),
+            Some(_) => Err(parser.error(SelectorErrorKind::UnexpectedCharacter)),
+        }
+    }
+
+    pub fn select(&self, document: &Document) -> Vec<NodeId> {
+        document.descendants(document.root()).filter(|&node| self.matches(document, node)).collect()
+    }
+
+    /// Whether the element `node` matches any of the selectors. Nodes that aren't elements
+    /// never do.
+    pub fn matches(&self, document: &Document, node: NodeId) -> bool {
+        document.element(node).is_some() &&
+        self.selectors.iter().any(|selector| selector.matches(document, node, None))
+    }
+}
+
+// Matching
+
+/// How matching part of a selector against an element went. A failure says how far back the
+/// search has to go for another element to stand a chance, which keeps selectors with several
+/// descendant or sibling combinators from retrying every combination of ancestors (the scheme
+/// Servo and Blink use).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MatchResult {
+    Matched,
+    // Another element to the left or further up may still match
+    NotMatchedRestartFromClosestLaterSibling,
+    // Only trying another ancestor for the closest descendant combinator may still match
+    NotMatchedRestartFromClosestDescendant,
+    // No other element can match either
+    NotMatchedGlobally,
+}
+
+impl Selector {
+    /// Matches right to left, backtracking over ancestors and siblings for the combinators
+    /// that allow more than one. `anchor` is the element a relative selector is relative to.
+    fn matches(&self, document: &Document, node: NodeId, anchor: Option<(Combinator, NodeId)>) -> bool {
+        self.matches_from(document, self.compounds.len() - 1, node, anchor) == MatchResult::Matched
+    }
+
+    fn matches_from(&self, document: &Document, index: usize, node: NodeId,
+                    anchor: Option<(Combinator, NodeId)>) -> MatchResult {
+        if !compound_matches(document, &self.compounds[index], node) {
+            return MatchResult::NotMatchedRestartFromClosestLaterSibling;
+        }
+        if index == 0 {
+            return match anchor {
+                Some((combinator, anchor)) if !related(document, combinator, anchor, node) => {
+                    MatchResult::NotMatchedRestartFromClosestLaterSibling
+                }
+                _ => MatchResult::Matched,
+            };
+        }
+        let combinator = self.combinators[index - 1];
+        let step = |candidate: NodeId| match combinator {
+            Combinator::Child | Combinator::Descendant => parent_element(document, candidate),
+            Combinator::NextSibling | Combinator::SubsequentSibling => previous_element(document, candidate),
+        };
+        let mut candidate = step(node);
+        while let Some(element) = candidate {
+            let result = self.matches_from(document, index - 1, element, anchor);
+            match (result, combinator) {
+                (MatchResult::Matched, _) | (MatchResult::NotMatchedGlobally, _) | (_, Combinator::NextSibling) => {
+                    return result;
+                }
+                (_, Combinator::Child) => return MatchResult::NotMatchedRestartFromClosestDescendant,
+                (MatchResult::NotMatchedRestartFromClosestDescendant, Combinator::SubsequentSibling) => {
+                    return result;
+                }
+                _ => {}
+            }
+            candidate = step(element);
+        }
+        // Ran out of ancestors or siblings to try
+        match combinator {
+            Combinator::Child | Combinator::Descendant => MatchResult::NotMatchedGlobally,
+            Combinator::NextSibling | Combinator::SubsequentSibling => MatchResult::NotMatchedRestartFromClosestDescendant,
+        }
+    }
+}
+
+/// Whether `node` is where `combinator` leads to from `anchor` (a descendant, a child...).
+fn related(document: &Document, combinator: Combinator, anchor: NodeId, node: NodeId) -> bool {
+    match combinator {
+        Combinator::Descendant => document.ancestors(node).any(|ancestor| ancestor == anchor),
+        Combinator::Child => document.parent(node) == Some(anchor),
+        Combinator::NextSibling => previous_element(document, node) == Some(anchor),
+        Combinator::SubsequentSibling => {
+            std::iter::successors(previous_element(document, node), |&sibling| previous_element(document, sibling))
+                .any(|sibling| sibling == anchor)
+        }
+    }
+}
+
+fn parent_element(document: &Document, node: NodeId) -> Option<NodeId> {
+    document.parent(node).filter(|&parent| document.element(parent).is_some())
+}
+
+fn previous_element(document: &Document, node: NodeId) -> Option<NodeId> {
+    std::iter::successors(document.node(node).prev_sibling, |&sibling| document.node(sibling).prev_sibling)
+        .find(|&sibling| document.element(sibling).is_some())
+}
+
+fn next_element(document: &Document, node: NodeId) -> Option<NodeId> {
+    std::iter::successors(document.node(node).next_sibling, |&sibling| document.node(sibling).next_sibling)
+        .find(|&sibling| document.element(sibling).is_some())
+}
+
+fn same_type(document: &Document, a: &Element, b: &Element) -> bool {
+    a.tag == b.tag && a.namespace == b.namespace &&
+    (a.tag != TagID::Undef || a.name(document.raw_html()) == b.name(document.raw_html()))
+}
+
+/// The 1-based position of `node` among its element siblings, counting from the start or the
+/// end, and only counting siblings of the same type if `of_type` is set.
+fn position(document: &Document, node: NodeId, from_end: bool, of_type: bool) -> usize {
+    let element = document.element(node).unwrap();
+    let step = |&sibling: &NodeId| if from_end { next_element(document, sibling) } else { previous_element(document, sibling) };
+    std::iter::successors(step(&node), step)
+        .filter(|&sibling| !of_type || same_type(document, element, document.element(sibling).unwrap()))
+        .count() + 1
+}
+
+fn compound_matches(document: &Document, compound: &Compound, node: NodeId) -> bool {
+    let element = match document.element(node) {
+        Some(element) => element,
+        None => return false,
+    };
+    compound.iter().all(|simple| simple_matches(document, simple, node, element))
+}
+
+fn simple_matches(document: &Document, simple: &Simple, node: NodeId, element: &Element) -> bool {
+    let raw_html = document.raw_html();
+    // Quirks mode documents match classes and ids case-insensitively
+    let quirks = document.quirks_mode == QuirksMode::Quirks;
+    let same_name = |a: &str, b: &str| if quirks { a.eq_ignore_ascii_case(b) } else { a == b };
+    match simple {
+        Simple::Type(tag, name) => {
+            element.tag == *tag && (*tag != TagID::Undef || element.name(raw_html).eq_ignore_ascii_case(name))
+        }
+        Simple::Id(id) => element.attribute(raw_html, "id").is_some_and(|value| same_name(&value, id)),
+        Simple::Class(class) => element.attribute(raw_html, "class").is_some_and(|value| {
+            value.split_ascii_whitespace().any(|word| same_name(word, class))
+        }),
+        Simple::Attribute(selector) => element.attributes().any(|attribute| {
+            attribute.name(raw_html).eq_ignore_ascii_case(&selector.name) &&
+            selector.value_matches(&attribute.value(raw_html))
+        }),
+        Simple::FirstChild => previous_element(document, node).is_none(),
+        Simple::LastChild => next_element(document, node).is_none(),
+        Simple::OnlyChild => previous_element(document, node).is_none() && next_element(document, node).is_none(),
+        Simple::FirstOfType => position(document, node, false, true) == 1,
+        Simple::LastOfType => position(document, node, true, true) == 1,
+        Simple::OnlyOfType => position(document, node, false, true) == 1 && position(document, node, true, true) == 1,
+        Simple::NthChild(nth) => nth.matches(position(document, node, false, false)),
+        Simple::NthLastChild(nth) => nth.matches(position(document, node, true, false)),
+        Simple::NthOfType(nth) => nth.matches(position(document, node, false, true)),
+        Simple::NthLastOfType(nth) => nth.matches(position(document, node, true, true)),
+        // Comments don't count, and the tree builder never makes empty text nodes
+        Simple::Empty => document.children(node).all(|child| matches!(document[child].data, NodeData::Comment(_))),
+        Simple::Root => document.parent(node).is_some_and(|parent| matches!(document[parent].data, NodeData::Document)),
+        Simple::Not(list) => !list.matches(document, node),
+        Simple::Is(list) => list.matches(document, node),
+        Simple::Has(relative) => relative.iter().any(|relative| relative.matches_from(document, node)),
+    }
+}
+
+impl AttributeSelector {
+    pub(crate) fn value_matches(&self, value: &str) -> bool {
+        let operator = match self.operator {
+            Some(operator) => operator,
+            None => return true,
+        };
+        let (value, expected) = if self.case_insensitive {
+            (value.to_ascii_lowercase(), self.value.to_ascii_lowercase())
+        } else {
+            (value.to_string(), self.value.clone())
+        };
+        match operator {
+            AttributeOperator::Equals => value == expected,
+            AttributeOperator::Includes => value.split_ascii_whitespace().any(|word| word == expected),
+            AttributeOperator::DashMatch => {
+                value == expected || value.strip_prefix(&expected).is_some_and(|rest| rest.starts_with('-'))
+            }
+            // An empty value is never a prefix, suffix or substring of anything
+            AttributeOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+            AttributeOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+            AttributeOperator::Substring => !expected.is_empty() && value.contains(&expected),
+        }
+    }
+}
+
+impl RelativeSelector {
+    /// Whether some element related to `anchor` the way this selector says matches it.
+    fn matches_from(&self, document: &Document, anchor: NodeId) -> bool {
+        let anchored = Some((self.combinator, anchor));
+        let selector = &self.selector;
+        match self.combinator {
+            Combinator::Descendant | Combinator::Child => {
+                // Whatever the selector matches has to be under the anchor either way
+                document.descendants(anchor).skip(1).any(|node| {
+                    document.element(node).is_some() && selector.matches(document, node, anchored)
+                })
+            }
+            Combinator::NextSibling | Combinator::SubsequentSibling => {
+                // The rightmost compound can be anywhere in the following siblings' subtrees
+                std::iter::successors(next_element(document, anchor), |&sibling| next_element(document, sibling))
+                    .flat_map(|sibling| document.descendants(sibling))
+                    .any(|node| document.element(node).is_some() && selector.matches(document, node, anchored))
+            }
+        }
+    }
+}
+
+// Parsing
+
+struct Parser<'s> {
+    input:     &'s str,
+    position:  usize,
+    streaming: bool, // Reject everything that needs more than the open elements to match
+}
+
+impl Parser<'_> {
+    fn error(&self, kind: SelectorErrorKind) -> SelectorError {
+        let kind = if kind == SelectorErrorKind::UnexpectedCharacter && self.peek().is_none() {
+            SelectorErrorKind::UnexpectedEnd
+        } else {
+            kind
+        };
+        SelectorError::new(kind, self.position)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.input[self.position..].chars().nth(offset)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SelectorError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(SelectorErrorKind::UnexpectedCharacter))
+        }
+    }
+
+    /// Skips whitespace, and says whether there was any.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C')) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn selector_list(&mut self) -> Result<SelectorList, SelectorError> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            selectors.push(self.selector()?);
+            self.skip_whitespace();
+            if !self.eat(',') {
+                break;
+            }
+        }
+        Ok(SelectorList { selectors })
+    }
+
+    fn relative_selector_list(&mut self) -> Result<Vec<RelativeSelector>, SelectorError> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let combinator = self.combinator().unwrap_or(Combinator::Descendant);
+            self.skip_whitespace();
+            selectors.push(RelativeSelector { combinator, selector: self.selector()? });
+            self.skip_whitespace();
+            if !self.eat(',') {
+                break;
+            }
+        }
+        Ok(selectors)
+    }
+
+    fn combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek()? {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            '~' => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+        self.position += 1;
+        Some(combinator)
+    }
+
+    fn selector(&mut self) -> Result<Selector, SelectorError> {
+        let mut compounds = vec![self.compound()?];
+        let mut combinators = Vec::new();
+        loop {
+            let whitespace = self.skip_whitespace();
+            let start = self.position;
+            let combinator = match self.combinator() {
+                Some(Combinator::NextSibling | Combinator::SubsequentSibling) if self.streaming => {
+                    return Err(SelectorError::new(SelectorErrorKind::NotStreamable, start))
+                }
+                Some(combinator) => combinator,
+                None if whitespace && self.peek().is_some_and(|c| c != ',' && c != ')') => Combinator::Descendant,
+                None => break,
+            };
+            self.skip_whitespace();
+            combinators.push(combinator);
+            compounds.push(self.compound()?);
+        }
+        Ok(Selector { compounds, combinators })
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let start = self.position;
+        let mut compound = Vec::new();
+        if self.eat('*') {
+            // The universal selector matches anything, so there's nothing to keep
+        } else if self.starts_identifier() {
+            let name = self.identifier()?;
+            let tag = ASCII_TO_TAG_ID.get(name.to_ascii_lowercase().as_bytes()).copied().unwrap_or(TagID::Undef);
+            compound.push(Simple::Type(tag, name));
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    compound.push(Simple::Id(self.identifier()?));
+                }
+                Some('.') => {
+                    self.position += 1;
+                    compound.push(Simple::Class(self.identifier()?));
+                }
+                Some('[') => compound.push(Simple::Attribute(self.attribute()?)),
+                Some(':') if self.streaming => return Err(self.error(SelectorErrorKind::NotStreamable)),
+                Some(':') => compound.push(self.pseudo_class()?),
+                _ => break,
+            }
+        }
+        if self.position == start {
+            return Err(match self.peek() {
+                None | Some(',') | Some(')') => SelectorError::new(SelectorErrorKind::EmptySelector, self.position),
+                Some(_) => self.error(SelectorErrorKind::UnexpectedCharacter),
+            });
+        }
+        Ok(compound)
+    }
+
+    fn attribute(&mut self) -> Result<AttributeSelector, SelectorError> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        let name = self.identifier()?;
+        self.skip_whitespace();
+        let operator = match (self.peek(), self.peek_at(1)) {
+            (Some('='), _) => Some((AttributeOperator::Equals, 1)),
+            (Some('~'), Some('=')) => Some((AttributeOperator::Includes, 2)),
+            (Some('|'), Some('=')) => Some((AttributeOperator::DashMatch, 2)),
+            (Some('^'), Some('=')) => Some((AttributeOperator::Prefix, 2)),
+            (Some('$'), Some('=')) => Some((AttributeOperator::Suffix, 2)),
+            (Some('*'), Some('=')) => Some((AttributeOperator::Substring, 2)),
+            (Some(']'), _) => None,
+            _ => return Err(self.error(SelectorErrorKind::UnexpectedCharacter)),
+        };
+        let mut selector = AttributeSelector {
+            name,
+            operator: None,
+            value: String::new(),
+            case_insensitive: false,
+        };
+        if let Some((operator, length)) = operator {
+            self.position += length;
+            self.skip_whitespace();
+            selector.operator = Some(operator);
+            selector.value = match self.peek() {
+                Some(quote @ ('"' | '\'')) => self.string(quote)?,
+                _ => self.identifier()?,
+            };
+            self.skip_whitespace();
+            if self.starts_identifier() {
+                let flag = self.identifier()?;
+                if flag.eq_ignore_ascii_case("i") {
+                    selector.case_insensitive = true;
+                } else if !flag.eq_ignore_ascii_case("s") {
+                    return Err(SelectorError::new(SelectorErrorKind::UnexpectedCharacter, self.position - flag.len()));
+                }
+                self.skip_whitespace();
+            }
+        }
+        self.expect(']')?;
+        Ok(selector)
+    }
+
+    fn pseudo_class(&mut self) -> Result<Simple, SelectorError> {
+        self.expect(':')?;
+        if self.peek() == Some(':') {
+            return Err(self.error(SelectorErrorKind::UnsupportedPseudoElement));
+        }
+        let start = self.position;
+        let name = self.identifier()?.to_ascii_lowercase();
+        if !self.eat('(') {
+            return Ok(match name.as_str() {
+                "first-child" => Simple::FirstChild,
+                "last-child" => Simple::LastChild,
+                "only-child" => Simple::OnlyChild,
+                "first-of-type" => Simple::FirstOfType,
+                "last-of-type" => Simple::LastOfType,
+                "only-of-type" => Simple::OnlyOfType,
+                "empty" => Simple::Empty,
+                "root" => Simple::Root,
+                // The old pseudo-element syntax with a single colon
+                "before" | "after" | "first-line" | "first-letter" => {
+                    return Err(SelectorError::new(SelectorErrorKind::UnsupportedPseudoElement, start))
+                }
+                _ => return Err(SelectorError::new(SelectorErrorKind::UnknownPseudoClass, start)),
+            });
+        }
+        self.skip_whitespace();
+        let simple = match name.as_str() {
+            "nth-child" => Simple::NthChild(self.nth()?),
+            "nth-last-child" => Simple::NthLastChild(self.nth()?),
+            "nth-of-type" => Simple::NthOfType(self.nth()?),
+            "nth-last-of-type" => Simple::NthLastOfType(self.nth()?),
+            "not" => Simple::Not(self.selector_list()?),
+            "is" | "where" => Simple::Is(self.selector_list()?),
+            "has" => Simple::Has(self.relative_selector_list()?),
+            _ => return Err(SelectorError::new(SelectorErrorKind::UnknownPseudoClass, start)),
+        };
+        self.skip_whitespace();
+        self.expect(')')?;
+        Ok(simple)
+    }
+
+    /// Parses `An+B`, `odd` or `even`, up to the closing parenthesis.
+    fn nth(&mut self) -> Result<Nth, SelectorError> {
+        let start = self.position;
+        let end = self.input[start..].find(')').map_or(self.input.len(), |offset| start + offset);
+        let text: String = self.input[start..end].chars().filter(|c| !c.is_whitespace()).collect::<String>()
+                                                 .to_ascii_lowercase();
+        let invalid = || SelectorError::new(SelectorErrorKind::InvalidNth, start);
+        let number = |digits: &str| -> Result<i32, SelectorError> {
+            if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse().map_err(|_| invalid())
+        };
+        let nth = match text.as_str() {
+            "odd" => Nth { a: 2, b: 1 },
+            "even" => Nth { a: 2, b: 0 },
+            _ => match text.find('n') {
+                Some(n) => {
+                    let a = match &text[..n] {
+                        "" | "+" => 1,
+                        "-" => -1,
+                        a => {
+                            let (sign, digits) = a.strip_prefix('-').map_or((1, a.trim_start_matches('+')), |digits| (-1, digits));
+                            sign * number(digits)?
+                        }
+                    };
+                    let b = match &text[n + 1..] {
+                        "" => 0,
+                        b if b.starts_with('+') => number(&b[1..])?,
+                        b if b.starts_with('-') => -number(&b[1..])?,
+                        _ => return Err(invalid()),
+                    };
+                    Nth { a, b }
+                }
+                None => {
+                    let (sign, digits) = text.strip_prefix('-').map_or((1, text.trim_start_matches('+')), |digits| (-1, digits));
+                    Nth { a: 0, b: sign * number(digits)? }
+                }
+            },
+        };
+        self.position = end;
+        Ok(nth)
+    }
+
+    fn starts_identifier(&self) -> bool {
+        let is_start = |c: char| c.is_ascii_alphabetic() || c == '_' || c == '\\' || !c.is_ascii();
+        match (self.peek(), self.peek_at(1)) {
+            (Some('-'), Some(next)) => is_start(next) || next == '-',
+            (Some(c), _) => is_start(c),
+            _ => false,
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, SelectorError> {
+        if !self.starts_identifier() {
+            return Err(self.error(SelectorErrorKind::UnexpectedCharacter));
+        }
+        let mut identifier = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.position += 1;
+                identifier.push(self.escape()?);
+            } else if c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii() {
+                self.position += c.len_utf8();
+                identifier.push(c);
+            } else {
+                break;
+            }
+        }
+        Ok(identifier)
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, SelectorError> {
+        self.expect(quote)?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error(SelectorErrorKind::UnexpectedEnd)),
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') => match self.peek() {
+                    // An escaped newline continues the string on the next line
+                    Some('\n') => self.position += 1,
+                    _ => string.push(self.escape()?),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// The character after a backslash: up to six hex digits (and a space after them), or
+    /// the character itself.
+    fn escape(&mut self) -> Result<char, SelectorError> {
+        let start = self.position;
+        let digits = self.input[start..].bytes().take(6).take_while(u8::is_ascii_hexdigit).count();
+        if digits == 0 {
+            return self.next().ok_or_else(|| self.error(SelectorErrorKind::UnexpectedEnd));
+        }
+        let code = u32::from_str_radix(&self.input[start..start + digits], 16).unwrap();
+        self.position += digits;
+        self.skip_single_whitespace();
+        Ok(match char::from_u32(code) {
+            Some(c) if c != '\0' => c,
+            _ => char::REPLACEMENT_CHARACTER,
+        })
+    }
+
+    fn skip_single_whitespace(&mut self) {
+        if self.peek().is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\x0C')) {
+            self.position += 1;
+        } else if self.input[self.position..].starts_with("\r\n") {
+            self.position += 2;
+        }
+    }
+}
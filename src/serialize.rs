@@ -0,0 +1,207 @@
+// Turning a tree, or a stream of tokens, back into HTML with the spec's "serializing HTML
+// fragments" algorithm: names as the tree has them, no end tags for void elements, text escaped
+// except in the elements whose text is raw, and template contents in place of a template's
+// children.
+//
+// Doctypes only keep their name, as the spec has it, so a document in quirks mode because of a
+// legacy doctype's identifiers comes back out as one that isn't.
+
+use std::borrow::Cow;
+
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::foreign::Namespace;
+use crate::tokens::{Flags, QualifiedName, TagID, Token};
+use crate::tree_builder::is_void;
+
+/// The whole document as HTML.
+pub fn serialize(document: &Document) -> String {
+    inner_html(document, document.root())
+}
+
+/// The HTML of everything under `node`: its children, or its contents for a template.
+pub fn inner_html(document: &Document, node: NodeId) -> String {
+    let mut out = String::new();
+    push_children(document, node, &mut out);
+    out
+}
+
+/// The HTML of `node` itself and everything under it.
+pub fn outer_html(document: &Document, node: NodeId) -> String {
+    match document[node].data {
+        NodeData::Document | NodeData::TemplateContents(_) => inner_html(document, node),
+        _ => {
+            let mut out = String::new();
+            serialize_node(document, node, &mut out);
+            out
+        }
+    }
+}
+
+/// Serializes tokens from a `Tokenizer` the same way, without building a tree. Tags are written
+/// as they come, so nothing is added or moved the way tree construction would; text is raw
+/// after the start tag of an element whose text is raw, up to its end tag.
+pub fn serialize_tokens(raw_html: &str, tokens: &[Token]) -> String {
+    let mut out = String::new();
+    let mut raw_text = None;
+    for token in tokens {
+        let is_end_tag = token.flags & Flags::Close as u8 != 0;
+        match token.token_id {
+            TagID::Text => {
+                let text = token.text(raw_html);
+                if raw_text.is_some() {
+                    out.push_str(&text);
+                } else {
+                    escape_text(&text, &mut out);
+                }
+            }
+            TagID::EmComment => push_comment(&token.text(raw_html), &mut out),
+            TagID::EmDoctype => push_doctype(token, raw_html, &mut out),
+            TagID::EndOfFile | TagID::EmTrivia | TagID::Document => {}
+            _ if is_end_tag => {
+                if raw_text == Some(token.token_id) {
+                    raw_text = None;
+                }
+                // Void elements have no end tag. Tree construction turns a </br> into a <br>
+                if token.namespace() == Namespace::Html && is_void(token.token_id) {
+                    if token.token_id == TagID::Br {
+                        out.push_str("<br>");
+                    }
+                    continue;
+                }
+                out.push_str("</");
+                out.push_str(&token.name(raw_html));
+                out.push('>');
+            }
+            _ => {
+                let attributes = token.attributes.iter().flat_map(|attributes| attributes.iter())
+                                                  .map(|attribute| (attribute.qualified_name(token, raw_html),
+                                                                    attribute.value(token, raw_html)));
+                // Foreign elements keep their "/>", which is what ends them if they're parsed again
+                let self_closing = token.namespace() != Namespace::Html && token.flags & Flags::CloseSelf as u8 != 0;
+                push_start_tag(&token.name(raw_html), attributes, self_closing, &mut out);
+                if token.namespace() == Namespace::Html && has_raw_text(token.token_id) {
+                    raw_text = Some(token.token_id);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn serialize_node(document: &Document, node: NodeId, out: &mut String) {
+    let raw_html = document.raw_html();
+    match &document[node].data {
+        NodeData::Element(element) => {
+            let name = element.name(raw_html);
+            let attributes = element.attributes().map(|attribute| {
+                (attribute.qualified_name(raw_html), attribute.value(raw_html))
+            });
+            push_start_tag(&name, attributes, false, out);
+            if serializes_as_void(element) {
+                return;
+            }
+            // The parser drops a newline right after these start tags, so a text that starts with
+            // one needs another in front to survive being parsed again. The spec used to have
+            // this step, and without it parsing what was serialized isn't stable
+            if element.namespace == Namespace::Html && matches!(element.tag, TagID::Pre | TagID::TextArea | TagID::Listing) {
+                let first = document.children(node).next();
+                if first.is_some_and(|first| matches!(document[first].data, NodeData::Text(_)) &&
+                                             document.text(first).starts_with('\n')) {
+                    out.push('\n');
+                }
+            }
+            push_children(document, node, out);
+            out.push_str("</");
+            out.push_str(&name);
+            out.push('>');
+        }
+        NodeData::Text(_) => {
+            let text = document.text(node);
+            let raw = document.parent(node).and_then(|parent| document.element(parent)).is_some_and(|parent| {
+                parent.namespace == Namespace::Html && has_raw_text(parent.tag)
+            });
+            if raw {
+                out.push_str(&text);
+            } else {
+                escape_text(&text, out);
+            }
+        }
+        NodeData::Comment(token) => push_comment(&token.text(raw_html), out),
+        NodeData::Doctype(token) => push_doctype(token, raw_html, out),
+        NodeData::Document | NodeData::TemplateContents(_) => push_children(document, node, out),
+    }
+}
+
+fn push_children(document: &Document, node: NodeId, out: &mut String) {
+    if document.element(node).is_some_and(serializes_as_void) {
+        return;
+    }
+    let parent = document.template_contents(node).unwrap_or(node);
+    for child in document.children(parent) {
+        serialize_node(document, child, out);
+    }
+}
+
+fn serializes_as_void(element: &Element) -> bool {
+    element.namespace == Namespace::Html && is_void(element.tag)
+}
+
+/// Elements whose text is written as it is. <noscript> would be one too with scripting on,
+/// but the tree builder parses with scripting off.
+pub(crate) fn has_raw_text(tag: TagID) -> bool {
+    matches!(tag, TagID::Style | TagID::Script | TagID::Xmp | TagID::IFrame | TagID::NoEmbed | TagID::NoFrames |
+                  TagID::PlainText)
+}
+
+fn push_start_tag<'a>(name: &str, attributes: impl Iterator<Item = (QualifiedName<'a>, Cow<'a, str>)>,
+                      self_closing: bool, out: &mut String) {
+    out.push('<');
+    out.push_str(name);
+    for (name, value) in attributes {
+        out.push(' ');
+        if let Some(prefix) = name.prefix {
+            out.push_str(prefix);
+            out.push(':');
+        }
+        out.push_str(&name.local);
+        out.push_str("=\"");
+        escape_attribute(&value, out);
+        out.push('"');
+    }
+    out.push_str(if self_closing { "/>" } else { ">" });
+}
+
+pub(crate) fn push_comment(text: &str, out: &mut String) {
+    out.push_str("<!--");
+    out.push_str(text);
+    out.push_str("-->");
+}
+
+fn push_doctype(token: &Token, raw_html: &str, out: &mut String) {
+    out.push_str("<!DOCTYPE ");
+    out.push_str(&token.text(raw_html).to_ascii_lowercase());
+    out.push('>');
+}
+
+pub(crate) fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+pub(crate) fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
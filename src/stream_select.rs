@@ -0,0 +1,334 @@
+// Matching selectors against the tokenizer's tokens as they come, without building a tree, for
+// when only a few elements of a big page are wanted. All that's kept is a stack of the open
+// elements' start tags, so only selectors that look at an element and its ancestors work: type,
+// class, id and attribute selectors, joined by descendant and child combinators.
+//
+// The stack follows the markup rather than the tree construction rules. End tags close the
+// nearest open element with their name, and only the most common implied end tags (<p>, <li>,
+// table cells and rows, <option>...) are handled, so misnested markup can end elements in other
+// places than a browser would.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::foreign::Namespace;
+use crate::selectors::{Combinator, Compound, MatchResult, Selector, SelectorError, SelectorList, Simple};
+use crate::tokenizer::{InitialState, Tokenizer};
+use crate::tokens::{Flags, TagID, Token};
+use crate::tree_builder::is_void;
+
+/// A selector list that only uses what can be matched against a token stream (see
+/// `SelectorErrorKind::NotStreamable`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSelector {
+    list: SelectorList,
+}
+
+/// An element matched in a stream.
+#[derive(Clone, Debug)]
+pub struct StreamMatch {
+    pub start_tag: Token,
+    pub span:      Range<usize>, // From the '<' of the start tag to the '>' of the end tag, if it has one
+    pub text:      String,       // The text inside the element, references decoded
+}
+
+impl StreamMatch {
+    pub fn name<'a>(&self, raw_html: &'a str) -> Cow<'a, str> {
+        self.start_tag.name(raw_html)
+    }
+
+    /// The value of the attribute `name` on the start tag. `name` is expected in lowercase.
+    pub fn attribute<'a>(&self, raw_html: &'a str, name: &str) -> Option<Cow<'a, str>> {
+        attribute(&self.start_tag, raw_html, name)
+    }
+}
+
+impl StreamSelector {
+    pub fn parse(selectors: &str) -> Result<Self, SelectorError> {
+        Ok(StreamSelector { list: SelectorList::parse_streaming(selectors)? })
+    }
+
+    /// The elements of `raw_html` that match, in the order they end: an element comes after
+    /// the matches inside it.
+    pub fn matches<'s, 'a>(&'s self, raw_html: &'a str) -> StreamMatches<'s, 'a> {
+        StreamMatches {
+            selector:  self,
+            raw_html,
+            tokenizer: Tokenizer::new(raw_html),
+            elements:  OpenElements::default(),
+            ready:     VecDeque::new(),
+        }
+    }
+
+    /// Whether a start tag whose open ancestors are `ancestors` (outermost first) matches.
+    pub(crate) fn matches_start_tag(&self, raw_html: &str, token: &Token, ancestors: &[OpenElement]) -> bool {
+        self.list.selectors.iter().any(|selector| {
+            selector_matches(raw_html, selector, selector.compounds.len() - 1, token, ancestors)
+        })
+    }
+}
+
+/// An iterator over the matches of a `StreamSelector`, which tokenizes the input as it goes.
+pub struct StreamMatches<'s, 'a> {
+    selector:  &'s StreamSelector,
+    raw_html:  &'a str,
+    tokenizer: Tokenizer<'a>,
+    elements:  OpenElements,
+    ready:     VecDeque<StreamMatch>,
+}
+
+impl Iterator for StreamMatches<'_, '_> {
+    type Item = StreamMatch;
+
+    fn next(&mut self) -> Option<StreamMatch> {
+        loop {
+            if let Some(found) = self.ready.pop_front() {
+                return Some(found);
+            }
+            let token = self.tokenizer.next_token()?;
+            self.process(token);
+        }
+    }
+}
+
+impl StreamMatches<'_, '_> {
+    fn process(&mut self, token: Token) {
+        let raw_html = self.raw_html;
+        match token.token_id {
+            TagID::Text => {
+                let text = token.text(raw_html);
+                for element in self.elements.stack.iter_mut().filter(|element| element.matched) {
+                    element.text.push_str(&text);
+                }
+            }
+            TagID::EndOfFile => {
+                let closed = self.elements.close_all();
+                self.finish(closed, raw_html.len(), raw_html.len());
+            }
+            TagID::EmComment | TagID::EmDoctype | TagID::EmTrivia => {}
+            _ if token.flags & Flags::Close as u8 != 0 => {
+                let start = token.start as usize - 2;
+                if let Some(closed) = self.elements.close(raw_html, &token) {
+                    self.finish(closed, start, tag_end(raw_html, &token));
+                }
+            }
+            _ => {
+                let start = token.start as usize - 1;
+                let closed = self.elements.close_implied(&token);
+                self.finish(closed, start, start);
+
+                let matched = self.selector.matches_start_tag(raw_html, &token, &self.elements.stack);
+                let end = tag_end(raw_html, &token);
+                if self.elements.open(token.clone(), start, matched) {
+                    if let Some(state) = text_state(&token) {
+                        self.tokenizer.set_state(state);
+                    }
+                } else if matched {
+                    self.ready.push_back(StreamMatch { start_tag: token, span: start..end, text: String::new() });
+                }
+            }
+        }
+    }
+
+    /// Reports the closed elements that matched. The innermost one comes first and ends at
+    /// `inner_end`, the outermost ends at `outer_end`.
+    fn finish(&mut self, closed: Vec<OpenElement>, inner_end: usize, outer_end: usize) {
+        let count = closed.len();
+        for (index, element) in closed.into_iter().enumerate() {
+            if element.matched {
+                let end = if index + 1 == count { outer_end } else { inner_end };
+                self.ready.push_back(StreamMatch { start_tag: element.token, span: element.start..end, text: element.text });
+            }
+        }
+    }
+}
+
+/// An element whose start tag has been seen, but not its end.
+#[derive(Clone, Debug)]
+pub(crate) struct OpenElement {
+    pub(crate) token:   Token,
+    pub(crate) start:   usize, // The '<' of the start tag
+    pub(crate) matched: bool,
+    pub(crate) text:    String, // Only collected for matched elements
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OpenElements {
+    pub(crate) stack: Vec<OpenElement>,
+}
+
+impl OpenElements {
+    /// Opens the element of a start tag, unless it's void or self-closing, and says whether it
+    /// did.
+    pub(crate) fn open(&mut self, token: Token, start: usize, matched: bool) -> bool {
+        let self_closing = token.flags & Flags::CloseSelf as u8 != 0;
+        let empty = match token.namespace() {
+            Namespace::Html => is_void(token.token_id),
+            _ => self_closing,
+        };
+        if !empty {
+            self.stack.push(OpenElement { token, start, matched, text: String::new() });
+        }
+        !empty
+    }
+
+    /// Closes the elements that a start tag implies the end of, innermost first.
+    pub(crate) fn close_implied(&mut self, token: &Token) -> Vec<OpenElement> {
+        let mut closed = Vec::new();
+        if token.namespace() != Namespace::Html {
+            return closed;
+        }
+        while let Some(current) = self.stack.last() {
+            if current.token.namespace() != Namespace::Html || !implies_end(current.token.token_id, token.token_id) {
+                break;
+            }
+            closed.extend(self.stack.pop());
+        }
+        closed
+    }
+
+    /// Closes the nearest open element an end tag is for, and everything opened after it,
+    /// innermost first. End tags nothing is open for are ignored.
+    pub(crate) fn close(&mut self, raw_html: &str, token: &Token) -> Option<Vec<OpenElement>> {
+        let name = token.name(raw_html);
+        let index = self.stack.iter().rposition(|element| {
+            element.token.token_id == token.token_id &&
+            (token.token_id != TagID::Undef || element.token.name(raw_html) == name)
+        })?;
+        let mut closed = self.stack.split_off(index);
+        closed.reverse();
+        Some(closed)
+    }
+
+    pub(crate) fn close_all(&mut self) -> Vec<OpenElement> {
+        let mut closed = std::mem::take(&mut self.stack);
+        closed.reverse();
+        closed
+    }
+}
+
+/// Whether an open `current` element ends when a start tag for `incoming` comes.
+fn implies_end(current: TagID, incoming: TagID) -> bool {
+    match current {
+        TagID::Paragraph => matches!(incoming,
+            TagID::Address | TagID::Article | TagID::Aside | TagID::Blockquote | TagID::Center | TagID::DD |
+            TagID::Details | TagID::Dialog | TagID::Dir | TagID::Div | TagID::Dl | TagID::Dt | TagID::FieldSet |
+            TagID::FigCaption | TagID::Figure | TagID::Footer | TagID::Form | TagID::H1 | TagID::H2 |
+            TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6 | TagID::Header | TagID::Hgroup | TagID::Hr |
+            TagID::Li | TagID::Listing | TagID::Main | TagID::Menu | TagID::Nav | TagID::Ol | TagID::Paragraph |
+            TagID::PlainText | TagID::Pre | TagID::Section | TagID::Summary | TagID::Table | TagID::Ul |
+            TagID::Xmp),
+        TagID::Li => incoming == TagID::Li,
+        TagID::DD | TagID::Dt => matches!(incoming, TagID::DD | TagID::Dt),
+        TagID::Option => matches!(incoming, TagID::Option | TagID::OptGroup),
+        TagID::OptGroup => incoming == TagID::OptGroup,
+        TagID::Td | TagID::Th => {
+            matches!(incoming, TagID::Td | TagID::Th | TagID::Tr | TagID::Tbody | TagID::Thead | TagID::TFoot)
+        }
+        TagID::Tr => matches!(incoming, TagID::Tr | TagID::Tbody | TagID::Thead | TagID::TFoot),
+        TagID::Tbody | TagID::Thead | TagID::TFoot => matches!(incoming, TagID::Tbody | TagID::Thead | TagID::TFoot),
+        _ => false,
+    }
+}
+
+/// The tokenizer state the content of an element starts in, for the elements whose content
+/// isn't markup.
+pub(crate) fn text_state(token: &Token) -> Option<InitialState> {
+    if token.namespace() != Namespace::Html {
+        return None;
+    }
+    match token.token_id {
+        TagID::Title | TagID::TextArea => Some(InitialState::Rcdata),
+        TagID::Style | TagID::Xmp | TagID::IFrame | TagID::NoEmbed | TagID::NoFrames => Some(InitialState::Rawtext),
+        TagID::Script => Some(InitialState::ScriptData),
+        TagID::PlainText => Some(InitialState::Plaintext),
+        _ => None,
+    }
+}
+
+/// The offset just past the '>' that ends a tag, or the end of the input. Tags only keep the
+/// span of their name, so this skips over the attributes, minding quoted values.
+pub(crate) fn tag_end(raw_html: &str, token: &Token) -> usize {
+    let bytes = raw_html.as_bytes();
+    let mut position = token.end as usize;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'>' => return position + 1,
+            b'=' => {
+                position += 1;
+                while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+                    position += 1;
+                }
+                if let Some(&quote @ (b'"' | b'\'')) = bytes.get(position) {
+                    position += 1;
+                    position = bytes[position..].iter().position(|&byte| byte == quote)
+                                                .map_or(bytes.len(), |offset| position + offset + 1);
+                }
+            }
+            _ => position += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn attribute<'a>(token: &Token, raw_html: &'a str, name: &str) -> Option<Cow<'a, str>> {
+    token.attributes.as_deref()?.iter()
+         .find(|attribute| attribute.name(token, raw_html).eq_ignore_ascii_case(name))
+         .map(|attribute| attribute.value(token, raw_html))
+}
+
+fn selector_matches(raw_html: &str, selector: &Selector, index: usize, token: &Token,
+                    ancestors: &[OpenElement]) -> bool {
+    match_from(raw_html, selector, index, token, ancestors) == MatchResult::Matched
+}
+
+/// Matches right to left like the tree matcher, giving up on the ancestors that are left as
+/// soon as a failure means none of them can match either.
+fn match_from(raw_html: &str, selector: &Selector, index: usize, token: &Token,
+              ancestors: &[OpenElement]) -> MatchResult {
+    if !compound_matches(raw_html, &selector.compounds[index], token) {
+        return MatchResult::NotMatchedRestartFromClosestLaterSibling;
+    }
+    if index == 0 {
+        return MatchResult::Matched;
+    }
+    match selector.combinators[index - 1] {
+        Combinator::Child => match ancestors.split_last() {
+            Some((parent, rest)) => match match_from(raw_html, selector, index - 1, &parent.token, rest) {
+                result @ (MatchResult::Matched | MatchResult::NotMatchedGlobally) => result,
+                _ => MatchResult::NotMatchedRestartFromClosestDescendant,
+            },
+            None => MatchResult::NotMatchedGlobally,
+        },
+        Combinator::Descendant => {
+            for parent in (0..ancestors.len()).rev() {
+                let result = match_from(raw_html, selector, index - 1, &ancestors[parent].token, &ancestors[..parent]);
+                if matches!(result, MatchResult::Matched | MatchResult::NotMatchedGlobally) {
+                    return result;
+                }
+            }
+            MatchResult::NotMatchedGlobally
+        }
+        // Sibling combinators are rejected when parsing
+        Combinator::NextSibling | Combinator::SubsequentSibling => MatchResult::NotMatchedGlobally,
+    }
+}
+
+fn compound_matches(raw_html: &str, compound: &Compound, token: &Token) -> bool {
+    compound.iter().all(|simple| match simple {
+        Simple::Type(tag, name) => {
+            token.token_id == *tag && (*tag != TagID::Undef || token.name(raw_html).eq_ignore_ascii_case(name))
+        }
+        Simple::Id(id) => attribute(token, raw_html, "id").is_some_and(|value| value == *id),
+        Simple::Class(class) => attribute(token, raw_html, "class").is_some_and(|value| {
+            value.split_ascii_whitespace().any(|word| word == class)
+        }),
+        Simple::Attribute(selector) => token.attributes.as_deref().unwrap_or(&[]).iter().any(|attribute| {
+            attribute.name(token, raw_html).eq_ignore_ascii_case(&selector.name) &&
+            selector.value_matches(&attribute.value(token, raw_html))
+        }),
+        // Everything else is rejected when parsing
+        _ => false,
+    })
+}
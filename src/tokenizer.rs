@@ -1,102 +1,336 @@
 // \r\n -> \n, \r -> \n, in other words replace every new line and \r with \n
 // But we shall skip this. \r = new line
 
+use crate::entities;
+use crate::errors;
+use crate::foreign;
+use crate::scan;
 use crate::tokens;
+use crate::trace;
 use tokens::ASCII_TO_TAG_ID;
 
-const EOF: u8 = 0x05;
 const FF: u8 = 0x0C; // FF - form feed character (normally '\f')
 
-pub struct Tokenizer<'a> {
+pub struct Tokenizer<'a, O: trace::Observer = trace::NoTrace> {
     raw_html:         &'a [u8],
     state:            State,
     return_state:     State,
     position:         usize,
-    token_start:      usize,
+    token_start:      usize, // Position of the '<' that opened the markup being tokenized
+    text_start:       Option<usize>,
+    text_flags:       u8,
+    finished:         bool,
+    foreign_content:  bool,
+    lossless:         bool,
+    track_namespaces: bool,
+    namespaces:       foreign::NamespaceTracker,
+    covered:          usize, // End of the input covered by tokens so far, in lossless mode
     pub tokens:           Vec<tokens::Token>,
+    pub errors:           Vec<errors::ParseError>,
 
     temp_buffer:      Vec<u8>,
+    last_start_tag:   Vec<u8>,
+    doctype_keyword:  usize,
+    drop_attribute:   bool,
+    char_ref_code:    u32,
     cur_attributes:   Vec<tokens::Attribute>,
     cur_start:        u32,
     cur_end:          u32,
-    cur_text_off:     u16,
+    cur_text_off:     u32,
     cur_text_size:    u32,
     cur_flags:        u8,
     cur_token_id:     tokens::TagID,
+
+    observer:         O,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(raw_html: &'a str) -> Self {
+        Tokenizer::with_observer(raw_html, trace::NoTrace)
+    }
+}
+
+impl<'a, O: trace::Observer> Tokenizer<'a, O> {
+    /// Creates a tokenizer that reports state transitions, tokens and parse errors to
+    /// `observer` as it goes.
+    pub fn with_observer(raw_html: &'a str, observer: O) -> Self {
         Tokenizer {
             raw_html: raw_html.as_bytes(),
             state: State::Data,
             return_state: State::Data,
             position: 0usize,
             token_start: 0usize,
+            text_start: None,
+            text_flags: 0u8,
+            finished: false,
+            foreign_content: false,
+            lossless: false,
+            track_namespaces: true,
+            namespaces: foreign::NamespaceTracker::new(),
+            covered: 0usize,
             tokens: Vec::new(),
+            errors: Vec::new(),
             temp_buffer: Vec::with_capacity(10),
+            last_start_tag: Vec::with_capacity(10),
+            doctype_keyword: 0usize,
+            drop_attribute: false,
+            char_ref_code: 0u32,
             cur_attributes: Vec::new(),
             cur_start: 0u32,
             cur_end: 0u32,
-            cur_text_off: 0u16,
+            cur_text_off: 0u32,
             cur_text_size: 0u32,
             cur_flags: 0u8,
-            cur_token_id: tokens::TagID::Data,
+            cur_token_id: tokens::TagID::Text,
+            observer,
         }
     }
 
     fn find_tag_id(&self, start: usize, end: usize) -> tokens::TagID {
         let slice = unsafe { std::slice::from_raw_parts(self.raw_html.as_ptr().add(start), end - start) };
-        match ASCII_TO_TAG_ID.get(slice) {
+        if let Some(&tag_id) = ASCII_TO_TAG_ID.get(slice) {
+            return tag_id;
+        }
+        // Tag names are case-insensitive, but the map only has the lowercase ones. Anything
+        // longer than the longest known name can't be in it.
+        let mut lowercase = [0u8; 24];
+        if slice.len() > lowercase.len() || !slice.iter().any(u8::is_ascii_uppercase) {
+            return tokens::TagID::Undef;
+        }
+        for (byte, lower) in slice.iter().zip(lowercase.iter_mut()) {
+            *lower = byte.to_ascii_lowercase();
+        }
+        match ASCII_TO_TAG_ID.get(&lowercase[..slice.len()]) {
             Some(&tag_id) => tag_id,
-            None => tokens::TagID::A, // FIXME: change this, we need to insert into a dynamic hash
-                                      // table since we can have user-defined tags
+            None => tokens::TagID::Undef,
+        }
+    }
+
+    /// Works out the namespace of the tag about to be emitted, and whether what follows it is
+    /// foreign content.
+    fn track_namespace(&mut self) {
+        let name = (self.cur_start, self.cur_end);
+        let namespace = if self.cur_flags & tokens::Flags::Close as u8 != 0 {
+            let raw_html = self.raw_html;
+            let tag_name = &raw_html[self.cur_start as usize..self.cur_end as usize];
+            self.namespaces.end_tag(self.cur_token_id, |(start, end)| {
+                raw_html[start as usize..end as usize].eq_ignore_ascii_case(tag_name)
+            })
+        } else {
+            let span = |begin: u32, size: u32| {
+                let begin = self.cur_start as usize + begin as usize;
+                &self.raw_html[begin..begin + size as usize]
+            };
+            let attribute = |name: &[u8]| self.cur_attributes.iter().find(|attribute| {
+                span(attribute.name_begin, attribute.name_size).eq_ignore_ascii_case(name)
+            });
+            let breakout = self.cur_token_id == tokens::TagID::Font &&
+                           [&b"color"[..], b"face", b"size"].iter().any(|name| attribute(name).is_some());
+            let html_annotation = self.cur_token_id == tokens::TagID::AnnotationXml &&
+                                  attribute(b"encoding").is_some_and(|encoding| {
+                                      let value = span(encoding.value_begin, encoding.value_size);
+                                      encoding.quoting != tokens::Quoting::Missing &&
+                                      (value.eq_ignore_ascii_case(b"text/html") ||
+                                       value.eq_ignore_ascii_case(b"application/xhtml+xml"))
+                                  });
+            let self_closing = self.cur_flags & tokens::Flags::CloseSelf as u8 != 0;
+            self.namespaces.start_tag(self.cur_token_id, name, self_closing, breakout, html_annotation)
+        };
+        match namespace {
+            foreign::Namespace::Svg => self.cur_flags |= tokens::Flags::Svg as u8,
+            foreign::Namespace::MathMl => self.cur_flags |= tokens::Flags::MathMl as u8,
+            _ => {}
+        }
+        self.foreign_content = self.namespaces.in_foreign_element();
+    }
+
+    fn error(&mut self, kind: errors::ErrorKind) {
+        let error = errors::ParseError::new(kind, self.position as u32);
+        self.observer.parse_error(&error);
+        self.errors.push(error);
+    }
+
+    fn push_token(&mut self, token: tokens::Token) {
+        if self.lossless {
+            let start = token.start as usize;
+            if start > self.covered {
+                let trivia = tokens::Token::new_no_attributes(self.covered as u32, token.start, 0, 0,
+                                                              tokens::TagID::EmTrivia, 0);
+                self.observer.token_emitted(&trivia, self.raw_html);
+                self.tokens.push(trivia);
+            }
+            self.covered = token.end as usize;
+        }
+        self.observer.token_emitted(&token, self.raw_html);
+        self.tokens.push(token);
+    }
+
+
+    fn starts_with_ignore_case(&self, pattern: &[u8]) -> bool {
+        match self.raw_html.get(self.position..self.position + pattern.len()) {
+            Some(slice) => slice.eq_ignore_ascii_case(pattern),
+            None => false,
         }
     }
 
     fn create_empty_attribute(&mut self) {
-        let attr_start = (self.position as u32 - self.cur_start) as u16;
+        self.drop_duplicate_attribute();
+        let attr_start = self.position as u32 - self.cur_start;
         self.cur_attributes.push(tokens::Attribute::new(attr_start, 0, 0, 0))
     }
-    
-    fn value_begin(position: usize, cur_start: u32) -> u16 {
-        return (position as u32 - cur_start) as u16
+
+    fn check_duplicate_attribute(&mut self) {
+        let (last, previous) = match self.cur_attributes.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        let name = |attribute: &tokens::Attribute| {
+            let begin = self.cur_start as usize + attribute.name_begin as usize;
+            &self.raw_html[begin..begin + attribute.name_size as usize]
+        };
+        let last_name = name(last);
+        if previous.iter().any(|attribute| name(attribute).eq_ignore_ascii_case(last_name)) {
+            self.drop_attribute = true;
+            self.error(errors::ErrorKind::DuplicateAttribute);
+        }
     }
 
-    fn emit_current_token_as_text(&mut self) {
-        if self.cur_end > self.cur_start {
-            self.tokens.push(tokens::Token::new_no_attributes(self.cur_start, 
-                                                              self.cur_end, 
-                                                              self.cur_text_off,
-                                                              self.cur_text_size, 
-                                                              self.cur_token_id,
-                                                              0));
-            self.clear_current_token();
+    fn drop_duplicate_attribute(&mut self) {
+        if self.drop_attribute {
+            self.cur_attributes.pop();
+            self.drop_attribute = false;
         }
     }
 
-    fn emit_current_token_no_text(&mut self, flags: u8) {
+    fn value_begin(position: usize, cur_start: u32) -> u32 {
+        position as u32 - cur_start
+    }
+
+    /// Marks the current position as part of a run of text, starting a new run if there is
+    /// none. Text is always a contiguous span of the input, so only its start has to be stored.
+    /// A run never goes from one text state to another, so the state it starts in is the one
+    /// whose rules its NULs follow.
+    fn start_text(&mut self, position: usize) {
+        if self.text_start.is_none() {
+            self.text_start = Some(position);
+            if !matches!(self.state, State::Data | State::TagOpen | State::EndTagOpen |
+                                     State::CdataSection | State::CdataSectionBracket | State::CdataSectionEnd) {
+                self.text_flags |= tokens::Flags::Nuls as u8;
+            }
+        }
+    }
+
+    /// Emits the pending run of text, which ends right before `end`.
+    fn flush_text(&mut self, end: usize) {
+        if let Some(start) = self.text_start.take() {
+            if end > start {
+                self.push_token(tokens::Token::new_no_attributes(start as u32,
+                                                                  end as u32,
+                                                                  0,
+                                                                  0,
+                                                                  tokens::TagID::Text,
+                                                                  self.text_flags));
+            }
+        }
+        self.text_flags = 0;
+    }
+
+    fn in_attribute_value(&self) -> bool {
+        matches!(self.return_state, State::AttributeValueDoubleQuoted |
+                                    State::AttributeValueSingleQuoted |
+                                    State::AttributeValueUnquoted)
+    }
+
+    /// Consumes `count` bytes of a character reference. Whatever a reference consumes is
+    /// flushed to where the '&' went, which for text is already the current run, but an
+    /// attribute value has to grow to cover it.
+    fn consume_character_reference(&mut self, count: usize) {
+        if self.in_attribute_value() {
+            self.cur_attributes.last_mut().unwrap().value_size += count as u32;
+        }
+        self.position += count;
+    }
+
+    /// Marks the text run or attribute value the current reference belongs to as having to be
+    /// decoded, since the span itself still holds the reference as written.
+    fn flush_decoded_character_reference(&mut self) {
+        if self.in_attribute_value() {
+            self.cur_attributes.last_mut().unwrap().flags |= tokens::Flags::CharRefs as u8;
+        } else {
+            self.text_flags |= tokens::Flags::CharRefs as u8;
+        }
+    }
+
+    fn create_tag_token(&mut self, flags: u8) {
+        self.flush_text(self.token_start);
+        self.clear_current_token();
+        self.cur_start = self.position as u32;
         self.cur_flags = flags;
+    }
+
+    fn create_token_at_position(&mut self) {
+        self.flush_text(self.token_start);
+        self.clear_current_token();
+        self.cur_start = self.position as u32;
+        self.cur_end = self.position as u32;
+    }
+
+    fn emit_current_token_no_text(&mut self, flags: u8) {
+        self.drop_duplicate_attribute();
+        self.cur_flags |= flags;
         self.cur_text_off = 0;
         self.cur_text_size = 0;
-        self.cur_token_id = self.find_tag_id(self.cur_start as usize, 
+        self.cur_token_id = self.find_tag_id(self.cur_start as usize,
                                              self.cur_end as usize);
 
+        let is_end_tag = self.cur_flags & tokens::Flags::Close as u8 != 0;
+        if is_end_tag {
+            if !self.cur_attributes.is_empty() {
+                self.error(errors::ErrorKind::EndTagWithAttributes);
+                self.cur_attributes.clear();
+            }
+            if self.cur_flags & tokens::Flags::CloseSelf as u8 != 0 {
+                self.error(errors::ErrorKind::EndTagWithTrailingSolidus);
+            }
+        } else {
+            self.last_start_tag.clear();
+            let name = &self.raw_html[self.cur_start as usize..self.cur_end as usize];
+            self.last_start_tag.extend(name.iter().map(u8::to_ascii_lowercase));
+        }
+        if self.track_namespaces {
+            self.track_namespace();
+        }
+
+        self.emit_current_token(self.cur_token_id);
+    }
+
+    fn emit_comment(&mut self) {
+        self.emit_current_token(tokens::TagID::EmComment);
+    }
+
+    fn emit_doctype(&mut self, force_quirks: bool) {
+        if force_quirks {
+            self.cur_flags |= tokens::Flags::ForceQuirks as u8;
+        }
+        self.emit_current_token(tokens::TagID::EmDoctype);
+    }
+
+    fn emit_current_token(&mut self, token_id: tokens::TagID) {
         if self.cur_attributes.is_empty() {
-            self.tokens.push(tokens::Token::new_no_attributes(self.cur_start, 
-                                                              self.cur_end, 
+            self.push_token(tokens::Token::new_no_attributes(self.cur_start,
+                                                              self.cur_end,
                                                               self.cur_text_off,
-                                                              self.cur_text_size, 
-                                                              self.cur_token_id,
+                                                              self.cur_text_size,
+                                                              token_id,
                                                               self.cur_flags));
         } else {
-            let attributes = std::mem::replace(&mut self.cur_attributes, Vec::new());
-            self.tokens.push(tokens::Token::new(attributes.into_boxed_slice(),
-                                                self.cur_start, 
-                                                self.cur_end, 
+            let attributes = std::mem::take(&mut self.cur_attributes);
+            self.push_token(tokens::Token::new(attributes.into_boxed_slice(),
+                                                self.cur_start,
+                                                self.cur_end,
                                                 self.cur_text_off,
-                                                self.cur_text_size, 
-                                                self.cur_token_id,
+                                                self.cur_text_size,
+                                                token_id,
                                                 self.cur_flags))
 
         }
@@ -104,27 +338,136 @@ impl<'a> Tokenizer<'a> {
         self.clear_current_token();
     }
 
+    fn emit_end_of_file(&mut self) {
+        self.flush_text(self.raw_html.len());
+        let end = self.raw_html.len() as u32;
+        self.push_token(tokens::Token::new_no_attributes(end, end, 0, 0, tokens::TagID::EndOfFile, 0));
+    }
+
     fn clear_current_token(&mut self) {
         self.cur_end = 0;
         self.cur_start = 0;
         self.cur_flags = 0;
         self.cur_text_off = 0;
         self.cur_text_size = 0;
+        self.drop_attribute = false;
         self.cur_attributes.clear();
     }
 
     pub fn tokenize(&mut self) {
-        while self.position < self.raw_html.len() {
+        while !self.finished {
+            self.step();
+        }
+    }
+
+    /// Runs the tokenizer until it has a token to hand back, and returns the oldest one that
+    /// hasn't been handed back yet, or `None` once the end-of-file token has been returned.
+    ///
+    /// Nothing past that token has been consumed yet, so a tree builder can switch the state
+    /// with `set_state` (e.g. to `Rcdata` after a `<title>`) before asking for the next one.
+    pub fn next_token(&mut self) -> Option<tokens::Token> {
+        while self.tokens.is_empty() && !self.finished {
+            self.step();
+        }
+        if self.tokens.is_empty() {
+            None
+        } else {
+            Some(self.tokens.remove(0))
+        }
+    }
+
+    /// Switches the tokenizer to another state, either before it starts (fragment parsing,
+    /// tests) or between two tokens.
+    pub fn set_state(&mut self, state: InitialState) {
+        let state = State::from(state);
+        if state != self.state {
+            self.observer.state_transition(self.state, state);
+        }
+        self.state = state;
+    }
+
+    /// Sets the tag name used to decide whether an end tag in RCDATA, RAWTEXT or script data is
+    /// "appropriate". It is otherwise the name of the last start tag that was emitted.
+    pub fn set_last_start_tag(&mut self, name: &str) {
+        self.last_start_tag.clear();
+        self.last_start_tag.extend(name.bytes().map(|byte| byte.to_ascii_lowercase()));
+    }
+
+    /// Tells the tokenizer whether the adjusted current node is an element in the SVG or MathML
+    /// namespace. Only then is `<![CDATA[...]]>` a CDATA section whose content is emitted as
+    /// text; in HTML content it is a bogus comment.
+    ///
+    /// Unless namespace tracking is turned off this is overwritten after every tag.
+    pub fn set_foreign_content(&mut self, in_foreign_content: bool) {
+        self.foreign_content = in_foreign_content;
+    }
+
+    /// Turns tracking of SVG and MathML on or off. While on (the default), tags inside `<svg>`
+    /// and `<math>` are flagged with their namespace (see `Token::namespace`), and CDATA
+    /// sections are recognized in foreign content. A tree builder that knows the real
+    /// namespaces turns it off and calls `set_foreign_content` itself.
+    pub fn set_track_namespaces(&mut self, track_namespaces: bool) {
+        self.track_namespaces = track_namespaces;
+    }
+
+    /// Makes the tokenizer lossless: every byte of the input that isn't part of a token's span
+    /// (the '<', '</' and '>' around tag names, attributes with their quotes and whitespace,
+    /// comment and doctype delimiters, and malformed markup that produces no token at all) is
+    /// emitted as an `EmTrivia` token. The spans of all tokens, in order, then add up to the
+    /// input byte for byte. Spans always hold the input as written, so case, quoting (see
+    /// `Attribute::quoting`) and references are kept either way.
+    ///
+    /// Has to be set before the first token is emitted.
+    pub fn set_lossless(&mut self, lossless: bool) {
+        self.lossless = lossless;
+    }
+
+    fn step(&mut self) {
+        if self.position < self.raw_html.len() {
+            let previous = self.state;
             self.induce_state();
-            println!("New state: {:?}", self.state);
+            if previous != self.state {
+                self.observer.state_transition(previous, self.state);
+            }
+        } else {
+            self.induce_eof();
+            self.finished = true;
         }
     }
 
     fn induce_state(&mut self) {
         match &self.state {
             State::Data => self.data_state(),
+            State::Rcdata => self.rcdata_state(),
+            State::Rawtext => self.rawtext_state(),
+            State::ScriptData => self.script_data_state(),
+            State::Plaintext => self.plaintext_state(),
             State::TagOpen => self.tag_open_state(),
+            State::EndTagOpen => self.end_tag_open_state(),
             State::TagName => self.tag_name_state(),
+            State::RcdataLessThanSign => self.text_less_than_sign_state(State::RcdataEndTagOpen, State::Rcdata),
+            State::RcdataEndTagOpen => self.text_end_tag_open_state(State::RcdataEndTagName, State::Rcdata),
+            State::RcdataEndTagName => self.text_end_tag_name_state(State::Rcdata),
+            State::RawtextLessThanSign => self.text_less_than_sign_state(State::RawtextEndTagOpen, State::Rawtext),
+            State::RawtextEndTagOpen => self.text_end_tag_open_state(State::RawtextEndTagName, State::Rawtext),
+            State::RawtextEndTagName => self.text_end_tag_name_state(State::Rawtext),
+            State::ScriptDataLessThanSign => self.script_data_less_than_sign_state(),
+            State::ScriptDataEndTagOpen => self.text_end_tag_open_state(State::ScriptDataEndTagName, State::ScriptData),
+            State::ScriptDataEndTagName => self.text_end_tag_name_state(State::ScriptData),
+            State::ScriptDataEscapeStart => self.script_data_escape_start_state(),
+            State::ScriptDataEscapeStartDash => self.script_data_escape_start_dash_state(),
+            State::ScriptDataEscaped => self.script_data_escaped_state(),
+            State::ScriptDataEscapedDash => self.script_data_escaped_dash_state(),
+            State::ScriptDataEscapedDashDash => self.script_data_escaped_dash_dash_state(),
+            State::ScriptDataEscapedLessThanSign => self.script_data_escaped_less_than_sign_state(),
+            State::ScriptDataEscapedEndTagOpen => self.text_end_tag_open_state(State::ScriptDataEscapedEndTagName, State::ScriptDataEscaped),
+            State::ScriptDataEscapedEndTagName => self.text_end_tag_name_state(State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart => self.script_data_double_escape_start_state(),
+            State::ScriptDataDoubleEscaped => self.script_data_double_escaped_state(),
+            State::ScriptDataDoubleEscapedDash => self.script_data_double_escaped_dash_state(),
+            State::ScriptDataDoubleEscapedDashDash => self.script_data_double_escaped_dash_dash_state(),
+            State::ScriptDataDoubleEscapedLessThanSign => self.script_data_double_escaped_less_than_sign_state(),
+            State::ScriptDataDoubleEscapeEnd => self.script_data_double_escape_end_state(),
             State::BeforeAttributeName => self.before_attribute_name_state(),
             State::AttributeName => self.attribute_name_state(),
             State::AfterAttributeName => self.after_attribute_name_state(),
@@ -132,73 +475,378 @@ impl<'a> Tokenizer<'a> {
             State::AttributeValueDoubleQuoted => self.attribute_value_double_quoted_state(),
             State::AttributeValueSingleQuoted => self.attribute_value_single_quoted_state(),
             State::AttributeValueUnquoted => self.attribute_value_unquoted_state(),
-            State::CharacterReference => self.character_reference_state(),
             State::AfterAttributeValueQuoted => self.after_attribute_value_quoted_state(),
             State::SelfClosingStartTag => self.self_closing_start_tag_state(),
-            _ => unimplemented!("Reached unimplemented state {:?}", self.state),
+            State::BogusComment => self.bogus_comment_state(),
+            State::MarkupDeclarationOpen => self.markup_declaration_open_state(),
+            State::CommentStart => self.comment_start_state(),
+            State::CommentStartDash => self.comment_start_dash_state(),
+            State::Comment => self.comment_state(),
+            State::CommentLessThanSign => self.comment_less_than_sign_state(),
+            State::CommentLessThanSignBang => self.comment_less_than_sign_bang_state(),
+            State::CommentLessThanSignBangDash => self.comment_less_than_sign_bang_dash_state(),
+            State::CommentLessThanSignBangDashDash => self.comment_less_than_sign_bang_dash_dash_state(),
+            State::CommentEndDash => self.comment_end_dash_state(),
+            State::CommentEnd => self.comment_end_state(),
+            State::CommentEndBang => self.comment_end_bang_state(),
+            State::Doctype => self.doctype_state(),
+            State::BeforeDoctypeName => self.before_doctype_name_state(),
+            State::DoctypeName => self.doctype_name_state(),
+            State::AfterDoctypeName => self.after_doctype_name_state(),
+            State::AfterDoctypePublicKeyword => self.after_doctype_keyword_state(State::BeforeDoctypePublicIdentifier,
+                                                                                 State::DoctypePublicIdentifierDoubleQuoted,
+                                                                                 State::DoctypePublicIdentifierSingleQuoted),
+            State::BeforeDoctypePublicIdentifier => self.before_doctype_identifier_state(State::DoctypePublicIdentifierDoubleQuoted,
+                                                                                         State::DoctypePublicIdentifierSingleQuoted),
+            State::DoctypePublicIdentifierDoubleQuoted => self.doctype_identifier_state(b'"', State::AfterDoctypePublicIdentifier),
+            State::DoctypePublicIdentifierSingleQuoted => self.doctype_identifier_state(b'\'', State::AfterDoctypePublicIdentifier),
+            State::AfterDoctypePublicIdentifier => self.after_doctype_public_identifier_state(),
+            State::BetweenDoctypePublicAndSystemIdentifiers => self.between_doctype_public_and_system_identifiers_state(),
+            State::AfterDoctypeSystemKeyword => self.after_doctype_keyword_state(State::BeforeDoctypeSystemIdentifier,
+                                                                                 State::DoctypeSystemIdentifierDoubleQuoted,
+                                                                                 State::DoctypeSystemIdentifierSingleQuoted),
+            State::BeforeDoctypeSystemIdentifier => self.before_doctype_identifier_state(State::DoctypeSystemIdentifierDoubleQuoted,
+                                                                                         State::DoctypeSystemIdentifierSingleQuoted),
+            State::DoctypeSystemIdentifierDoubleQuoted => self.doctype_identifier_state(b'"', State::AfterDoctypeSystemIdentifier),
+            State::DoctypeSystemIdentifierSingleQuoted => self.doctype_identifier_state(b'\'', State::AfterDoctypeSystemIdentifier),
+            State::AfterDoctypeSystemIdentifier => self.after_doctype_system_identifier_state(),
+            State::BogusDoctype => self.bogus_doctype_state(),
+            State::CdataSection => self.cdata_section_state(),
+            State::CdataSectionBracket => self.cdata_section_bracket_state(),
+            State::CdataSectionEnd => self.cdata_section_end_state(),
+            State::CharacterReference => self.character_reference_state(),
+            State::NamedCharacterReference => self.named_character_reference_state(),
+            State::AmbiguousAmpersand => self.ambiguous_ampersand_state(),
+            State::NumericCharacterReference => self.numeric_character_reference_state(),
+            State::HexadecimalCharacterReferenceStart => self.hexadecimal_character_reference_start_state(),
+            State::DecimalCharacterReferenceStart => self.decimal_character_reference_start_state(),
+            State::HexadecimalCharacterReference => self.numeric_character_reference_digits_state(16),
+            State::DecimalCharacterReference => self.numeric_character_reference_digits_state(10),
+            State::NumericCharacterReferenceEnd => self.numeric_character_reference_end_state(),
+        }
+    }
+
+    /// Runs the "EOF" branch of the current state once the whole input has been consumed.
+    /// Some states reconsume the end of file in another state, so keep going until one of
+    /// them emits the end-of-file token.
+    fn induce_eof(&mut self) {
+        loop {
+            let previous = self.state;
+            match self.state {
+                State::Data | State::Rcdata | State::Rawtext | State::ScriptData | State::Plaintext => {
+                    break
+                }
+                State::TagOpen => {
+                    self.error(errors::ErrorKind::EofBeforeTagName);
+                    self.start_text(self.token_start);
+                    break
+                }
+                State::EndTagOpen => {
+                    self.error(errors::ErrorKind::EofBeforeTagName);
+                    self.start_text(self.token_start);
+                    break
+                }
+                State::RcdataLessThanSign | State::RcdataEndTagOpen | State::RcdataEndTagName |
+                State::RawtextLessThanSign | State::RawtextEndTagOpen | State::RawtextEndTagName |
+                State::ScriptDataLessThanSign | State::ScriptDataEndTagOpen | State::ScriptDataEndTagName |
+                State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => {
+                    // Whatever was consumed since the '<' is emitted as text
+                    self.start_text(self.token_start);
+                    break
+                }
+                State::ScriptDataEscaped | State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash |
+                State::ScriptDataDoubleEscaped | State::ScriptDataDoubleEscapedDash |
+                State::ScriptDataDoubleEscapedDashDash => {
+                    self.error(errors::ErrorKind::EofInScriptHtmlCommentLikeText);
+                    break
+                }
+                State::ScriptDataEscapedLessThanSign | State::ScriptDataEscapedEndTagOpen |
+                State::ScriptDataEscapedEndTagName => {
+                    self.start_text(self.token_start);
+                    self.state = State::ScriptDataEscaped;
+                }
+                State::ScriptDataDoubleEscapeStart => {
+                    self.state = State::ScriptDataEscaped;
+                }
+                State::ScriptDataDoubleEscapedLessThanSign | State::ScriptDataDoubleEscapeEnd => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                }
+                State::TagName | State::BeforeAttributeName | State::AttributeName |
+                State::AfterAttributeName | State::BeforeAttributeValue |
+                State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted |
+                State::AttributeValueUnquoted | State::AfterAttributeValueQuoted |
+                State::SelfClosingStartTag => {
+                    self.error(errors::ErrorKind::EofInTag);
+                    self.clear_current_token();
+                    break
+                }
+                State::BogusComment => {
+                    self.cur_end = self.position as u32;
+                    self.emit_comment();
+                    break
+                }
+                State::MarkupDeclarationOpen => {
+                    self.error(errors::ErrorKind::IncorrectlyOpenedComment);
+                    self.create_token_at_position();
+                    self.state = State::BogusComment;
+                }
+                State::CommentStart | State::CommentLessThanSign | State::CommentLessThanSignBang => {
+                    self.state = State::Comment;
+                }
+                State::CommentLessThanSignBangDash => {
+                    self.state = State::CommentEndDash;
+                }
+                State::CommentLessThanSignBangDashDash => {
+                    self.state = State::CommentEnd;
+                }
+                State::CommentStartDash | State::Comment | State::CommentEndDash |
+                State::CommentEnd | State::CommentEndBang => {
+                    self.error(errors::ErrorKind::EofInComment);
+                    self.emit_comment();
+                    break
+                }
+                State::Doctype | State::BeforeDoctypeName => {
+                    self.error(errors::ErrorKind::EofInDoctype);
+                    self.create_token_at_position();
+                    self.emit_doctype(true);
+                    break
+                }
+                State::DoctypeName => {
+                    self.error(errors::ErrorKind::EofInDoctype);
+                    self.cur_end = self.position as u32;
+                    self.emit_doctype(true);
+                    break
+                }
+                State::AfterDoctypeName | State::AfterDoctypePublicKeyword |
+                State::BeforeDoctypePublicIdentifier | State::DoctypePublicIdentifierDoubleQuoted |
+                State::DoctypePublicIdentifierSingleQuoted | State::AfterDoctypePublicIdentifier |
+                State::BetweenDoctypePublicAndSystemIdentifiers | State::AfterDoctypeSystemKeyword |
+                State::BeforeDoctypeSystemIdentifier | State::DoctypeSystemIdentifierDoubleQuoted |
+                State::DoctypeSystemIdentifierSingleQuoted | State::AfterDoctypeSystemIdentifier => {
+                    self.error(errors::ErrorKind::EofInDoctype);
+                    self.emit_doctype(true);
+                    break
+                }
+                State::BogusDoctype => {
+                    self.emit_doctype(false);
+                    break
+                }
+                State::CdataSection => {
+                    self.error(errors::ErrorKind::EofInCdata);
+                    break
+                }
+                State::CdataSectionBracket | State::CdataSectionEnd => {
+                    self.error(errors::ErrorKind::EofInCdata);
+                    self.start_text(self.token_start);
+                    break
+                }
+                State::CharacterReference | State::NamedCharacterReference | State::AmbiguousAmpersand => {
+                    self.state = self.return_state;
+                }
+                State::NumericCharacterReference | State::HexadecimalCharacterReferenceStart |
+                State::DecimalCharacterReferenceStart => {
+                    self.error(errors::ErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                    self.state = self.return_state;
+                }
+                State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                    self.error(errors::ErrorKind::MissingSemicolonAfterCharacterReference);
+                    self.state = State::NumericCharacterReferenceEnd;
+                }
+                State::NumericCharacterReferenceEnd => {
+                    self.numeric_character_reference_end_state();
+                }
+            }
+            self.observer.state_transition(previous, self.state);
+        }
+        self.emit_end_of_file();
+    }
+
+    /// Skips over a run of plain text, stopping at the next byte that is one of `needles`.
+    fn scan_text<const N: usize>(&mut self, needles: [u8; N]) {
+        let next = scan::find_any(self.raw_html, self.position, needles);
+        if next > self.position {
+            self.start_text(self.position);
+            self.position = next;
         }
     }
 
     fn data_state(&mut self) {
         while self.position < self.raw_html.len() {
+            self.scan_text([b'<', b'&', b'\0']);
+            if self.position == self.raw_html.len() {
+                break;
+            }
             match self.raw_html[self.position] {
                 b'&' => {
+                    self.start_text(self.position);
                     self.return_state = State::Data;
                     self.state = State::CharacterReference;
                     self.position += 1;
                     break;
                 },
                 b'<' => {
+                    self.token_start = self.position;
                     self.state = State::TagOpen;
                     self.position += 1;
                     break;
                 },
                 b'\0' => {
-                    // Parse error
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.start_text(self.position);
+                    self.position += 1;
                 },
-                EOF => {
-                    // Parse error
+                _ => {
+                    self.start_text(self.position);
+                    self.position += 1;
                 },
-                _ => {},
             }
         }
     }
 
-    fn tag_open_state(&mut self) {
+    fn rcdata_state(&mut self) {
         while self.position < self.raw_html.len() {
+            self.scan_text([b'<', b'&', b'\0']);
+            if self.position == self.raw_html.len() {
+                break;
+            }
             match self.raw_html[self.position] {
-                b'A'..=b'z' => {
-                    self.emit_current_token_as_text();
-                    self.cur_start = self.position as u32;
-                    self.state = State::TagName;
-                    break;
+                b'&' => {
+                    self.start_text(self.position);
+                    self.return_state = State::Rcdata;
+                    self.state = State::CharacterReference;
+                    self.position += 1;
+                    break
                 }
-                b'!' => {
-                    self.emit_current_token_as_text();
-                    self.state = State::MarkupDeclarationOpen;
+                b'<' => {
+                    self.token_start = self.position;
+                    self.state = State::RcdataLessThanSign;
                     self.position += 1;
-                    break;
+                    break
                 }
-                b'/' => {
-                    self.state = State::EndTagOpen;
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.start_text(self.position);
                     self.position += 1;
-                    break;
                 }
-                b'?' => {
-                    // Parse error
-                    self.state = State::BogusComment;
-                    break;
+                _ => {
+                    self.start_text(self.position);
+                    self.position += 1;
+                },
+            }
+        }
+    }
+
+    fn rawtext_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            self.scan_text([b'<', b'\0']);
+            if self.position == self.raw_html.len() {
+                break;
+            }
+            match self.raw_html[self.position] {
+                b'<' => {
+                    self.token_start = self.position;
+                    self.state = State::RawtextLessThanSign;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.start_text(self.position);
+                    self.position += 1;
+                }
+                _ => {
+                    self.start_text(self.position);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn script_data_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            self.scan_text([b'<', b'\0']);
+            if self.position == self.raw_html.len() {
+                break;
+            }
+            match self.raw_html[self.position] {
+                b'<' => {
+                    self.token_start = self.position;
+                    self.state = State::ScriptDataLessThanSign;
+                    self.position += 1;
+                    break
                 }
-                EOF => {
-                    // TODO: handle unexpected EOF
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.start_text(self.position);
+                    self.position += 1;
                 }
                 _ => {
-                    // Parse error
+                    self.start_text(self.position);
+                    self.position += 1;
                 }
             }
         }
     }
 
+    fn plaintext_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            self.scan_text([b'\0']);
+            if self.position == self.raw_html.len() {
+                break;
+            }
+            self.error(errors::ErrorKind::UnexpectedNullCharacter);
+            self.start_text(self.position);
+            self.position += 1;
+        }
+    }
+
+    fn tag_open_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'!' => {
+                self.state = State::MarkupDeclarationOpen;
+                self.position += 1;
+            }
+            b'/' => {
+                self.state = State::EndTagOpen;
+                self.position += 1;
+            }
+            b'A'..=b'Z' | b'a'..=b'z' => {
+                self.create_tag_token(tokens::Flags::Open as u8);
+                self.state = State::TagName;
+            }
+            b'?' => {
+                self.error(errors::ErrorKind::UnexpectedQuestionMarkInsteadOfTagName);
+                self.create_token_at_position();
+                self.state = State::BogusComment;
+            }
+            _ => {
+                self.error(errors::ErrorKind::InvalidFirstCharacterOfTagName);
+                self.start_text(self.token_start);
+                self.state = State::Data;
+            }
+        }
+    }
+
+    fn end_tag_open_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'A'..=b'Z' | b'a'..=b'z' => {
+                self.create_tag_token(tokens::Flags::Close as u8);
+                self.state = State::TagName;
+            }
+            b'>' => {
+                self.error(errors::ErrorKind::MissingEndTagName);
+                self.flush_text(self.token_start);
+                self.state = State::Data;
+                self.position += 1;
+            }
+            _ => {
+                self.error(errors::ErrorKind::InvalidFirstCharacterOfTagName);
+                self.create_token_at_position();
+                self.state = State::BogusComment;
+            }
+        }
+    }
+
     fn tag_name_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
@@ -215,389 +863,1401 @@ impl<'a> Tokenizer<'a> {
                     break
                 }
                 b'>' => {
-                    self.cur_end = self.position as u32;
-                    self.emit_current_token_no_text(0);
+                    self.cur_end = self.position as u32;
+                    self.emit_current_token_no_text(0);
+                    self.state = State::Data;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
+                }
+                _ => {
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    /// Shared by the RCDATA and RAWTEXT less-than sign states.
+    fn text_less_than_sign_state(&mut self, end_tag_open: State, text_state: State) {
+        match self.raw_html[self.position] {
+            b'/' => {
+                self.temp_buffer.clear();
+                self.state = end_tag_open;
+                self.position += 1
+            }
+            _ => {
+                self.start_text(self.token_start);
+                self.state = text_state;
+            }
+        }
+    }
+
+    /// Shared by the RCDATA, RAWTEXT, script data and script data escaped end tag open states.
+    fn text_end_tag_open_state(&mut self, end_tag_name: State, text_state: State) {
+        match self.raw_html[self.position] {
+            b'A'..=b'Z' | b'a'..=b'z' => {
+                self.cur_start = self.position as u32;
+                self.state = end_tag_name
+            }
+            _ => {
+                self.start_text(self.token_start);
+                self.state = text_state
+            }
+        }
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        let name = &self.raw_html[self.cur_start as usize..self.position];
+        !self.last_start_tag.is_empty() && name.eq_ignore_ascii_case(&self.last_start_tag)
+    }
+
+    /// The end tag name is kept as a span of the input, so instead of filling the temporary
+    /// buffer we compare that span with the last start tag once the name is over.
+    fn text_end_tag_name_state(&mut self, text_state: State) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' if self.is_appropriate_end_tag() => {
+                    self.commit_end_tag();
+                    self.state = State::BeforeAttributeName;
+                    self.position += 1;
+                    break
+                }
+                b'/' if self.is_appropriate_end_tag() => {
+                    self.commit_end_tag();
+                    self.state = State::SelfClosingStartTag;
+                    self.position += 1;
+                    break
+                }
+                b'>' if self.is_appropriate_end_tag() => {
+                    self.commit_end_tag();
+                    self.emit_current_token_no_text(0);
+                    self.state = State::Data;
+                    self.position += 1;
+                    break
+                }
+                b'A'..=b'Z' | b'a'..=b'z' => {
+                    self.position += 1;
+                }
+                _ => {
+                    self.start_text(self.token_start);
+                    self.state = text_state;
+                    break
+                }
+            }
+        }
+    }
+
+    fn commit_end_tag(&mut self) {
+        let name_start = self.cur_start;
+        self.create_tag_token(tokens::Flags::Close as u8);
+        self.cur_start = name_start;
+        self.cur_end = self.position as u32;
+    }
+
+    fn script_data_less_than_sign_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'/' => {
+                self.temp_buffer.clear();
+                self.state = State::ScriptDataEndTagOpen;
+                self.position += 1;
+            }
+            b'!' => {
+                self.start_text(self.token_start);
+                self.state = State::ScriptDataEscapeStart;
+                self.position += 1;
+            }
+            _ => {
+                self.start_text(self.token_start);
+                self.state = State::ScriptData;
+            }
+        }
+    }
+
+    fn script_data_escape_start_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::ScriptDataEscapeStartDash;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::ScriptData;
+            }
+        }
+    }
+
+    fn script_data_escape_start_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::ScriptDataEscapedDashDash;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::ScriptData;
+            }
+        }
+    }
+
+    fn script_data_escaped_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            self.scan_text([b'-', b'<', b'\0']);
+            if self.position == self.raw_html.len() {
+                break
+            }
+            match self.raw_html[self.position] {
+                b'-' => {
+                    self.start_text(self.position);
+                    self.state = State::ScriptDataEscapedDash;
+                    self.position += 1;
+                    break
+                }
+                b'<' => {
+                    self.token_start = self.position;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.start_text(self.position);
+                    self.position += 1;
+                }
+                _ => {
+                    self.start_text(self.position);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn script_data_escaped_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::ScriptDataEscapedDashDash;
+                self.position += 1;
+            }
+            b'<' => {
+                self.token_start = self.position;
+                self.state = State::ScriptDataEscapedLessThanSign;
+                self.position += 1;
+            }
+            b'\0' => {
+                self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                self.state = State::ScriptDataEscaped;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::ScriptDataEscaped;
+                self.position += 1;
+            }
+        }
+    }
+
+    fn script_data_escaped_dash_dash_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'-' => {
+                    self.start_text(self.position);
+                    self.position += 1;
+                }
+                b'<' => {
+                    self.token_start = self.position;
+                    self.state = State::ScriptDataEscapedLessThanSign;
+                    self.position += 1;
+                    break
+                }
+                b'>' => {
+                    self.state = State::ScriptData;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.state = State::ScriptDataEscaped;
+                    self.position += 1;
+                    break
+                }
+                _ => {
+                    self.state = State::ScriptDataEscaped;
+                    self.position += 1;
+                    break
+                }
+            }
+        }
+    }
+
+    fn script_data_escaped_less_than_sign_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'/' => {
+                self.temp_buffer.clear();
+                self.state = State::ScriptDataEscapedEndTagOpen;
+                self.position += 1;
+            }
+            b'A'..=b'Z' | b'a'..=b'z' => {
+                self.temp_buffer.clear();
+                self.start_text(self.token_start);
+                self.state = State::ScriptDataDoubleEscapeStart;
+            }
+            _ => {
+                self.start_text(self.token_start);
+                self.state = State::ScriptDataEscaped;
+            }
+        }
+    }
+
+    fn script_data_double_escape_start_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' | b'/' | b'>' => {
+                    if self.temp_buffer == b"script" {
+                        self.state = State::ScriptDataDoubleEscaped;
+                    } else {
+                        self.state = State::ScriptDataEscaped;
+                    }
+                    self.position += 1;
+                    break
+                }
+                byte @ (b'A'..=b'Z' | b'a'..=b'z') => {
+                    self.temp_buffer.push(byte.to_ascii_lowercase());
+                    self.position += 1;
+                }
+                _ => {
+                    self.state = State::ScriptDataEscaped;
+                    break
+                }
+            }
+        }
+    }
+
+    fn script_data_double_escaped_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'-' => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.position += 1;
+                    break
+                }
+                b'<' => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
+                }
+                _ => {
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn script_data_double_escaped_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::ScriptDataDoubleEscapedDashDash;
+            }
+            b'<' => {
+                self.state = State::ScriptDataDoubleEscapedLessThanSign;
+            }
+            b'\0' => {
+                self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                self.state = State::ScriptDataDoubleEscaped;
+            }
+            _ => {
+                self.state = State::ScriptDataDoubleEscaped;
+            }
+        }
+        self.position += 1;
+    }
+
+    fn script_data_double_escaped_dash_dash_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'-' => {
+                    self.position += 1;
+                }
+                b'<' => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.position += 1;
+                    break
+                }
+                b'>' => {
+                    self.state = State::ScriptData;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.position += 1;
+                    break
+                }
+                _ => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.position += 1;
+                    break
+                }
+            }
+        }
+    }
+
+    fn script_data_double_escaped_less_than_sign_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'/' => {
+                self.temp_buffer.clear();
+                self.state = State::ScriptDataDoubleEscapeEnd;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::ScriptDataDoubleEscaped;
+            }
+        }
+    }
+
+    fn script_data_double_escape_end_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' | b'/' | b'>' => {
+                    if self.temp_buffer == b"script" {
+                        self.state = State::ScriptDataEscaped;
+                    } else {
+                        self.state = State::ScriptDataDoubleEscaped;
+                    }
+                    self.position += 1;
+                    break
+                }
+                byte @ (b'A'..=b'Z' | b'a'..=b'z') => {
+                    self.temp_buffer.push(byte.to_ascii_lowercase());
+                    self.position += 1;
+                }
+                _ => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    break
+                }
+            }
+        }
+    }
+
+    fn before_attribute_name_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' => {
+                    self.position += 1
+                }
+                b'/' | b'>' => {
+                    self.state = State::AfterAttributeName;
+                    break
+                }
+                b'=' => {
+                    self.error(errors::ErrorKind::UnexpectedEqualsSignBeforeAttributeName);
+                    self.create_empty_attribute();
+                    self.cur_attributes.last_mut().unwrap().name_size = 1;
+                    self.state = State::AttributeName;
+                    self.position += 1;
+                    break
+                }
+                _ => {
+                    self.state = State::AttributeName;
+                    self.create_empty_attribute();
+                    break
+                }
+            }
+        }
+    }
+
+    fn attribute_name_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' | b'/' | b'>' => {
+                    self.check_duplicate_attribute();
+                    self.state = State::AfterAttributeName;
+                    break
+                }
+                b'=' => {
+                    self.check_duplicate_attribute();
+                    self.state = State::BeforeAttributeValue;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.cur_attributes.last_mut().unwrap().name_size += 1;
+                    self.position += 1
+                }
+                b'"' | b'\'' | b'<' => {
+                    self.error(errors::ErrorKind::UnexpectedCharacterInAttributeName);
+                    self.cur_attributes.last_mut().unwrap().name_size += 1;
+                    self.position += 1
+                }
+                _ => {
+                    let cur_attribute = self.cur_attributes.last_mut().unwrap();
+                    cur_attribute.name_size += 1;
+                    self.position += 1
+                }
+            }
+        }
+    }
+
+    fn after_attribute_name_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' => {
+                    self.position += 1
+                }
+                b'/' => {
+                    self.state = State::SelfClosingStartTag;
+                    self.position += 1;
+                    break
+                }
+                b'=' => {
+                    self.state = State::BeforeAttributeValue;
+                    self.position += 1;
+                    break
+                }
+                b'>' => {
+                    self.state = State::Data;
+                    self.emit_current_token_no_text(0);
+                    self.position += 1;
+                    break
+                }
+                _ => {
+                    self.state = State::AttributeName;
+                    self.create_empty_attribute();
+                    break
+                }
+            }
+        }
+    }
+
+    fn before_attribute_value_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' => {
+                    self.position += 1
+                }
+                b'"' => {
+                    self.state = State::AttributeValueDoubleQuoted;
+                    self.position += 1;
+                    let attr = self.cur_attributes.last_mut().unwrap();
+                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
+                    attr.quoting = tokens::Quoting::DoubleQuoted;
+                    break
+                }
+                b'\'' => {
+                    self.state = State::AttributeValueSingleQuoted;
+                    self.position += 1;
+                    let attr = self.cur_attributes.last_mut().unwrap();
+                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
+                    attr.quoting = tokens::Quoting::SingleQuoted;
+                    break
+                }
+                b'>' => {
+                    self.error(errors::ErrorKind::MissingAttributeValue);
+                    let attr = self.cur_attributes.last_mut().unwrap();
+                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
+                    self.state = State::Data;
+                    self.emit_current_token_no_text(0);
+                    self.position += 1;
+                    break
+                }
+                _ => {
+                    self.state = State::AttributeValueUnquoted;
+                    let attr = self.cur_attributes.last_mut().unwrap();
+                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
+                    attr.quoting = tokens::Quoting::Unquoted;
+                    break
+                }
+            }
+        }
+    }
+
+    fn attribute_value_double_quoted_state(&mut self) {
+        self.attribute_value_quoted_state(b'"')
+    }
+
+    fn attribute_value_single_quoted_state(&mut self) {
+        self.attribute_value_quoted_state(b'\'')
+    }
+
+    fn attribute_value_quoted_state(&mut self, quote: u8) {
+        while self.position < self.raw_html.len() {
+            let next = scan::find_any(self.raw_html, self.position, [quote, b'&', b'\0']);
+            self.cur_attributes.last_mut().unwrap().value_size += (next - self.position) as u32;
+            self.position = next;
+            if self.position == self.raw_html.len() {
+                break
+            }
+            match self.raw_html[self.position] {
+                b'&' => {
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.return_state = self.state;
+                    self.state = State::CharacterReference;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.position += 1
+                }
+                _ => {
+                    self.state = State::AfterAttributeValueQuoted;
+                    self.position += 1;
+                    break
+                }
+            }
+        }
+    }
+
+    fn attribute_value_unquoted_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'\t' | b'\n' | FF | b' ' => {
+                    self.state = State::BeforeAttributeName;
+                    self.position += 1;
+                    break
+                }
+                b'&' => {
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.return_state = self.state;
+                    self.state = State::CharacterReference;
+                    self.position += 1;
+                    break
+                }
+                b'>' => {
+                    self.state = State::Data;
+                    self.emit_current_token_no_text(0);
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.position += 1
+                }
+                b'"' | b'\'' | b'<' | b'=' | b'`' => {
+                    self.error(errors::ErrorKind::UnexpectedCharacterInUnquotedAttributeValue);
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.position += 1
+                }
+                _ => {
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.position += 1
+                }
+            }
+        }
+    }
+
+    fn after_attribute_value_quoted_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'\t' | b'\n' | FF | b' ' => {
+                self.state = State::BeforeAttributeName;
+                self.position += 1;
+            }
+            b'/' => {
+                self.state = State::SelfClosingStartTag;
+                self.position += 1;
+            }
+            b'>' => {
+                self.state = State::Data;
+                self.emit_current_token_no_text(0);
+                self.position += 1;
+            }
+            _ => {
+                self.error(errors::ErrorKind::MissingWhitespaceBetweenAttributes);
+                self.state = State::BeforeAttributeName;
+            }
+        }
+    }
+
+    fn self_closing_start_tag_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'>' => {
+                self.state = State::Data;
+                self.emit_current_token_no_text(tokens::Flags::CloseSelf as u8);
+                self.position += 1;
+            }
+            _ => {
+                self.error(errors::ErrorKind::UnexpectedSolidusInTag);
+                self.state = State::BeforeAttributeName;
+            }
+        }
+    }
+
+    fn bogus_comment_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            self.position = scan::find_any(self.raw_html, self.position, [b'>', b'\0']);
+            if self.position == self.raw_html.len() {
+                break
+            }
+            match self.raw_html[self.position] {
+                b'>' => {
+                    self.cur_end = self.position as u32;
+                    self.emit_comment();
+                    self.state = State::Data;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
+                }
+                _ => {
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn markup_declaration_open_state(&mut self) {
+        if self.raw_html[self.position..].starts_with(b"--") {
+            self.position += 2;
+            self.create_token_at_position();
+            self.state = State::CommentStart;
+        } else if self.starts_with_ignore_case(b"DOCTYPE") {
+            self.position += 7;
+            self.state = State::Doctype;
+        } else if self.raw_html[self.position..].starts_with(b"[CDATA[") {
+            if self.foreign_content {
+                // "<![CDATA[" isn't part of the text that follows
+                self.flush_text(self.token_start);
+                self.position += 7;
+                self.state = State::CdataSection;
+            } else {
+                self.error(errors::ErrorKind::CdataInHtmlContent);
+                self.create_token_at_position();
+                self.state = State::BogusComment;
+                self.position += 7;
+            }
+        } else {
+            self.error(errors::ErrorKind::IncorrectlyOpenedComment);
+            self.create_token_at_position();
+            self.state = State::BogusComment;
+        }
+    }
+
+    // The comment data is always a contiguous span of the input, `cur_end` marks how much of it
+    // has been appended so far. Dashes and bangs that may turn out to close the comment are
+    // only appended once we know they don't.
+
+    fn comment_start_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::CommentStartDash;
+                self.position += 1;
+            }
+            b'>' => {
+                self.error(errors::ErrorKind::AbruptClosingOfEmptyComment);
+                self.emit_comment();
+                self.state = State::Data;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    fn comment_start_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::CommentEnd;
+                self.position += 1;
+            }
+            b'>' => {
+                self.error(errors::ErrorKind::AbruptClosingOfEmptyComment);
+                self.emit_comment();
+                self.state = State::Data;
+                self.position += 1;
+            }
+            _ => {
+                self.cur_end = self.position as u32;
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    fn comment_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            let next = scan::find_any(self.raw_html, self.position, [b'<', b'-', b'\0']);
+            if next > self.position {
+                self.position = next;
+                self.cur_end = next as u32;
+            }
+            if self.position == self.raw_html.len() {
+                break
+            }
+            match self.raw_html[self.position] {
+                b'<' => {
+                    self.state = State::CommentLessThanSign;
+                    self.position += 1;
+                    self.cur_end = self.position as u32;
+                    break
+                }
+                b'-' => {
+                    self.state = State::CommentEndDash;
+                    self.position += 1;
+                    break
+                }
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
+                    self.cur_end = self.position as u32;
+                }
+                _ => {
+                    self.position += 1;
+                    self.cur_end = self.position as u32;
+                }
+            }
+        }
+    }
+
+    fn comment_less_than_sign_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'!' => {
+                self.state = State::CommentLessThanSignBang;
+                self.position += 1;
+                self.cur_end = self.position as u32;
+            }
+            b'<' => {
+                self.position += 1;
+                self.cur_end = self.position as u32;
+            }
+            _ => {
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    fn comment_less_than_sign_bang_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::CommentLessThanSignBangDash;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    fn comment_less_than_sign_bang_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::CommentLessThanSignBangDashDash;
+                self.position += 1;
+            }
+            _ => {
+                self.state = State::CommentEndDash;
+            }
+        }
+    }
+
+    fn comment_less_than_sign_bang_dash_dash_state(&mut self) {
+        if self.raw_html[self.position] != b'>' {
+            self.error(errors::ErrorKind::NestedComment);
+        }
+        self.state = State::CommentEnd;
+    }
+
+    fn comment_end_dash_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.state = State::CommentEnd;
+                self.position += 1;
+            }
+            _ => {
+                self.cur_end = self.position as u32;
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    fn comment_end_state(&mut self) {
+        while self.position < self.raw_html.len() {
+            match self.raw_html[self.position] {
+                b'>' => {
+                    self.emit_comment();
                     self.state = State::Data;
                     self.position += 1;
                     break
                 }
-                b'\0' => {
-                    // TODO: handle unexpected null
+                b'!' => {
+                    self.state = State::CommentEndBang;
+                    self.position += 1;
+                    break
                 }
-                EOF => {
-                    // TODO: handle unexpected EOF
+                b'-' => {
+                    self.cur_end = self.position as u32 - 1;
+                    self.position += 1;
                 }
                 _ => {
-                    self.position += 1;
+                    self.cur_end = self.position as u32;
+                    self.state = State::Comment;
+                    break
                 }
             }
         }
     }
 
-    fn before_attribute_name_state(&mut self) {
+    fn comment_end_bang_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'-' => {
+                self.cur_end = self.position as u32;
+                self.state = State::CommentEndDash;
+                self.position += 1;
+            }
+            b'>' => {
+                self.error(errors::ErrorKind::IncorrectlyClosedComment);
+                self.emit_comment();
+                self.state = State::Data;
+                self.position += 1;
+            }
+            _ => {
+                self.cur_end = self.position as u32;
+                self.state = State::Comment;
+            }
+        }
+    }
+
+    // Doctype tokens keep their name in `start`/`end`. The public and system identifiers are
+    // stored as attributes whose name is the keyword that introduced them (empty for a system
+    // identifier following a public one) and whose value is the identifier itself. A missing
+    // identifier has no attribute at all.
+
+    fn doctype_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'\t' | b'\n' | FF | b' ' => {
+                self.state = State::BeforeDoctypeName;
+                self.position += 1;
+            }
+            b'>' => {
+                self.state = State::BeforeDoctypeName;
+            }
+            _ => {
+                self.error(errors::ErrorKind::MissingWhitespaceBeforeDoctypeName);
+                self.state = State::BeforeDoctypeName;
+            }
+        }
+    }
+
+    fn before_doctype_name_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'\t' | b'\n' | FF | b' ' => {
-                    self.position += 1
+                    self.position += 1;
                 }
-                b'/' | b'>' | EOF => {
-                    self.state = State::AfterAttributeName;
+                b'>' => {
+                    self.error(errors::ErrorKind::MissingDoctypeName);
+                    self.create_token_at_position();
+                    self.emit_doctype(true);
+                    self.state = State::Data;
+                    self.position += 1;
                     break
                 }
-                b'=' => {
-                    // TODO: handle unique error
-                }
-                _ => {
-                    self.state = State::AttributeName;
-                    self.create_empty_attribute();
+                byte => {
+                    if byte == b'\0' {
+                        self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    }
+                    self.create_token_at_position();
+                    self.state = State::DoctypeName;
+                    self.position += 1;
                     break
                 }
             }
         }
     }
 
-    fn attribute_name_state(&mut self) {
-        // TODO: compare with previous attributes for duplicates
+    fn doctype_name_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
-                b'\t' | b'\n' | FF | b' ' | b'/' | b'>' | EOF => {
-                    self.state = State::AfterAttributeName;
+                b'\t' | b'\n' | FF | b' ' => {
+                    self.cur_end = self.position as u32;
+                    self.state = State::AfterDoctypeName;
+                    self.position += 1;
                     break
                 }
-                b'=' => {
-                    self.state = State::BeforeAttributeValue;
+                b'>' => {
+                    self.cur_end = self.position as u32;
+                    self.emit_doctype(false);
+                    self.state = State::Data;
                     self.position += 1;
                     break
                 }
                 b'\0' => {
-                    // TODO: handle unique error
-                }
-                b'"' | b'\'' | b'<' => {
-                    // TODO: handle unique error
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
                 }
                 _ => {
-                    let cur_attribute = self.cur_attributes.last_mut().unwrap();
-                    cur_attribute.name_size += 1;
-                    self.position += 1
+                    self.position += 1;
                 }
             }
         }
     }
 
-    fn after_attribute_name_state(&mut self) {
+    fn after_doctype_name_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'\t' | b'\n' | FF | b' ' => {
-                    self.position += 1
+                    self.position += 1;
                 }
-                b'/' => {
-                    self.state = State::SelfClosingStartTag;
+                b'>' => {
+                    self.emit_doctype(false);
+                    self.state = State::Data;
                     self.position += 1;
                     break
                 }
-                b'=' => {
-                    self.state = State::BeforeAttributeValue;
-                    self.position += 1;
+                _ if self.starts_with_ignore_case(b"PUBLIC") => {
+                    self.doctype_keyword = self.position;
+                    self.state = State::AfterDoctypePublicKeyword;
+                    self.position += 6;
                     break
                 }
-                b'>' => {
-                    self.state = State::Data;
-                    self.position += 1;
-                    self.emit_current_token_no_text(0);
+                _ if self.starts_with_ignore_case(b"SYSTEM") => {
+                    self.doctype_keyword = self.position;
+                    self.state = State::AfterDoctypeSystemKeyword;
+                    self.position += 6;
                     break
-                } 
-                EOF => {
-                    // TODO: handle unique error
                 }
                 _ => {
-                    self.state = State::BeforeAttributeName;
-                    self.create_empty_attribute();
+                    self.error(errors::ErrorKind::InvalidCharacterSequenceAfterDoctypeName);
+                    self.cur_flags |= tokens::Flags::ForceQuirks as u8;
+                    self.state = State::BogusDoctype;
                     break
                 }
             }
         }
     }
 
-    fn before_attribute_value_state(&mut self) {
-        let attr = self.cur_attributes.last_mut().unwrap();
+    fn is_public_identifier(state: State) -> bool {
+        matches!(state, State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier |
+                        State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted)
+    }
+
+    /// Starts a doctype identifier right after its opening quote.
+    fn create_doctype_identifier(&mut self, keyword: Option<usize>) {
+        let value_begin = Self::value_begin(self.position + 1, self.cur_start);
+        let mut attribute = match keyword {
+            Some(keyword) => tokens::Attribute::new(Self::value_begin(keyword, self.cur_start), 6, value_begin, 0),
+            None => tokens::Attribute::new(value_begin - 1, 0, value_begin, 0),
+        };
+        attribute.quoting = match self.raw_html[self.position] {
+            b'"' => tokens::Quoting::DoubleQuoted,
+            _ => tokens::Quoting::SingleQuoted,
+        };
+        self.cur_attributes.push(attribute);
+    }
+
+    fn after_doctype_keyword_state(&mut self, before_identifier: State, double_quoted: State, single_quoted: State) {
+        let public = Self::is_public_identifier(self.state);
+        match self.raw_html[self.position] {
+            b'\t' | b'\n' | FF | b' ' => {
+                self.state = before_identifier;
+                self.position += 1;
+            }
+            quote @ (b'"' | b'\'') => {
+                self.error(if public {
+                    errors::ErrorKind::MissingWhitespaceAfterDoctypePublicKeyword
+                } else {
+                    errors::ErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword
+                });
+                self.create_doctype_identifier(Some(self.doctype_keyword));
+                self.state = if quote == b'"' { double_quoted } else { single_quoted };
+                self.position += 1;
+            }
+            b'>' => {
+                self.missing_doctype_identifier(public);
+            }
+            _ => {
+                self.missing_quote_before_doctype_identifier(public);
+            }
+        }
+    }
+
+    fn before_doctype_identifier_state(&mut self, double_quoted: State, single_quoted: State) {
+        let public = Self::is_public_identifier(self.state);
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'\t' | b'\n' | FF | b' ' => {
-                    self.position += 1
-                }
-                b'"' => {
-                    self.state = State::AttributeValueDoubleQuoted;
                     self.position += 1;
-                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
-                    break
                 }
-                b'\'' => {
-                    self.state = State::AttributeValueSingleQuoted;
+                quote @ (b'"' | b'\'') => {
+                    self.create_doctype_identifier(Some(self.doctype_keyword));
+                    self.state = if quote == b'"' { double_quoted } else { single_quoted };
                     self.position += 1;
-                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
                     break
                 }
                 b'>' => {
-                    // TODO: Handle unique error state
+                    self.missing_doctype_identifier(public);
+                    break
                 }
                 _ => {
-                    self.state = State::AttributeValueUnquoted;
-                    attr.value_begin = Self::value_begin(self.position, self.cur_start);
+                    self.missing_quote_before_doctype_identifier(public);
                     break
                 }
             }
         }
     }
 
-    fn attribute_value_double_quoted_state(&mut self) {
-        let attribute = self.cur_attributes.last_mut().unwrap();
+    fn missing_doctype_identifier(&mut self, public: bool) {
+        self.error(if public {
+            errors::ErrorKind::MissingDoctypePublicIdentifier
+        } else {
+            errors::ErrorKind::MissingDoctypeSystemIdentifier
+        });
+        self.emit_doctype(true);
+        self.state = State::Data;
+        self.position += 1;
+    }
+
+    fn missing_quote_before_doctype_identifier(&mut self, public: bool) {
+        self.error(if public {
+            errors::ErrorKind::MissingQuoteBeforeDoctypePublicIdentifier
+        } else {
+            errors::ErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier
+        });
+        self.cur_flags |= tokens::Flags::ForceQuirks as u8;
+        self.state = State::BogusDoctype;
+    }
+
+    fn doctype_identifier_state(&mut self, quote: u8, after_identifier: State) {
+        let public = Self::is_public_identifier(self.state);
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
-                b'"' => {
-                    self.state = State::AfterAttributeValueQuoted;
+                byte if byte == quote => {
+                    self.state = after_identifier;
                     self.position += 1;
                     break
                 }
-                b'&' => {
-                    self.return_state = self.state;
-                    self.state = State::CharacterReference;
+                b'>' => {
+                    self.error(if public {
+                        errors::ErrorKind::AbruptDoctypePublicIdentifier
+                    } else {
+                        errors::ErrorKind::AbruptDoctypeSystemIdentifier
+                    });
+                    self.emit_doctype(true);
+                    self.state = State::Data;
                     self.position += 1;
                     break
                 }
-                b'\0' => {
-                    // TODO: handle unique error
-                }
-                EOF => {
-                    // TODO: handle unique error
-                }
-                _ => {
-                    attribute.value_size += 1;
-                    self.position += 1
+                byte => {
+                    if byte == b'\0' {
+                        self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    }
+                    self.cur_attributes.last_mut().unwrap().value_size += 1;
+                    self.position += 1;
                 }
             }
         }
     }
 
-    fn attribute_value_single_quoted_state(&mut self) {
-        let attribute = self.cur_attributes.last_mut().unwrap();
-        while self.position < self.raw_html.len() {
-            match self.raw_html[self.position] {
-                b'\'' => {
-                    self.state = State::AfterAttributeValueQuoted;
-                    self.position += 1;
-                    break
-                }
-                b'&' => {
-                    self.return_state = self.state;
-                    self.state = State::CharacterReference;
-                    self.position += 1;
-                    break
-                }
-                b'\0' => {
-                    // TODO: Handle unique error
-                }
-                EOF => {
-                    // TODO: Handle unique error
-                }
-                _ => {
-                    attribute.value_size += 1;
-                    self.position += 1
-                }
+    fn after_doctype_public_identifier_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'\t' | b'\n' | FF | b' ' => {
+                self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                self.position += 1;
+            }
+            b'>' => {
+                self.emit_doctype(false);
+                self.state = State::Data;
+                self.position += 1;
+            }
+            quote @ (b'"' | b'\'') => {
+                self.error(errors::ErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers);
+                self.create_doctype_identifier(None);
+                self.state = if quote == b'"' {
+                    State::DoctypeSystemIdentifierDoubleQuoted
+                } else {
+                    State::DoctypeSystemIdentifierSingleQuoted
+                };
+                self.position += 1;
+            }
+            _ => {
+                self.missing_quote_before_doctype_identifier(false);
             }
         }
     }
 
-    fn attribute_value_unquoted_state(&mut self) {
-        let attribute = self.cur_attributes.last_mut().unwrap();
+    fn between_doctype_public_and_system_identifiers_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'\t' | b'\n' | FF | b' ' => {
-                    self.state = State::BeforeAttributeName;
-                    self.position += 1;
-                    break
-                }
-                b'&' => {
-                    self.return_state = self.state;
-                    self.state = State::CharacterReference;
                     self.position += 1;
-                    break
                 }
                 b'>' => {
+                    self.emit_doctype(false);
                     self.state = State::Data;
-                    self.emit_current_token_no_text(0);
                     self.position += 1;
                     break
                 }
-                b'\0' => {
-                    // TODO: handle unique error
-                }
-                b'"' | b'\'' | b'<' | b'=' | b'`' => {
-                    // TODO: handle unique error
-                    attribute.value_size += 1;
+                quote @ (b'"' | b'\'') => {
+                    self.create_doctype_identifier(None);
+                    self.state = if quote == b'"' {
+                        State::DoctypeSystemIdentifierDoubleQuoted
+                    } else {
+                        State::DoctypeSystemIdentifierSingleQuoted
+                    };
+                    self.position += 1;
+                    break
                 }
                 _ => {
-                    attribute.value_size += 1;
-                    self.position += 1
+                    self.missing_quote_before_doctype_identifier(false);
+                    break
                 }
             }
         }
     }
 
-    fn after_attribute_value_quoted_state(&mut self) {
+    fn after_doctype_system_identifier_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'\t' | b'\n' | FF | b' ' => {
-                    self.state = State::BeforeAttributeName;
-                    self.position += 1;
-                    break
-                }
-                b'/' => {
-                    self.state = State::SelfClosingStartTag;
                     self.position += 1;
-                    break
                 }
                 b'>' => {
+                    self.emit_doctype(false);
                     self.state = State::Data;
-                    self.emit_current_token_no_text(0);
                     self.position += 1;
                     break
                 }
-                EOF => {
-                    // TODO: handle unique error
-                }
                 _ => {
-                    // TODO: handle unique error
+                    self.error(errors::ErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier);
+                    self.state = State::BogusDoctype;
+                    break
                 }
             }
         }
     }
 
-    fn self_closing_start_tag_state(&mut self) {
+    fn bogus_doctype_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
                 b'>' => {
+                    self.emit_doctype(false);
                     self.state = State::Data;
-                    self.emit_current_token_no_text(tokens::Flags::CloseSelf as u8);
                     self.position += 1;
                     break
                 }
-                EOF => {
-                    // TODO: Handle error
+                b'\0' => {
+                    self.error(errors::ErrorKind::UnexpectedNullCharacter);
+                    self.position += 1;
                 }
                 _ => {
-                    // TODO: Handle error
+                    self.position += 1;
                 }
             }
         }
     }
 
+    // CDATA section content is emitted as text. The closing "]]>" is not part of it, so when a
+    // ']' shows up `token_start` remembers where the text would end if it does close the section.
 
-    fn rcdata_state(&mut self) {
+    fn cdata_section_state(&mut self) {
+        self.scan_text([b']']);
+        if self.position < self.raw_html.len() {
+            self.token_start = self.position;
+            self.state = State::CdataSectionBracket;
+            self.position += 1;
+        }
+    }
+
+    fn cdata_section_bracket_state(&mut self) {
+        match self.raw_html[self.position] {
+            b']' => {
+                self.state = State::CdataSectionEnd;
+                self.position += 1;
+            }
+            _ => {
+                self.start_text(self.token_start);
+                self.state = State::CdataSection;
+            }
+        }
+    }
+
+    fn cdata_section_end_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
-                b'&' => {
-                    self.return_state = State::Rcdata;
-                    self.state = State::CharacterReference;
+                b']' => {
+                    self.start_text(self.token_start);
+                    self.token_start += 1;
                     self.position += 1;
-                    break
                 }
-                b'<' => {
-                    self.state = State::RcdataLessThanSign;
+                b'>' => {
+                    self.flush_text(self.token_start);
+                    self.state = State::Data;
                     self.position += 1;
                     break
                 }
-                b'\r' => {
-                    // Need to handle carriage return(?)
-                }
-                b'\0' => {
-                    // Parse Error
-                }
-                EOF => {
-                    // EOF, emit end of file
+                _ => {
+                    self.start_text(self.token_start);
+                    self.state = State::CdataSection;
+                    break
                 }
-                _ => {},
             }
         }
     }
 
-    fn rawtext_state(&mut self) {
+    // Tokens are spans of the input, so a reference is never replaced by what it decodes to.
+    // The states below only work out how far each reference goes and which parse errors it
+    // causes, and flag the text run or attribute value it ends up in as needing decoding.
+    fn character_reference_state(&mut self) {
+        match self.raw_html[self.position] {
+            b'#' => {
+                self.consume_character_reference(1);
+                self.state = State::NumericCharacterReference;
+            }
+            byte if byte.is_ascii_alphanumeric() => {
+                self.state = State::NamedCharacterReference;
+            }
+            _ => {
+                self.state = self.return_state;
+            }
+        }
+    }
+
+    fn named_character_reference_state(&mut self) {
+        let (size, _) = match entities::match_named(&self.raw_html[self.position..]) {
+            Some(reference) => reference,
+            None => {
+                self.state = State::AmbiguousAmpersand;
+                return
+            }
+        };
+        let has_semicolon = self.raw_html[self.position + size - 1] == b';';
+        let next = self.raw_html.get(self.position + size).copied();
+        self.consume_character_reference(size);
+        self.state = self.return_state;
+
+        // For historical reasons, "&not=" or "&notit" in an attribute value is left as it is
+        let is_historical = !has_semicolon && self.in_attribute_value() &&
+                            next.is_some_and(|byte| byte == b'=' || byte.is_ascii_alphanumeric());
+        if !is_historical {
+            if !has_semicolon {
+                self.error(errors::ErrorKind::MissingSemicolonAfterCharacterReference);
+            }
+            self.flush_decoded_character_reference();
+        }
+    }
+
+    fn ambiguous_ampersand_state(&mut self) {
         while self.position < self.raw_html.len() {
             match self.raw_html[self.position] {
-                b'<' => {
-                    self.state = State::RawtextLessThanSign;
-                    self.position += 1;
+                byte if byte.is_ascii_alphanumeric() => {
+                    self.consume_character_reference(1);
                 }
-                b'\0' => {
-                    // Parse Error
+                b';' => {
+                    self.error(errors::ErrorKind::UnknownNamedCharacterReference);
+                    self.state = self.return_state;
+                    break
                 }
-                EOF => {
-                    // EOF, emit end of file
+                _ => {
+                    self.state = self.return_state;
+                    break
                 }
-                _ => {}
             }
         }
     }
 
-    fn rawtext_less_than_sign_state(&mut self) {
+    fn numeric_character_reference_state(&mut self) {
+        self.char_ref_code = 0;
         match self.raw_html[self.position] {
-            b'/' => {
-                self.temp_buffer.clear();
-                self.state = State::RawtextEndTagOpen;
-                self.position += 1
+            b'x' | b'X' => {
+                self.consume_character_reference(1);
+                self.state = State::HexadecimalCharacterReferenceStart;
             }
             _ => {
-                self.state = State::Rawtext;
+                self.state = State::DecimalCharacterReferenceStart;
             }
         }
     }
 
-    fn rawtext_end_tag_open_state(&mut self) {
-        match self.raw_html[self.position] {
-            b'A'..=b'z' => {
-                self.state = State::RawtextEndTagName
-            }
-            _ => {
-                self.state = State::Rawtext
-            }
+    fn hexadecimal_character_reference_start_state(&mut self) {
+        if self.raw_html[self.position].is_ascii_hexdigit() {
+            self.state = State::HexadecimalCharacterReference;
+        } else {
+            self.error(errors::ErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+            self.state = self.return_state;
         }
     }
 
-    fn rawtext_end_tag_name_state(&mut self) {
-        // TODO
+    fn decimal_character_reference_start_state(&mut self) {
+        if self.raw_html[self.position].is_ascii_digit() {
+            self.state = State::DecimalCharacterReference;
+        } else {
+            self.error(errors::ErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+            self.state = self.return_state;
+        }
     }
 
-
-    fn character_reference_state(&mut self) {
-        // TODO: make this work correctly
-        self.temp_buffer.clear();
+    fn numeric_character_reference_digits_state(&mut self, radix: u32) {
         while self.position < self.raw_html.len() {
-            match self.raw_html[self.position] {
-                b'A'..=b'z' => {
-                    // Apparently the slowest part of HTML parsing in the named char reference
-                    // state
-                    self.state = State::NamedCharacterReference
-                }
-                b'#' => {
-                    self.temp_buffer.push(b'#');
-                    self.state = State::NumericCharacterReference
+            match (self.raw_html[self.position] as char).to_digit(radix) {
+                Some(digit) => {
+                    // Anything past the end of Unicode is an error either way, so stop counting
+                    self.char_ref_code = (self.char_ref_code * radix + digit).min(0x110000);
+                    self.consume_character_reference(1);
+                }
+                None if self.raw_html[self.position] == b';' => {
+                    self.consume_character_reference(1);
+                    self.state = State::NumericCharacterReferenceEnd;
+                    break
                 }
-                _ => {
-                    // Flush temp buffer as part of code point or otherwise as part of attribute
+                None => {
+                    self.error(errors::ErrorKind::MissingSemicolonAfterCharacterReference);
+                    self.state = State::NumericCharacterReferenceEnd;
+                    break
                 }
             }
         }
     }
+
+    fn numeric_character_reference_end_state(&mut self) {
+        if let (_, Some(error)) = entities::numeric_reference(self.char_ref_code) {
+            self.error(error);
+        }
+        self.flush_decoded_character_reference();
+        self.state = self.return_state;
+    }
+}
+
+/// The states a tokenizer can be started in or switched to from the outside. Which one applies
+/// depends on the element the content belongs to (see the fragment parsing algorithm).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitialState {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    CdataSection,
+}
+
+impl From<InitialState> for State {
+    fn from(state: InitialState) -> Self {
+        match state {
+            InitialState::Data => State::Data,
+            InitialState::Rcdata => State::Rcdata,
+            InitialState::Rawtext => State::Rawtext,
+            InitialState::ScriptData => State::ScriptData,
+            InitialState::Plaintext => State::Plaintext,
+            InitialState::CdataSection => State::CdataSection,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum State {
-    Data, 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Data,
     Rcdata,
     Rawtext,
     ScriptData,
     Plaintext,
     TagOpen,
-    EndTagOpen, 
+    EndTagOpen,
     TagName,
     RcdataLessThanSign,
     RcdataEndTagOpen,
@@ -621,7 +2281,7 @@ enum State {
     ScriptDataDoubleEscapedDash,
     ScriptDataDoubleEscapedDashDash,
     ScriptDataDoubleEscapedLessThanSign,
-    ScriptDataDoubleEscapeEnd, 
+    ScriptDataDoubleEscapeEnd,
     BeforeAttributeName,
     AttributeName,
     AfterAttributeName,
@@ -1,31 +1,125 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
 use phf::phf_map;
 
-#[derive(Debug)]
+use crate::entities;
+use crate::foreign::{self, Namespace};
+
+#[derive(Clone, Debug)]
 pub struct Attribute {
-    pub name_begin: u16,
-    pub name_size: u16,
-    pub value_begin: u16,
-    pub value_size: u16,
+    pub name_begin: u32,
+    pub name_size: u32,
+    pub value_begin: u32,
+    pub value_size: u32,
+    pub flags: u8,
+    pub quoting: Quoting,
     // Maybe add error type as in lexbor?
 }
 
+/// How an attribute's value was written. A value that is `Missing` (`<input disabled>`, or
+/// `<input disabled=>`) is the empty string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quoting {
+    DoubleQuoted,
+    SingleQuoted,
+    Unquoted,
+    Missing,
+}
+
 impl Attribute {
-    pub fn new(name_begin: u16, name_size: u16, value_begin: u16, value_size: u16) -> Self {
+    pub fn new(name_begin: u32, name_size: u32, value_begin: u32, value_size: u32) -> Self {
         Attribute {
             name_begin,
             name_size,
             value_begin,
-            value_size
+            value_size,
+            flags: 0,
+            quoting: Quoting::Missing,
+        }
+    }
+
+    /// Where the attribute's name is in the input. `token` is the token the attribute belongs
+    /// to, since offsets are relative to its start.
+    pub fn name_span(&self, token: &Token) -> Range<usize> {
+        let begin = token.start as usize + self.name_begin as usize;
+        begin..begin + self.name_size as usize
+    }
+
+    /// Where the attribute's value is in the input, without its quotes. Empty (and right
+    /// after the name) when the value is `Missing`.
+    pub fn value_span(&self, token: &Token) -> Range<usize> {
+        if self.quoting == Quoting::Missing {
+            let end = self.name_span(token).end;
+            return end..end;
+        }
+        let begin = token.start as usize + self.value_begin as usize;
+        begin..begin + self.value_size as usize
+    }
+
+    /// The attribute's name as written.
+    pub fn name<'a>(&self, token: &Token, raw_html: &'a str) -> &'a str {
+        &raw_html[self.name_span(token)]
+    }
+
+    /// The attribute's value as written, without its quotes.
+    pub fn raw_value<'a>(&self, token: &Token, raw_html: &'a str) -> &'a str {
+        &raw_html[self.value_span(token)]
+    }
+
+    /// The attribute's name, lowercased and then adjusted the way the spec does for attributes
+    /// of SVG and MathML elements (`viewBox`, `xlink:href`...).
+    pub fn qualified_name<'a>(&self, token: &Token, raw_html: &'a str) -> QualifiedName<'a> {
+        let local = normalize_name(self.name(token, raw_html));
+        let namespace = token.namespace();
+        if namespace != Namespace::Html {
+            if let Some((prefix, local, namespace)) = foreign::adjust_foreign_attribute(local.as_bytes()) {
+                return QualifiedName { prefix, local: Cow::Borrowed(local), namespace: Some(namespace) };
+            }
+            let adjusted = match namespace {
+                Namespace::Svg => foreign::adjust_svg_attribute_name(local.as_bytes()),
+                _ => foreign::adjust_mathml_attribute_name(local.as_bytes()),
+            };
+            if let Some(adjusted) = adjusted {
+                return QualifiedName { prefix: None, local: Cow::Borrowed(adjusted), namespace: None };
+            }
         }
+        QualifiedName { prefix: None, local, namespace: None }
+    }
+
+    /// The attribute's value with its character references decoded, newlines normalized and
+    /// NULs replaced. Only allocates when one of those changed something.
+    pub fn value<'a>(&self, token: &Token, raw_html: &'a str) -> Cow<'a, str> {
+        let references = self.flags & Flags::CharRefs as u8 != 0;
+        entities::decode_with(self.raw_value(token, raw_html), references, true, true)
+    }
+}
+
+/// An attribute name after adjustment. Only a few attributes of foreign elements have a prefix
+/// and namespace, every other attribute is in no namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QualifiedName<'a> {
+    pub prefix:    Option<&'static str>,
+    pub local:     Cow<'a, str>,
+    pub namespace: Option<Namespace>,
+}
+
+/// A tag or attribute name as the tokenizer would have built it: lowercased, with its NULs
+/// replaced.
+fn normalize_name(name: &str) -> Cow<'_, str> {
+    if name.bytes().any(|byte| byte.is_ascii_uppercase() || byte == b'\0') {
+        Cow::Owned(name.to_ascii_lowercase().replace('\0', "\u{FFFD}"))
+    } else {
+        Cow::Borrowed(name)
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Token {
     pub attributes: Option<Box<[Attribute]>>,
     pub start:        u32,
     pub end:          u32,
-    pub text_off:     u16,
+    pub text_off:     u32,
     pub text_size:    u32,
     pub flags:        u8,
     pub token_id:     TagID,
@@ -33,7 +127,7 @@ pub struct Token {
 
 impl Token {
     pub fn new(attributes: Box<[Attribute]>, 
-               start: u32, end: u32, text_off: u16, text_size: u32,
+               start: u32, end: u32, text_off: u32, text_size: u32,
                token_id: TagID, flags: u8) -> Self {
         Token {
             attributes: Some(attributes),
@@ -46,7 +140,7 @@ impl Token {
         }
     }
 
-    pub fn new_no_attributes(start: u32, end: u32, text_off: u16, text_size: u32,
+    pub fn new_no_attributes(start: u32, end: u32, text_off: u32, text_size: u32,
                              token_id: TagID, flags: u8) -> Self {
         Token {
             attributes: None,
@@ -71,6 +165,56 @@ impl Token {
         }
     }
 
+    /// The namespace of a start or end tag.
+    pub fn namespace(&self) -> Namespace {
+        if self.flags & Flags::Svg as u8 != 0 {
+            Namespace::Svg
+        } else if self.flags & Flags::MathMl as u8 != 0 {
+            Namespace::MathMl
+        } else {
+            Namespace::Html
+        }
+    }
+
+    /// The tag name, lowercased, and with the spec's case adjustments for SVG elements
+    /// (`foreignObject`, `clipPath`...).
+    pub fn name<'a>(&self, raw_html: &'a str) -> Cow<'a, str> {
+        let name = normalize_name(&raw_html[self.start as usize..self.end as usize]);
+        if self.namespace() == Namespace::Svg {
+            if let Some(adjusted) = foreign::adjust_svg_tag_name(name.as_bytes()) {
+                return Cow::Borrowed(adjusted);
+            }
+        }
+        name
+    }
+
+    /// The token's span (text, comment data, or a tag or doctype name) with its character
+    /// references decoded, newlines normalized and NULs replaced, borrowed when none of that
+    /// changed anything. Text only has its NULs replaced when it wasn't in the data state.
+    pub fn text<'a>(&self, raw_html: &'a str) -> Cow<'a, str> {
+        let references = self.flags & Flags::CharRefs as u8 != 0;
+        let nuls = match self.token_id {
+            TagID::Text | TagID::EmTrivia => self.flags & Flags::Nuls as u8 != 0,
+            _ => true,
+        };
+        entities::decode_with(&raw_html[self.start as usize..self.end as usize], references, false, nuls)
+    }
+
+    /// A doctype's public and system identifiers, the ones it has. They're stored as attributes
+    /// named after their keyword (a system identifier right after a public one has no keyword).
+    pub fn doctype_identifiers<'a>(&self, raw_html: &'a str) -> (Option<Cow<'a, str>>, Option<Cow<'a, str>>) {
+        let mut public = None;
+        let mut system = None;
+        for attribute in self.attributes.iter().flat_map(|attributes| attributes.iter()) {
+            if attribute.name(self, raw_html).eq_ignore_ascii_case("public") {
+                public = Some(attribute.value(self, raw_html));
+            } else {
+                system = Some(attribute.value(self, raw_html));
+            }
+        }
+        (public, system)
+    }
+
     pub fn print_self(&self, raw_html: &[u8]) {
         let tag = unsafe {
             let raw_tag = std::slice::from_raw_parts(raw_html.as_ptr().add(self.start as usize), 
@@ -83,28 +227,15 @@ impl Token {
         match &self.attributes {
             None => {},
             Some(attributes) => {
+                let raw_html = unsafe { std::str::from_utf8_unchecked(raw_html) };
                 for attribute in attributes.iter() {
-                    let key = unsafe {
-                        let offset = (self.start as usize) + (attribute.name_begin as usize);
-                        let raw_key = std::slice::from_raw_parts(raw_html.as_ptr().add(offset), 
-                                                                 attribute.name_size as usize);
-                        std::str::from_utf8_unchecked(raw_key)
-                    };
-
-                    println!("Key: {}", key);
+                    println!("Key: {}", attribute.name(self, raw_html));
 
-                    if attribute.value_begin < attribute.name_begin {
+                    if attribute.quoting == Quoting::Missing {
                         continue
                     }
 
-                    let value = unsafe {
-                        let offset = (self.start as usize) + (attribute.value_begin as usize);
-                        let raw_value = std::slice::from_raw_parts(raw_html.as_ptr().add(offset), 
-                                                                   attribute.value_size as usize);
-                        std::str::from_utf8_unchecked(raw_value)
-                    };
-
-                    println!("Value: {}", value)
+                    println!("Value: {}", attribute.value(self, raw_html))
                 }
             }
         }
@@ -120,10 +251,14 @@ pub enum Flags {
     CloseSelf   = 0x02,
     ForceQuirks = 0x04,
     Done        = 0x08,
+    CharRefs    = 0x10, // The span holds character references that still have to be decoded
+    Svg         = 0x20, // Tags in the SVG namespace
+    MathMl      = 0x40, // Tags in the MathML namespace
+    Nuls        = 0x80, // Text whose NULs become U+FFFD (RCDATA, RAWTEXT, script data, PLAINTEXT)
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TagID {
     Undef               = 0x00,
     EndOfFile           = 0x01,
@@ -321,36 +456,400 @@ pub enum TagID {
     Video               = 0xc1,
     Wbr                 = 0xc2,
     Xmp                 = 0xc3,
-    _LastEntry          = 0xc4
+    EmTrivia            = 0xc4, // Markup between tokens, only emitted by a lossless tokenizer
+    _LastEntry          = 0xc5
+}
+
+impl TagID {
+    /// The lowercase name of the tag, for elements that aren't backed by a tag in the input
+    /// (like the `<html>`, `<head>` and `<body>` a parser adds). Empty for `Undef` and the
+    /// token types that aren't tags.
+    pub fn name(self) -> &'static str {
+        match self {
+            TagID::A => "a",
+            TagID::Abbr => "abbr",
+            TagID::Acronym => "acronym",
+            TagID::Address => "address",
+            TagID::AltGlyph => "altglyph",
+            TagID::AltGlyphDef => "altglyphdef",
+            TagID::AltGlyphItem => "altglyphitem",
+            TagID::AnimateColor => "animatecolor",
+            TagID::AnimateMotion => "animatemotion",
+            TagID::AnimateTransform => "animatetransform",
+            TagID::AnnotationXml => "annotation-xml",
+            TagID::Applet => "applet",
+            TagID::Area => "area",
+            TagID::Article => "article",
+            TagID::Aside => "aside",
+            TagID::Audio => "audio",
+            TagID::B => "b",
+            TagID::Base => "base",
+            TagID::BaseFont => "basefont",
+            TagID::Bdi => "bdi",
+            TagID::Bdo => "bdo",
+            TagID::BGSound => "bgsound",
+            TagID::Big => "big",
+            TagID::Blink => "blink",
+            TagID::Blockquote => "blockquote",
+            TagID::Body => "body",
+            TagID::Br => "br",
+            TagID::Button => "button",
+            TagID::Canvas => "canvas",
+            TagID::Caption => "caption",
+            TagID::Center => "center",
+            TagID::Cite => "cite",
+            TagID::ClipPath => "clippath",
+            TagID::Code => "code",
+            TagID::Col => "col",
+            TagID::ColGroup => "colgroup",
+            TagID::Data => "data",
+            TagID::DataList => "datalist",
+            TagID::DD => "dd",
+            TagID::Del => "del",
+            TagID::Desc => "desc",
+            TagID::Details => "details",
+            TagID::Dfn => "dfn",
+            TagID::Dialog => "dialog",
+            TagID::Dir => "dir",
+            TagID::Div => "div",
+            TagID::Dl => "dl",
+            TagID::Dt => "dt",
+            TagID::Em => "em",
+            TagID::Embed => "embed",
+            TagID::Feblend => "feblend",
+            TagID::FeColorMatrix => "fecolormatrix",
+            TagID::FeComponentTransfer => "fecomponenttransfer",
+            TagID::FeComposite => "fecomposite",
+            TagID::FeConvolveMatrix => "feconvolvematrix",
+            TagID::FeDiffuseLighting => "fediffuselighting",
+            TagID::FeDisplacementMap => "fedisplacementmap",
+            TagID::FeDistantLight => "fedistantlight",
+            TagID::FeDropShadow => "fedropshadow",
+            TagID::FeFlood => "feflood",
+            TagID::FeFuncA => "fefunca",
+            TagID::FefuncB => "fefuncb",
+            TagID::FeFuncG => "fefuncg",
+            TagID::FeFuncR => "fefuncr",
+            TagID::FeGaussianBlur => "fegaussianblur",
+            TagID::FeImage => "feimage",
+            TagID::FeMerge => "femerge",
+            TagID::FeMergeNode => "femergenode",
+            TagID::FeMorphology => "femorphology",
+            TagID::FeOffset => "feoffset",
+            TagID::FePointLight => "fepointlight",
+            TagID::FeSpecularLighting => "fespecularlighting",
+            TagID::FeSpotlight => "fespotlight",
+            TagID::FeTile => "fetile",
+            TagID::FeTurbulence => "feturbulence",
+            TagID::FieldSet => "fieldset",
+            TagID::FigCaption => "figcaption",
+            TagID::Figure => "figure",
+            TagID::Font => "font",
+            TagID::Footer => "footer",
+            TagID::ForeignObject => "foreignobject",
+            TagID::Form => "form",
+            TagID::Frame => "frame",
+            TagID::Frameset => "frameset",
+            TagID::Glyphref => "glyphref",
+            TagID::H1 => "h1",
+            TagID::H2 => "h2",
+            TagID::H3 => "h3",
+            TagID::H4 => "h4",
+            TagID::H5 => "h5",
+            TagID::H6 => "h6",
+            TagID::Head => "head",
+            TagID::Header => "header",
+            TagID::Hgroup => "hgroup",
+            TagID::Hr => "hr",
+            TagID::Html => "html",
+            TagID::I => "i",
+            TagID::IFrame => "iframe",
+            TagID::Image => "image",
+            TagID::Img => "img",
+            TagID::Input => "input",
+            TagID::Ins => "ins",
+            TagID::Isindex => "isindex",
+            TagID::Kbd => "kbd",
+            TagID::Keygen => "keygen",
+            TagID::Label => "label",
+            TagID::Legend => "legend",
+            TagID::Li => "li",
+            TagID::LinearGradient => "lineargradient",
+            TagID::Link => "link",
+            TagID::Listing => "listing",
+            TagID::Main => "main",
+            TagID::MAlignMark => "malignmark",
+            TagID::Map => "map",
+            TagID::Mark => "mark",
+            TagID::Marquee => "marquee",
+            TagID::Math => "math",
+            TagID::Menu => "menu",
+            TagID::Meta => "meta",
+            TagID::Meter => "meter",
+            TagID::MFenced => "mfenced",
+            TagID::MGlyph => "mglyph",
+            TagID::Mi => "mi",
+            TagID::Mn => "mn",
+            TagID::Mo => "mo",
+            TagID::Ms => "ms",
+            TagID::MText => "mtext",
+            TagID::MultiCol => "multicol",
+            TagID::Nav => "nav",
+            TagID::NextId => "nextid",
+            TagID::Nobr => "nobr",
+            TagID::NoEmbed => "noembed",
+            TagID::NoFrames => "noframes",
+            TagID::NoScript => "noscript",
+            TagID::Object => "object",
+            TagID::Ol => "ol",
+            TagID::OptGroup => "optgroup",
+            TagID::Option => "option",
+            TagID::Output => "output",
+            TagID::Paragraph => "p",
+            TagID::Param => "param",
+            TagID::Path => "path",
+            TagID::Picture => "picture",
+            TagID::PlainText => "plaintext",
+            TagID::Pre => "pre",
+            TagID::Progress => "progress",
+            TagID::Q => "q",
+            TagID::RadialGradient => "radialgradient",
+            TagID::Rb => "rb",
+            TagID::Rp => "rp",
+            TagID::Rt => "rt",
+            TagID::Rtc => "rtc",
+            TagID::Ruby => "ruby",
+            TagID::S => "s",
+            TagID::Samp => "samp",
+            TagID::Script => "script",
+            TagID::Section => "section",
+            TagID::Select => "select",
+            TagID::Slot => "slot",
+            TagID::Small => "small",
+            TagID::Source => "source",
+            TagID::Spacer => "spacer",
+            TagID::Span => "span",
+            TagID::Strike => "strike",
+            TagID::Strong => "strong",
+            TagID::Style => "style",
+            TagID::Sub => "sub",
+            TagID::Summary => "summary",
+            TagID::Sup => "sup",
+            TagID::Svg => "svg",
+            TagID::Table => "table",
+            TagID::Tbody => "tbody",
+            TagID::Td => "td",
+            TagID::Template => "template",
+            TagID::TextArea => "textarea",
+            TagID::TextPath => "textpath",
+            TagID::TFoot => "tfoot",
+            TagID::Th => "th",
+            TagID::Thead => "thead",
+            TagID::Time => "time",
+            TagID::Title => "title",
+            TagID::Tr => "tr",
+            TagID::Track => "track",
+            TagID::Tt => "tt",
+            TagID::U => "u",
+            TagID::Ul => "ul",
+            TagID::Var => "var",
+            TagID::Video => "video",
+            TagID::Wbr => "wbr",
+            TagID::Xmp => "xmp",
+            _ => "",
+        }
+    }
 }
 
 pub static ASCII_TO_TAG_ID: phf::Map<&'static [u8], TagID> = phf_map! {
     b"a" => TagID::A,
-    b"!DOCTYPE" => TagID::EmDoctype,
-    b"body" => TagID::Body,
-    b"p" => TagID::Paragraph,
-    b"h1" => TagID::H1,
-    b"head" => TagID::Head,
-    b"div" => TagID::Div,
-    b"template" => TagID::Template,
-    b"textarea" => TagID::TextArea,
-    b"title" => TagID::Title,
-    b"script" => TagID::Script,
-    b"style" => TagID::Style,
-    b"base" => TagID::Base,
+    b"abbr" => TagID::Abbr,
+    b"acronym" => TagID::Acronym,
+    b"address" => TagID::Address,
+    b"altglyph" => TagID::AltGlyph,
+    b"altglyphdef" => TagID::AltGlyphDef,
+    b"altglyphitem" => TagID::AltGlyphItem,
+    b"animatecolor" => TagID::AnimateColor,
+    b"animatemotion" => TagID::AnimateMotion,
+    b"animatetransform" => TagID::AnimateTransform,
+    b"annotation-xml" => TagID::AnnotationXml,
+    b"applet" => TagID::Applet,
     b"area" => TagID::Area,
+    b"article" => TagID::Article,
+    b"aside" => TagID::Aside,
+    b"audio" => TagID::Audio,
+    b"b" => TagID::B,
+    b"base" => TagID::Base,
+    b"basefont" => TagID::BaseFont,
+    b"bdi" => TagID::Bdi,
+    b"bdo" => TagID::Bdo,
+    b"bgsound" => TagID::BGSound,
+    b"big" => TagID::Big,
+    b"blink" => TagID::Blink,
+    b"blockquote" => TagID::Blockquote,
+    b"body" => TagID::Body,
     b"br" => TagID::Br,
+    b"button" => TagID::Button,
+    b"canvas" => TagID::Canvas,
+    b"caption" => TagID::Caption,
+    b"center" => TagID::Center,
+    b"cite" => TagID::Cite,
+    b"clippath" => TagID::ClipPath,
+    b"code" => TagID::Code,
     b"col" => TagID::Col,
+    b"colgroup" => TagID::ColGroup,
+    b"data" => TagID::Data,
+    b"datalist" => TagID::DataList,
+    b"dd" => TagID::DD,
+    b"del" => TagID::Del,
+    b"desc" => TagID::Desc,
+    b"details" => TagID::Details,
+    b"dfn" => TagID::Dfn,
+    b"dialog" => TagID::Dialog,
+    b"dir" => TagID::Dir,
+    b"div" => TagID::Div,
+    b"dl" => TagID::Dl,
+    b"dt" => TagID::Dt,
+    b"em" => TagID::Em,
     b"embed" => TagID::Embed,
+    b"feblend" => TagID::Feblend,
+    b"fecolormatrix" => TagID::FeColorMatrix,
+    b"fecomponenttransfer" => TagID::FeComponentTransfer,
+    b"fecomposite" => TagID::FeComposite,
+    b"feconvolvematrix" => TagID::FeConvolveMatrix,
+    b"fediffuselighting" => TagID::FeDiffuseLighting,
+    b"fedisplacementmap" => TagID::FeDisplacementMap,
+    b"fedistantlight" => TagID::FeDistantLight,
+    b"fedropshadow" => TagID::FeDropShadow,
+    b"feflood" => TagID::FeFlood,
+    b"fefunca" => TagID::FeFuncA,
+    b"fefuncb" => TagID::FefuncB,
+    b"fefuncg" => TagID::FeFuncG,
+    b"fefuncr" => TagID::FeFuncR,
+    b"fegaussianblur" => TagID::FeGaussianBlur,
+    b"feimage" => TagID::FeImage,
+    b"femerge" => TagID::FeMerge,
+    b"femergenode" => TagID::FeMergeNode,
+    b"femorphology" => TagID::FeMorphology,
+    b"feoffset" => TagID::FeOffset,
+    b"fepointlight" => TagID::FePointLight,
+    b"fespecularlighting" => TagID::FeSpecularLighting,
+    b"fespotlight" => TagID::FeSpotlight,
+    b"fetile" => TagID::FeTile,
+    b"feturbulence" => TagID::FeTurbulence,
+    b"fieldset" => TagID::FieldSet,
+    b"figcaption" => TagID::FigCaption,
+    b"figure" => TagID::Figure,
+    b"font" => TagID::Font,
+    b"footer" => TagID::Footer,
+    b"foreignobject" => TagID::ForeignObject,
+    b"form" => TagID::Form,
+    b"frame" => TagID::Frame,
+    b"frameset" => TagID::Frameset,
+    b"glyphref" => TagID::Glyphref,
+    b"h1" => TagID::H1,
+    b"h2" => TagID::H2,
+    b"h3" => TagID::H3,
+    b"h4" => TagID::H4,
+    b"h5" => TagID::H5,
+    b"h6" => TagID::H6,
+    b"head" => TagID::Head,
+    b"header" => TagID::Header,
+    b"hgroup" => TagID::Hgroup,
     b"hr" => TagID::Hr,
+    b"html" => TagID::Html,
+    b"i" => TagID::I,
+    b"iframe" => TagID::IFrame,
+    b"image" => TagID::Image,
     b"img" => TagID::Img,
     b"input" => TagID::Input,
+    b"ins" => TagID::Ins,
+    b"isindex" => TagID::Isindex,
+    b"kbd" => TagID::Kbd,
+    b"keygen" => TagID::Keygen,
+    b"label" => TagID::Label,
+    b"legend" => TagID::Legend,
+    b"li" => TagID::Li,
+    b"lineargradient" => TagID::LinearGradient,
     b"link" => TagID::Link,
+    b"listing" => TagID::Listing,
+    b"main" => TagID::Main,
+    b"malignmark" => TagID::MAlignMark,
+    b"map" => TagID::Map,
+    b"mark" => TagID::Mark,
+    b"marquee" => TagID::Marquee,
+    b"math" => TagID::Math,
+    b"menu" => TagID::Menu,
     b"meta" => TagID::Meta,
+    b"meter" => TagID::Meter,
+    b"mfenced" => TagID::MFenced,
+    b"mglyph" => TagID::MGlyph,
+    b"mi" => TagID::Mi,
+    b"mn" => TagID::Mn,
+    b"mo" => TagID::Mo,
+    b"ms" => TagID::Ms,
+    b"mtext" => TagID::MText,
+    b"multicol" => TagID::MultiCol,
+    b"nav" => TagID::Nav,
+    b"nextid" => TagID::NextId,
+    b"nobr" => TagID::Nobr,
+    b"noembed" => TagID::NoEmbed,
+    b"noframes" => TagID::NoFrames,
+    b"noscript" => TagID::NoScript,
+    b"object" => TagID::Object,
+    b"ol" => TagID::Ol,
+    b"optgroup" => TagID::OptGroup,
+    b"option" => TagID::Option,
+    b"output" => TagID::Output,
+    b"p" => TagID::Paragraph,
+    b"param" => TagID::Param,
+    b"path" => TagID::Path,
+    b"picture" => TagID::Picture,
+    b"plaintext" => TagID::PlainText,
+    b"pre" => TagID::Pre,
+    b"progress" => TagID::Progress,
+    b"q" => TagID::Q,
+    b"radialgradient" => TagID::RadialGradient,
+    b"rb" => TagID::Rb,
+    b"rp" => TagID::Rp,
+    b"rt" => TagID::Rt,
+    b"rtc" => TagID::Rtc,
+    b"ruby" => TagID::Ruby,
+    b"s" => TagID::S,
+    b"samp" => TagID::Samp,
+    b"script" => TagID::Script,
+    b"section" => TagID::Section,
+    b"select" => TagID::Select,
+    b"slot" => TagID::Slot,
+    b"small" => TagID::Small,
     b"source" => TagID::Source,
+    b"spacer" => TagID::Spacer,
+    b"span" => TagID::Span,
+    b"strike" => TagID::Strike,
+    b"strong" => TagID::Strong,
+    b"style" => TagID::Style,
+    b"sub" => TagID::Sub,
+    b"summary" => TagID::Summary,
+    b"sup" => TagID::Sup,
+    b"svg" => TagID::Svg,
+    b"table" => TagID::Table,
+    b"tbody" => TagID::Tbody,
+    b"td" => TagID::Td,
+    b"template" => TagID::Template,
+    b"textarea" => TagID::TextArea,
+    b"textpath" => TagID::TextPath,
+    b"tfoot" => TagID::TFoot,
+    b"th" => TagID::Th,
+    b"thead" => TagID::Thead,
+    b"time" => TagID::Time,
+    b"title" => TagID::Title,
+    b"tr" => TagID::Tr,
     b"track" => TagID::Track,
+    b"tt" => TagID::Tt,
+    b"u" => TagID::U,
+    b"ul" => TagID::Ul,
+    b"var" => TagID::Var,
+    b"video" => TagID::Video,
     b"wbr" => TagID::Wbr,
-    b"svg" => TagID::Svg,
-    b"math" => TagID::Math,
-    b"html" => TagID::Html,
+    b"xmp" => TagID::Xmp,
 };
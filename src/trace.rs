@@ -0,0 +1,37 @@
+use crate::errors;
+use crate::tokenizer::State;
+use crate::tokens;
+
+/// Hooks into the tokenizer, mostly useful for debugging it. Every method does nothing by
+/// default, and since the tokenizer is generic over its observer the calls are compiled away
+/// entirely for `NoTrace`.
+pub trait Observer {
+    fn state_transition(&mut self, _from: State, _to: State) {}
+
+    fn token_emitted(&mut self, _token: &tokens::Token, _raw_html: &[u8]) {}
+
+    fn parse_error(&mut self, _error: &errors::ParseError) {}
+}
+
+/// The default observer, which ignores everything.
+pub struct NoTrace;
+
+impl Observer for NoTrace {}
+
+/// Prints every state transition, token and parse error to stdout.
+pub struct PrintTrace;
+
+impl Observer for PrintTrace {
+    fn state_transition(&mut self, from: State, to: State) {
+        println!("State: {:?} -> {:?}", from, to);
+    }
+
+    fn token_emitted(&mut self, token: &tokens::Token, raw_html: &[u8]) {
+        let text = String::from_utf8_lossy(&raw_html[token.start as usize..token.end as usize]);
+        println!("Token: {:?} {:?} (flags {:#04x})", token.token_id, text, token.flags);
+    }
+
+    fn parse_error(&mut self, error: &errors::ParseError) {
+        println!("Error: {} at {}", error.kind.code(), error.position);
+    }
+}
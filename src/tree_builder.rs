@@ -0,0 +1,2394 @@
+// The tree construction stage of the spec: builds a `Document` out of the token stream with the
+// insertion modes, the stack of open elements and the list of active formatting elements.
+//
+// Tokens are pulled one at a time, since what the tokenizer does next (RCDATA after a <title>,
+// CDATA sections in foreign content) depends on the tree built so far.
+
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::errors::{self, ErrorKind};
+use crate::foreign::{self, Namespace};
+use crate::quirks::{self, QuirksMode};
+use crate::tokenizer::{InitialState, Tokenizer};
+use crate::tokens::{Flags, TagID, Token};
+
+pub fn parse(raw_html: &str) -> Document<'_> {
+    let mut builder = TreeBuilder::new(raw_html);
+    builder.run();
+    builder.finish()
+}
+
+/// Parses `raw_html` the way `innerHTML` does, as the contents of a `context` element. The
+/// fragment's nodes end up as the children of the document's root.
+pub fn parse_fragment(raw_html: &str, context: TagID) -> Document<'_> {
+    let mut builder = TreeBuilder::new_fragment(raw_html, context);
+    builder.run();
+    builder.finish()
+}
+
+/// Elements that never have any content, so their start tag is all there is.
+pub fn is_void(tag: TagID) -> bool {
+    matches!(tag, TagID::Area | TagID::Base | TagID::BaseFont | TagID::BGSound | TagID::Br | TagID::Col |
+                  TagID::Embed | TagID::Frame | TagID::Hr | TagID::Img | TagID::Input | TagID::Keygen |
+                  TagID::Link | TagID::Meta | TagID::Param | TagID::Source | TagID::Track | TagID::Wbr)
+}
+
+/// Elements that are closed by the "generate implied end tags" steps.
+fn has_implied_end_tag(tag: TagID) -> bool {
+    matches!(tag, TagID::DD | TagID::Dt | TagID::Li | TagID::OptGroup | TagID::Option | TagID::Paragraph |
+                  TagID::Rb | TagID::Rp | TagID::Rt | TagID::Rtc)
+}
+
+fn has_implied_end_tag_thoroughly(tag: TagID) -> bool {
+    has_implied_end_tag(tag) ||
+    matches!(tag, TagID::Caption | TagID::ColGroup | TagID::Tbody | TagID::Td | TagID::TFoot | TagID::Th |
+                  TagID::Thead | TagID::Tr)
+}
+
+fn is_formatting(tag: TagID) -> bool {
+    matches!(tag, TagID::A | TagID::B | TagID::Big | TagID::Code | TagID::Em | TagID::Font | TagID::I |
+                  TagID::Nobr | TagID::S | TagID::Small | TagID::Strike | TagID::Strong | TagID::Tt | TagID::U)
+}
+
+fn is_heading(tag: TagID) -> bool {
+    matches!(tag, TagID::H1 | TagID::H2 | TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6)
+}
+
+/// The "special" category of the spec, for elements in the HTML namespace.
+fn is_special_html(tag: TagID) -> bool {
+    matches!(tag, TagID::Address | TagID::Applet | TagID::Area | TagID::Article | TagID::Aside | TagID::Base |
+                  TagID::BaseFont | TagID::BGSound | TagID::Blockquote | TagID::Body | TagID::Br | TagID::Button |
+                  TagID::Caption | TagID::Center | TagID::Col | TagID::ColGroup | TagID::DD | TagID::Details |
+                  TagID::Dir | TagID::Div | TagID::Dl | TagID::Dt | TagID::Embed | TagID::FieldSet |
+                  TagID::FigCaption | TagID::Figure | TagID::Footer | TagID::Form | TagID::Frame |
+                  TagID::Frameset | TagID::H1 | TagID::H2 | TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6 |
+                  TagID::Head | TagID::Header | TagID::Hgroup | TagID::Hr | TagID::Html | TagID::IFrame |
+                  TagID::Img | TagID::Input | TagID::Keygen | TagID::Li | TagID::Link | TagID::Listing |
+                  TagID::Main | TagID::Marquee | TagID::Menu | TagID::Meta | TagID::Nav | TagID::NoEmbed |
+                  TagID::NoFrames | TagID::NoScript | TagID::Object | TagID::Ol | TagID::Paragraph |
+                  TagID::Param | TagID::PlainText | TagID::Pre | TagID::Script | TagID::Section |
+                  TagID::Select | TagID::Source | TagID::Style | TagID::Summary | TagID::Table |
+                  TagID::Tbody | TagID::Td | TagID::Template | TagID::TextArea | TagID::TFoot | TagID::Th |
+                  TagID::Thead | TagID::Title | TagID::Tr | TagID::Track | TagID::Ul | TagID::Wbr | TagID::Xmp)
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | 0x0C | b'\r' | b' ')
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// What a token is, as far as the insertion modes care.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Doctype,
+    StartTag(TagID),
+    EndTag(TagID),
+    Text,
+    Comment,
+    EndOfFile,
+}
+
+fn kind(token: &Token) -> Kind {
+    match token.token_id {
+        TagID::EmDoctype => Kind::Doctype,
+        TagID::Text => Kind::Text,
+        TagID::EmComment => Kind::Comment,
+        TagID::EndOfFile => Kind::EndOfFile,
+        tag if token.flags & Flags::Close as u8 != 0 => Kind::EndTag(tag),
+        tag => Kind::StartTag(tag),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Formatting {
+    Marker,
+    Element(NodeId),
+}
+
+pub(crate) struct TreeBuilder<'a> {
+    tokenizer:          Tokenizer<'a>,
+    document:           Document<'a>,
+    errors:             Vec<errors::ParseError>,
+    mode:               InsertionMode,
+    original_mode:      InsertionMode,
+    template_modes:     Vec<InsertionMode>,
+    open_elements:      Vec<NodeId>,
+    active_formatting:  Vec<Formatting>,
+    pending_table_text: Vec<Token>,
+    head:               Option<NodeId>,
+    form:               Option<NodeId>,
+    context:            Option<NodeId>, // The context element when parsing a fragment
+    frameset_ok:        bool,
+    foster_parenting:   bool,
+    ignore_line_feed:   bool,
+    done:               bool,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub fn new(raw_html: &'a str) -> Self {
+        let mut tokenizer = Tokenizer::new(raw_html);
+        tokenizer.set_track_namespaces(false);
+        TreeBuilder {
+            tokenizer,
+            document: Document::new(raw_html),
+            errors: Vec::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            template_modes: Vec::new(),
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            pending_table_text: Vec::new(),
+            head: None,
+            form: None,
+            context: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            ignore_line_feed: false,
+            done: false,
+        }
+    }
+
+    /// A builder for the fragment parsing algorithm. The context element isn't part of the
+    /// tree, the fragment is parsed into an `<html>` element that `finish` takes apart again.
+    pub fn new_fragment(raw_html: &'a str, context: TagID) -> Self {
+        let mut builder = TreeBuilder::new(raw_html);
+        builder.tokenizer.set_state(match context {
+            TagID::Title | TagID::TextArea => InitialState::Rcdata,
+            TagID::Style | TagID::Xmp | TagID::IFrame | TagID::NoEmbed | TagID::NoFrames => InitialState::Rawtext,
+            TagID::Script => InitialState::ScriptData,
+            TagID::PlainText => InitialState::Plaintext,
+            _ => InitialState::Data,
+        });
+
+        let context_token = Token::new_no_attributes(0, 0, 0, 0, context, 0);
+        let context = builder.create_element(context_token, Namespace::Html);
+        builder.context = Some(context);
+        let html = builder.create_element(Token::new_no_attributes(0, 0, 0, 0, TagID::Html, 0), Namespace::Html);
+        builder.document.append(builder.document.root(), html);
+        builder.open_elements.push(html);
+        if builder.is_html(context, TagID::Template) {
+            builder.template_modes.push(InsertionMode::InTemplate);
+        }
+        if builder.is_html(context, TagID::Form) {
+            builder.form = Some(context);
+        }
+        builder.reset_insertion_mode();
+        builder
+    }
+
+    pub fn run(&mut self) {
+        while !self.done {
+            let in_foreign_content = self.adjusted_current_node()
+                                         .is_some_and(|node| self.namespace_of(node) != Namespace::Html);
+            self.tokenizer.set_foreign_content(in_foreign_content);
+            match self.tokenizer.next_token() {
+                Some(token) => self.process(token),
+                None => break,
+            }
+        }
+    }
+
+    /// Hands back the document, with the tokenizer's and the tree builder's parse errors in
+    /// the order they appear in the input.
+    pub fn finish(mut self) -> Document<'a> {
+        let mut errors = std::mem::take(&mut self.tokenizer.errors);
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|error| error.position);
+        self.document.errors = errors;
+        if self.context.is_some() {
+            let root = self.document.root();
+            if let Some(html) = self.document.node(root).first_child {
+                self.document.reparent_children(html, root);
+                self.document.detach(html);
+            }
+        }
+        self.document
+    }
+
+    fn error(&mut self, kind: ErrorKind, token: &Token) {
+        self.errors.push(errors::ParseError::new(kind, token.start));
+    }
+
+    fn raw_html(&self) -> &'a str {
+        self.document.raw_html()
+    }
+
+    // Questions about nodes
+
+    fn element(&self, node: NodeId) -> &Element {
+        self.document.element(node).unwrap()
+    }
+
+    fn namespace_of(&self, node: NodeId) -> Namespace {
+        self.document.element(node).map_or(Namespace::Html, |element| element.namespace)
+    }
+
+    fn is_html(&self, node: NodeId, tag: TagID) -> bool {
+        self.document.node(node).is_element(tag)
+    }
+
+    fn is_html_where(&self, node: NodeId, predicate: impl Fn(TagID) -> bool) -> bool {
+        self.document.element(node).is_some_and(|element| element.namespace == Namespace::Html && predicate(element.tag))
+    }
+
+    fn is_special(&self, node: NodeId) -> bool {
+        let element = self.element(node);
+        match element.namespace {
+            Namespace::Html => is_special_html(element.tag),
+            namespace => foreign::is_html_integration_point(namespace, element.tag) ||
+                         foreign::is_mathml_text_integration_point(namespace, element.tag) ||
+                         (namespace == Namespace::MathMl && element.tag == TagID::AnnotationXml),
+        }
+    }
+
+    /// Whether `node` has the same tag name as `token`. Only tags we don't know the name of
+    /// have to be compared by name.
+    fn same_name(&self, node: NodeId, token: &Token) -> bool {
+        let element = self.element(node);
+        let raw_html = self.raw_html();
+        element.tag == token.token_id &&
+        (element.tag != TagID::Undef ||
+         element.token.name(raw_html).eq_ignore_ascii_case(&raw_html[token.start as usize..token.end as usize]))
+    }
+
+    fn is_mathml_text_integration_point(&self, node: NodeId) -> bool {
+        let element = self.element(node);
+        foreign::is_mathml_text_integration_point(element.namespace, element.tag)
+    }
+
+    fn is_html_integration_point(&self, node: NodeId) -> bool {
+        let element = self.element(node);
+        if element.namespace == Namespace::MathMl && element.tag == TagID::AnnotationXml {
+            return element.attribute(self.raw_html(), "encoding").is_some_and(|encoding| {
+                encoding.eq_ignore_ascii_case("text/html") || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+            });
+        }
+        foreign::is_html_integration_point(element.namespace, element.tag)
+    }
+
+    // The stack of open elements
+
+    fn current_node(&self) -> Option<NodeId> {
+        self.open_elements.last().copied()
+    }
+
+    /// The current node, except that it's the context element while a fragment's `<html>` is
+    /// all that's open.
+    fn adjusted_current_node(&self) -> Option<NodeId> {
+        match self.context {
+            Some(context) if self.open_elements.len() == 1 => Some(context),
+            _ => self.current_node(),
+        }
+    }
+
+    fn current_is(&self, tag: TagID) -> bool {
+        self.current_node().is_some_and(|node| self.is_html(node, tag))
+    }
+
+    fn is_scope_boundary(&self, node: NodeId, scope: Scope) -> bool {
+        let element = self.element(node);
+        let tag = element.tag;
+        match element.namespace {
+            Namespace::Html => match scope {
+                Scope::Select => !matches!(tag, TagID::OptGroup | TagID::Option),
+                Scope::Table => matches!(tag, TagID::Html | TagID::Table | TagID::Template),
+                _ => matches!(tag, TagID::Applet | TagID::Caption | TagID::Html | TagID::Table | TagID::Td |
+                                   TagID::Th | TagID::Marquee | TagID::Object | TagID::Template) ||
+                     (scope == Scope::ListItem && matches!(tag, TagID::Ol | TagID::Ul)) ||
+                     (scope == Scope::Button && tag == TagID::Button),
+            },
+            namespace => match scope {
+                Scope::Select => true,
+                Scope::Table => false,
+                _ => foreign::is_html_integration_point(namespace, tag) ||
+                     foreign::is_mathml_text_integration_point(namespace, tag) ||
+                     (namespace == Namespace::MathMl && tag == TagID::AnnotationXml),
+            },
+        }
+    }
+
+    fn in_scope_where(&self, target: impl Fn(TagID) -> bool, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if self.is_html_where(node, &target) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn in_scope(&self, tag: TagID, scope: Scope) -> bool {
+        self.in_scope_where(|candidate| candidate == tag, scope)
+    }
+
+    fn node_in_scope(&self, target: NodeId, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if node == target {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn has_open(&self, tag: TagID) -> bool {
+        self.open_elements.iter().any(|&node| self.is_html(node, tag))
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        self.open_elements.pop()
+    }
+
+    /// Pops elements until one in the HTML namespace that `predicate` accepts has been popped.
+    fn pop_until_where(&mut self, predicate: impl Fn(TagID) -> bool) {
+        while let Some(node) = self.pop() {
+            if self.is_html_where(node, &predicate) {
+                break;
+            }
+        }
+    }
+
+    fn pop_until(&mut self, tag: TagID) {
+        self.pop_until_where(|candidate| candidate == tag)
+    }
+
+    fn remove_from_stack(&mut self, node: NodeId) {
+        if let Some(index) = self.open_elements.iter().rposition(|&open| open == node) {
+            self.open_elements.remove(index);
+        }
+    }
+
+    fn generate_implied_end_tags_except(&mut self, except: Option<TagID>) {
+        while let Some(node) = self.current_node() {
+            if !self.is_html_where(node, |tag| has_implied_end_tag(tag) && Some(tag) != except) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self) {
+        self.generate_implied_end_tags_except(None)
+    }
+
+    fn generate_implied_end_tags_thoroughly(&mut self) {
+        while let Some(node) = self.current_node() {
+            if !self.is_html_where(node, has_implied_end_tag_thoroughly) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn close_p_element(&mut self, token: &Token) {
+        self.generate_implied_end_tags_except(Some(TagID::Paragraph));
+        if !self.current_is(TagID::Paragraph) {
+            self.error(ErrorKind::UnexpectedEndTag, token);
+        }
+        self.pop_until(TagID::Paragraph);
+    }
+
+    fn close_p_in_button_scope(&mut self, token: &Token) {
+        if self.in_scope(TagID::Paragraph, Scope::Button) {
+            self.close_p_element(token);
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, tags: &[TagID]) {
+        while let Some(node) = self.current_node() {
+            if self.is_html_where(node, |tag| tags.contains(&tag)) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn clear_stack_back_to_table_context(&mut self) {
+        self.clear_stack_back_to(&[TagID::Table, TagID::Template, TagID::Html])
+    }
+
+    fn clear_stack_back_to_table_body_context(&mut self) {
+        self.clear_stack_back_to(&[TagID::Tbody, TagID::TFoot, TagID::Thead, TagID::Template, TagID::Html])
+    }
+
+    fn clear_stack_back_to_table_row_context(&mut self) {
+        self.clear_stack_back_to(&[TagID::Tr, TagID::Template, TagID::Html])
+    }
+
+    // The list of active formatting elements
+
+    /// Adds a formatting element to the list. The Noah's Ark clause keeps at most three
+    /// entries with the same tag, namespace and attributes after the last marker, dropping the
+    /// earliest one.
+    fn push_active_formatting(&mut self, node: NodeId) {
+        let mut same = Vec::new();
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match *entry {
+                Formatting::Marker => break,
+                Formatting::Element(other) if self.same_formatting_element(node, other) => same.push(index),
+                Formatting::Element(_) => {}
+            }
+        }
+        if same.len() >= 3 {
+            self.active_formatting.remove(same[same.len() - 1]);
+        }
+        self.active_formatting.push(Formatting::Element(node));
+    }
+
+    fn same_formatting_element(&self, node: NodeId, other: NodeId) -> bool {
+        let raw_html = self.raw_html();
+        let (node, other) = (self.element(node), self.element(other));
+        if node.tag != other.tag || node.namespace != other.namespace ||
+           node.attributes().count() != other.attributes().count() {
+            return false;
+        }
+        // Duplicate attributes are dropped by the tokenizer, so matching each one is enough
+        node.attributes().all(|attribute| {
+            let name = attribute.name(raw_html);
+            other.attributes().any(|candidate| {
+                candidate.name(raw_html).eq_ignore_ascii_case(name) && candidate.value(raw_html) == attribute.value(raw_html)
+            })
+        })
+    }
+
+    fn insert_marker(&mut self) {
+        self.active_formatting.push(Formatting::Marker);
+    }
+
+    fn clear_active_formatting_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry == Formatting::Marker {
+                break;
+            }
+        }
+    }
+
+    fn remove_active_formatting(&mut self, node: NodeId) {
+        self.active_formatting.retain(|&entry| entry != Formatting::Element(node));
+    }
+
+    /// The last element with `tag` in the list, after the last marker.
+    fn active_formatting_element(&self, tag: TagID) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match *entry {
+                Formatting::Marker => return None,
+                Formatting::Element(node) if self.is_html(node, tag) => return Some(node),
+                Formatting::Element(_) => {}
+            }
+        }
+        None
+    }
+
+    /// Reopens the formatting elements that were closed implicitly, so "<p><b>x<p>y" puts the
+    /// "y" in a new `<b>` too.
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let is_open = |builder: &Self, entry: Formatting| match entry {
+            Formatting::Marker => true,
+            Formatting::Element(node) => builder.open_elements.contains(&node),
+        };
+        let mut index = match self.active_formatting.last() {
+            Some(&entry) if !is_open(self, entry) => self.active_formatting.len() - 1,
+            _ => return,
+        };
+        // Rewind to the entry after the last one that's still open (or a marker)
+        while index > 0 && !is_open(self, self.active_formatting[index - 1]) {
+            index -= 1;
+        }
+        for index in index..self.active_formatting.len() {
+            if let Formatting::Element(node) = self.active_formatting[index] {
+                let element = self.clone_element(node);
+                let place = self.appropriate_place(None);
+                self.insert_node_at(place, element);
+                self.open_elements.push(element);
+                self.active_formatting[index] = Formatting::Element(element);
+            }
+        }
+    }
+
+    /// A new element made from the same start tag as `node`, without its children.
+    fn clone_element(&mut self, node: NodeId) -> NodeId {
+        let element = self.element(node).clone();
+        self.document.create_node(NodeData::Element(element))
+    }
+
+    /// Handles the end tag of a formatting element, which can close elements that were opened
+    /// after it without being closed. Those get split in two so the tree stays a tree, e.g.
+    /// "<b>1<p>2</b>3" becomes `<b>1</b><p><b>2</b>3</p>`.
+    fn adoption_agency(&mut self, token: &Token) {
+        let subject = token.token_id;
+        if let Some(current) = self.current_node() {
+            if self.is_html(current, subject) && !self.active_formatting.contains(&Formatting::Element(current)) {
+                self.pop();
+                return;
+            }
+        }
+
+        for _ in 0..8 {
+            let formatting_element = match self.active_formatting_element(subject) {
+                Some(node) => node,
+                None => return self.any_other_end_tag(token),
+            };
+            let stack_index = match self.open_elements.iter().rposition(|&node| node == formatting_element) {
+                Some(index) => index,
+                None => {
+                    self.error(ErrorKind::UnexpectedEndTag, token);
+                    self.remove_active_formatting(formatting_element);
+                    return;
+                }
+            };
+            if !self.node_in_scope(formatting_element, Scope::Default) {
+                self.error(ErrorKind::UnexpectedEndTag, token);
+                return;
+            }
+            if self.current_node() != Some(formatting_element) {
+                self.error(ErrorKind::UnexpectedEndTag, token);
+            }
+
+            let furthest_block = (stack_index + 1..self.open_elements.len()).find(|&index| {
+                self.is_special(self.open_elements[index])
+            });
+            let furthest_block_index = match furthest_block {
+                Some(index) => index,
+                None => {
+                    self.open_elements.truncate(stack_index);
+                    self.remove_active_formatting(formatting_element);
+                    return;
+                }
+            };
+            let furthest_block = self.open_elements[furthest_block_index];
+            let common_ancestor = self.open_elements[stack_index - 1];
+            let mut bookmark = self.formatting_index(formatting_element).unwrap();
+
+            // Walk up from the furthest block to the formatting element, cloning the formatting
+            // elements in between (the first three of them) and dropping everything else
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut entry = self.formatting_index(node);
+                if inner > 3 {
+                    if let Some(index) = entry {
+                        self.active_formatting.remove(index);
+                        if index < bookmark {
+                            bookmark -= 1;
+                        }
+                        entry = None;
+                    }
+                }
+                let entry = match entry {
+                    Some(index) => index,
+                    None => {
+                        self.open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+                let node = self.clone_element(node);
+                self.active_formatting[entry] = Formatting::Element(node);
+                self.open_elements[node_index] = node;
+                if last_node == furthest_block {
+                    bookmark = entry + 1;
+                }
+                self.document.append(node, last_node);
+                last_node = node;
+            }
+
+            let place = self.appropriate_place(Some(common_ancestor));
+            self.insert_node_at(place, last_node);
+
+            let element = self.clone_element(formatting_element);
+            self.document.reparent_children(furthest_block, element);
+            self.document.append(furthest_block, element);
+
+            let index = self.formatting_index(formatting_element).unwrap();
+            self.active_formatting.remove(index);
+            if index < bookmark {
+                bookmark -= 1;
+            }
+            self.active_formatting.insert(bookmark, Formatting::Element(element));
+
+            self.remove_from_stack(formatting_element);
+            let furthest_block_index = self.open_elements.iter().rposition(|&node| node == furthest_block).unwrap();
+            self.open_elements.insert(furthest_block_index + 1, element);
+        }
+    }
+
+    fn formatting_index(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter().rposition(|&entry| entry == Formatting::Element(node))
+    }
+
+    // Inserting nodes
+
+    /// Where a new node goes: appended to the first node, or before the second if there is
+    /// one.
+    fn appropriate_place(&self, override_target: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+        let (parent, before) = self.adjusted_place(override_target);
+        // What goes in a template goes in its contents
+        match self.document.template_contents(parent) {
+            Some(contents) => (contents, None),
+            None => (parent, before),
+        }
+    }
+
+    fn adjusted_place(&self, override_target: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+        let target = override_target.or(self.current_node()).unwrap_or(self.document.root());
+        if !self.foster_parenting ||
+           !self.is_html_where(target, |tag| matches!(tag, TagID::Table | TagID::Tbody | TagID::TFoot |
+                                                           TagID::Thead | TagID::Tr)) {
+            return (target, None);
+        }
+
+        // Foster parenting: content that can't go in a table goes right before it instead
+        let last = |tag| self.open_elements.iter().rposition(|&node| self.is_html(node, tag));
+        let (last_template, last_table) = (last(TagID::Template), last(TagID::Table));
+        match (last_template, last_table) {
+            (Some(template), table) if table.is_none_or(|table| template > table) => {
+                (self.open_elements[template], None)
+            }
+            (_, None) => (self.open_elements[0], None),
+            (_, Some(table)) => {
+                let table_node = self.open_elements[table];
+                match self.document.parent(table_node) {
+                    Some(parent) => (parent, Some(table_node)),
+                    None => (self.open_elements[table - 1], None),
+                }
+            }
+        }
+    }
+
+    fn insert_node_at(&mut self, place: (NodeId, Option<NodeId>), node: NodeId) {
+        match place {
+            (_, Some(before)) => self.document.insert_before(before, node),
+            (parent, None) => self.document.append(parent, node),
+        }
+    }
+
+    fn create_element(&mut self, mut token: Token, namespace: Namespace) -> NodeId {
+        // Foreign tokens carry their namespace, so their names and attributes get adjusted
+        match namespace {
+            Namespace::Svg => token.flags |= Flags::Svg as u8,
+            Namespace::MathMl => token.flags |= Flags::MathMl as u8,
+            _ => {}
+        }
+        let tag = token.token_id;
+        let element = self.document.create_node(NodeData::Element(Element::new(tag, namespace, token)));
+        if tag == TagID::Template && namespace == Namespace::Html {
+            let contents = self.document.create_node(NodeData::TemplateContents(element));
+            if let NodeData::Element(element) = &mut self.document.node_mut(element).data {
+                element.contents = Some(contents);
+            }
+        }
+        element
+    }
+
+    fn insert_foreign_element(&mut self, token: Token, namespace: Namespace) -> NodeId {
+        let place = self.appropriate_place(None);
+        let element = self.create_element(token, namespace);
+        self.insert_node_at(place, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    fn insert_html_element(&mut self, token: Token) -> NodeId {
+        self.insert_foreign_element(token, Namespace::Html)
+    }
+
+    /// Inserts an element the markup implies but doesn't have a tag for, like a `<tbody>`
+    /// around a `<tr>`.
+    fn insert_implied_element(&mut self, tag: TagID, at: &Token) -> NodeId {
+        let token = Token::new_no_attributes(at.start, at.start, 0, 0, tag, 0);
+        self.insert_html_element(token)
+    }
+
+    fn insert_comment(&mut self, token: Token, parent: Option<NodeId>) {
+        let place = match parent {
+            Some(parent) => (parent, None),
+            None => self.appropriate_place(None),
+        };
+        let comment = self.document.create_node(NodeData::Comment(token));
+        self.insert_node_at(place, comment);
+    }
+
+    fn insert_text(&mut self, token: Token) {
+        if token.start == token.end {
+            return;
+        }
+        let (parent, before) = self.appropriate_place(None);
+        if parent == self.document.root() {
+            return;
+        }
+        match before {
+            Some(before) => self.document.insert_text_before(before, token),
+            None => self.document.append_text(parent, token),
+        }
+    }
+
+    /// Adds the attributes of `token` that `node` doesn't have yet (a second `<html>` or
+    /// `<body>` tag).
+    fn merge_attributes(&mut self, node: NodeId, token: &Token) {
+        let raw_html = self.raw_html();
+        let element = self.element(node);
+        let new: Vec<_> = token.attributes.iter().flat_map(|attributes| attributes.iter()).filter(|attribute| {
+            !element.has_attribute(raw_html, attribute.name(token, raw_html))
+        }).cloned().collect();
+        if new.is_empty() {
+            return;
+        }
+        let mut merged = token.clone();
+        merged.attributes = Some(new.into_boxed_slice());
+        if let NodeData::Element(element) = &mut self.document.node_mut(node).data {
+            element.merged.push(merged);
+        }
+    }
+
+    /// The generic raw text and RCDATA element parsing algorithms.
+    fn parse_text_element(&mut self, token: Token, state: InitialState) {
+        self.insert_html_element(token);
+        self.tokenizer.set_state(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // Text
+
+    fn sub_token(token: &Token, start: usize, end: usize) -> Token {
+        Token::new_no_attributes(start as u32, end as u32, 0, 0, TagID::Text, token.flags)
+    }
+
+    /// Splits a text token into its leading whitespace and the rest.
+    fn split_whitespace(&self, token: &Token) -> (Option<Token>, Option<Token>) {
+        let bytes = self.raw_html().as_bytes();
+        let (start, end) = (token.start as usize, token.end as usize);
+        let split = bytes[start..end].iter().position(|&byte| !is_whitespace(byte)).map_or(end, |offset| start + offset);
+        let whitespace = (split > start).then(|| Self::sub_token(token, start, split));
+        let rest = (split < end).then(|| Self::sub_token(token, split, end));
+        (whitespace, rest)
+    }
+
+    /// The runs of whitespace in a text token, for modes that drop everything else.
+    fn whitespace_runs(&self, token: &Token) -> Vec<Token> {
+        let bytes = self.raw_html().as_bytes();
+        let mut runs = Vec::new();
+        let mut run_start = None;
+        let start = token.start as usize;
+        for (position, &byte) in bytes[start..token.end as usize].iter().enumerate() {
+            let position = start + position;
+            match (is_whitespace(byte), run_start) {
+                (true, None) => run_start = Some(position),
+                (false, Some(start)) => {
+                    runs.push(Self::sub_token(token, start, position));
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run_start {
+            runs.push(Self::sub_token(token, start, token.end as usize));
+        }
+        runs
+    }
+
+    /// Splits a text token around its NULs, which most modes drop.
+    fn split_nuls(&mut self, token: Token) -> Vec<Token> {
+        let bytes = self.raw_html().as_bytes();
+        let (start, end) = (token.start as usize, token.end as usize);
+        if !bytes[start..end].contains(&0) {
+            return vec![token];
+        }
+        self.error(ErrorKind::UnexpectedNullCharacter, &token);
+        bytes[start..end].split(|&byte| byte == 0)
+                         .scan(start, |position, piece| {
+                             let piece_start = *position;
+                             *position += piece.len() + 1;
+                             Some((piece_start, piece_start + piece.len()))
+                         })
+                         .filter(|(piece_start, piece_end)| piece_end > piece_start)
+                         .map(|(piece_start, piece_end)| Self::sub_token(&token, piece_start, piece_end))
+                         .collect()
+    }
+
+    fn is_all_whitespace(&self, token: &Token) -> bool {
+        self.raw_html().as_bytes()[token.start as usize..token.end as usize].iter().all(|&byte| is_whitespace(byte))
+    }
+
+    // Dispatching
+
+    fn process(&mut self, mut token: Token) {
+        if self.ignore_line_feed {
+            self.ignore_line_feed = false;
+            if token.token_id == TagID::Text {
+                let bytes = self.raw_html().as_bytes();
+                let start = token.start as usize;
+                match bytes.get(start) {
+                    Some(b'\n') => token.start += 1,
+                    Some(b'\r') => token.start += if bytes.get(start + 1) == Some(&b'\n') { 2 } else { 1 },
+                    _ => {}
+                }
+                if token.start >= token.end {
+                    return;
+                }
+            }
+        }
+        if token.token_id == TagID::EmTrivia {
+            return;
+        }
+
+        let mut next = Some(token);
+        while let Some(token) = next.take() {
+            next = if self.use_html_rules(&token) {
+                self.check_self_closing(&token);
+                self.process_in(self.mode, token)
+            } else {
+                self.in_foreign_content(token)
+            };
+        }
+    }
+
+    /// The tree construction dispatcher: whether a token goes through the insertion modes or
+    /// the rules for foreign content.
+    fn use_html_rules(&self, token: &Token) -> bool {
+        let node = match self.adjusted_current_node() {
+            Some(node) => node,
+            None => return true,
+        };
+        if self.namespace_of(node) == Namespace::Html {
+            return true;
+        }
+        match kind(token) {
+            Kind::EndOfFile => true,
+            Kind::StartTag(tag) if self.is_mathml_text_integration_point(node) => {
+                !matches!(tag, TagID::MGlyph | TagID::MAlignMark)
+            }
+            Kind::Text if self.is_mathml_text_integration_point(node) => true,
+            Kind::StartTag(TagID::Svg) if self.element(node).namespace == Namespace::MathMl &&
+                                          self.element(node).tag == TagID::AnnotationXml => true,
+            Kind::StartTag(_) | Kind::Text => self.is_html_integration_point(node),
+            _ => false,
+        }
+    }
+
+    /// Reports a self-closing start tag that the spec doesn't acknowledge, which is every one
+    /// but void elements and foreign elements.
+    fn check_self_closing(&mut self, token: &Token) {
+        if let Kind::StartTag(tag) = kind(token) {
+            if token.flags & Flags::CloseSelf as u8 != 0 && !is_void(tag) &&
+               !matches!(tag, TagID::Svg | TagID::Math | TagID::Frame) {
+                self.error(ErrorKind::NonVoidHtmlElementStartTagWithTrailingSolidus, token);
+            }
+        }
+    }
+
+    /// Processes a token in `mode`. Returns the token if it has to be reprocessed (in whatever
+    /// the mode is by then).
+    fn process_in(&mut self, mode: InsertionMode, token: Token) -> Option<Token> {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    /// Handles the whitespace at the start of a text token with `whitespace`, and returns the
+    /// rest of it, if any, for the mode's "anything else" branch.
+    fn leading_whitespace(&mut self, token: Token, whitespace: impl FnOnce(&mut Self, Token)) -> Option<Token> {
+        let (leading, rest) = self.split_whitespace(&token);
+        if let Some(leading) = leading {
+            whitespace(self, leading);
+        }
+        rest
+    }
+
+    fn stop(&mut self) -> Option<Token> {
+        self.done = true;
+        None
+    }
+
+    // The insertion modes
+
+    fn initial(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, |_, _| {})?,
+            Kind::Comment => {
+                self.insert_comment(token, Some(self.document.root()));
+                return None;
+            }
+            Kind::Doctype => {
+                self.document.quirks_mode = quirks::from_doctype(&token, self.raw_html());
+                let doctype = self.document.create_node(NodeData::Doctype(token));
+                self.document.append(self.document.root(), doctype);
+                self.mode = InsertionMode::BeforeHtml;
+                return None;
+            }
+            _ => token,
+        };
+        self.error(ErrorKind::MissingDoctype, &token);
+        self.document.quirks_mode = QuirksMode::Quirks;
+        self.mode = InsertionMode::BeforeHtml;
+        Some(token)
+    }
+
+    fn before_html(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::Comment => {
+                self.insert_comment(token, Some(self.document.root()));
+                return None;
+            }
+            Kind::Text => self.leading_whitespace(token, |_, _| {})?,
+            Kind::StartTag(TagID::Html) => {
+                self.insert_html_element(token);
+                self.mode = InsertionMode::BeforeHead;
+                return None;
+            }
+            Kind::EndTag(TagID::Head | TagID::Body | TagID::Html | TagID::Br) => token,
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            _ => token,
+        };
+        self.insert_implied_element(TagID::Html, &token);
+        self.mode = InsertionMode::BeforeHead;
+        Some(token)
+    }
+
+    fn before_head(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, |_, _| {})?,
+            Kind::Comment => {
+                self.insert_comment(token, None);
+                return None;
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Head) => {
+                self.head = Some(self.insert_html_element(token));
+                self.mode = InsertionMode::InHead;
+                return None;
+            }
+            Kind::EndTag(TagID::Head | TagID::Body | TagID::Html | TagID::Br) => token,
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            _ => token,
+        };
+        self.head = Some(self.insert_implied_element(TagID::Head, &token));
+        self.mode = InsertionMode::InHead;
+        Some(token)
+    }
+
+    fn in_head(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, Self::insert_text)?,
+            Kind::Comment => {
+                self.insert_comment(token, None);
+                return None;
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Base | TagID::BaseFont | TagID::BGSound | TagID::Link | TagID::Meta) => {
+                self.insert_html_element(token);
+                self.pop();
+                return None;
+            }
+            Kind::StartTag(TagID::Title) => {
+                self.parse_text_element(token, InitialState::Rcdata);
+                return None;
+            }
+            Kind::StartTag(TagID::NoFrames | TagID::Style) => {
+                self.parse_text_element(token, InitialState::Rawtext);
+                return None;
+            }
+            Kind::StartTag(TagID::NoScript) => {
+                // Scripting is off, so <noscript> is parsed as markup
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InHeadNoscript;
+                return None;
+            }
+            Kind::StartTag(TagID::Script) => {
+                self.parse_text_element(token, InitialState::ScriptData);
+                return None;
+            }
+            Kind::EndTag(TagID::Head) => {
+                self.pop();
+                self.mode = InsertionMode::AfterHead;
+                return None;
+            }
+            Kind::EndTag(TagID::Body | TagID::Html | TagID::Br) => token,
+            Kind::StartTag(TagID::Template) => {
+                self.insert_html_element(token);
+                self.insert_marker();
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTemplate;
+                self.template_modes.push(InsertionMode::InTemplate);
+                return None;
+            }
+            Kind::EndTag(TagID::Template) => {
+                if !self.has_open(TagID::Template) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags_thoroughly();
+                if !self.current_is(TagID::Template) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until(TagID::Template);
+                self.clear_active_formatting_to_last_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                return None;
+            }
+            Kind::StartTag(TagID::Head) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                return None;
+            }
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            _ => token,
+        };
+        self.pop();
+        self.mode = InsertionMode::AfterHead;
+        Some(token)
+    }
+
+    fn in_head_noscript(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::EndTag(TagID::NoScript) => {
+                self.pop();
+                self.mode = InsertionMode::InHead;
+                return None;
+            }
+            Kind::Text => self.leading_whitespace(token, |builder, whitespace| { builder.in_head(whitespace); })?,
+            Kind::Comment |
+            Kind::StartTag(TagID::BaseFont | TagID::BGSound | TagID::Link | TagID::Meta | TagID::NoFrames | TagID::Style) => {
+                return self.in_head(token);
+            }
+            Kind::EndTag(TagID::Br) => token,
+            Kind::StartTag(TagID::Head | TagID::NoScript) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                return None;
+            }
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            _ => token,
+        };
+        self.error(ErrorKind::UnexpectedStartTag, &token);
+        self.pop();
+        self.mode = InsertionMode::InHead;
+        Some(token)
+    }
+
+    fn after_head(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, Self::insert_text)?,
+            Kind::Comment => {
+                self.insert_comment(token, None);
+                return None;
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Body) => {
+                self.insert_html_element(token);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InBody;
+                return None;
+            }
+            Kind::StartTag(TagID::Frameset) => {
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InFrameset;
+                return None;
+            }
+            Kind::StartTag(TagID::Base | TagID::BaseFont | TagID::BGSound | TagID::Link | TagID::Meta |
+                           TagID::NoFrames | TagID::Script | TagID::Style | TagID::Template | TagID::Title) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                let head = self.head?;
+                self.open_elements.push(head);
+                let reprocess = self.in_head(token);
+                self.remove_from_stack(head);
+                return reprocess;
+            }
+            Kind::EndTag(TagID::Template) => return self.in_head(token),
+            Kind::EndTag(TagID::Body | TagID::Html | TagID::Br) => token,
+            Kind::StartTag(TagID::Head) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                return None;
+            }
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            _ => token,
+        };
+        self.insert_implied_element(TagID::Body, &token);
+        self.mode = InsertionMode::InBody;
+        Some(token)
+    }
+
+    fn in_body(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                for piece in self.split_nuls(token) {
+                    self.reconstruct_active_formatting_elements();
+                    if !self.is_all_whitespace(&piece) {
+                        self.frameset_ok = false;
+                    }
+                    self.insert_text(piece);
+                }
+                None
+            }
+            Kind::Comment => {
+                self.insert_comment(token, None);
+                None
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                None
+            }
+            Kind::StartTag(tag) => self.in_body_start_tag(tag, token),
+            Kind::EndTag(tag) => self.in_body_end_tag(tag, token),
+            Kind::EndOfFile => {
+                if !self.template_modes.is_empty() {
+                    return self.in_template(token);
+                }
+                let unclosed = self.open_elements.iter().any(|&node| {
+                    !self.is_html_where(node, |tag| has_implied_end_tag_thoroughly(tag) ||
+                                                    matches!(tag, TagID::Body | TagID::Html))
+                });
+                if unclosed {
+                    self.error(ErrorKind::EofWithOpenElements, &token);
+                }
+                self.stop()
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: TagID, mut token: Token) -> Option<Token> {
+        match tag {
+            TagID::Html => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                if !self.has_open(TagID::Template) {
+                    if let Some(&html) = self.open_elements.first() {
+                        self.merge_attributes(html, &token);
+                    }
+                }
+            }
+            TagID::Base | TagID::BaseFont | TagID::BGSound | TagID::Link | TagID::Meta | TagID::NoFrames |
+            TagID::Script | TagID::Style | TagID::Template | TagID::Title => return self.in_head(token),
+            TagID::Body => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                let body = self.open_elements.get(1).copied().filter(|&node| self.is_html(node, TagID::Body));
+                if let Some(body) = body {
+                    if !self.has_open(TagID::Template) {
+                        self.frameset_ok = false;
+                        self.merge_attributes(body, &token);
+                    }
+                }
+            }
+            TagID::Frameset => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                let body = self.open_elements.get(1).copied().filter(|&node| self.is_html(node, TagID::Body));
+                if let (Some(body), true) = (body, self.frameset_ok) {
+                    self.document.detach(body);
+                    self.open_elements.truncate(1);
+                    self.insert_html_element(token);
+                    self.mode = InsertionMode::InFrameset;
+                }
+            }
+            TagID::Address | TagID::Article | TagID::Aside | TagID::Blockquote | TagID::Center | TagID::Details |
+            TagID::Dialog | TagID::Dir | TagID::Div | TagID::Dl | TagID::FieldSet | TagID::FigCaption |
+            TagID::Figure | TagID::Footer | TagID::Header | TagID::Hgroup | TagID::Main | TagID::Menu |
+            TagID::Nav | TagID::Ol | TagID::Paragraph | TagID::Section | TagID::Summary | TagID::Ul => {
+                self.close_p_in_button_scope(&token);
+                self.insert_html_element(token);
+            }
+            TagID::H1 | TagID::H2 | TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6 => {
+                self.close_p_in_button_scope(&token);
+                if self.current_node().is_some_and(|node| self.is_html_where(node, is_heading)) {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    self.pop();
+                }
+                self.insert_html_element(token);
+            }
+            TagID::Pre | TagID::Listing => {
+                self.close_p_in_button_scope(&token);
+                self.insert_html_element(token);
+                self.ignore_line_feed = true;
+                self.frameset_ok = false;
+            }
+            TagID::Form => {
+                let has_template = self.has_open(TagID::Template);
+                if self.form.is_some() && !has_template {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    return None;
+                }
+                self.close_p_in_button_scope(&token);
+                let form = self.insert_html_element(token);
+                if !has_template {
+                    self.form = Some(form);
+                }
+            }
+            TagID::Li | TagID::DD | TagID::Dt => {
+                self.frameset_ok = false;
+                let closes = |candidate: TagID| match tag {
+                    TagID::Li => candidate == TagID::Li,
+                    _ => matches!(candidate, TagID::DD | TagID::Dt),
+                };
+                for index in (0..self.open_elements.len()).rev() {
+                    let node = self.open_elements[index];
+                    if let Some(closed) = self.document.element(node).map(|element| element.tag).filter(|&candidate| {
+                        self.namespace_of(node) == Namespace::Html && closes(candidate)
+                    }) {
+                        self.generate_implied_end_tags_except(Some(closed));
+                        if !self.current_is(closed) {
+                            self.error(ErrorKind::UnexpectedStartTag, &token);
+                        }
+                        self.pop_until(closed);
+                        break;
+                    }
+                    if self.is_special(node) && !self.is_html_where(node, |candidate| {
+                        matches!(candidate, TagID::Address | TagID::Div | TagID::Paragraph)
+                    }) {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope(&token);
+                self.insert_html_element(token);
+            }
+            TagID::PlainText => {
+                self.close_p_in_button_scope(&token);
+                self.insert_html_element(token);
+                self.tokenizer.set_state(InitialState::Plaintext);
+            }
+            TagID::Button => {
+                if self.in_scope(TagID::Button, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    self.generate_implied_end_tags();
+                    self.pop_until(TagID::Button);
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+                self.frameset_ok = false;
+            }
+            TagID::A => {
+                if let Some(a) = self.active_formatting_element(TagID::A) {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    let end_tag = Token::new_no_attributes(token.start, token.end, 0, 0, TagID::A, Flags::Close as u8);
+                    self.adoption_agency(&end_tag);
+                    self.remove_active_formatting(a);
+                    self.remove_from_stack(a);
+                }
+                self.reconstruct_active_formatting_elements();
+                let element = self.insert_html_element(token);
+                self.push_active_formatting(element);
+            }
+            TagID::B | TagID::Big | TagID::Code | TagID::Em | TagID::Font | TagID::I | TagID::S | TagID::Small |
+            TagID::Strike | TagID::Strong | TagID::Tt | TagID::U => {
+                self.reconstruct_active_formatting_elements();
+                let element = self.insert_html_element(token);
+                self.push_active_formatting(element);
+            }
+            TagID::Nobr => {
+                self.reconstruct_active_formatting_elements();
+                if self.in_scope(TagID::Nobr, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    let end_tag = Token::new_no_attributes(token.start, token.end, 0, 0, TagID::Nobr, Flags::Close as u8);
+                    self.adoption_agency(&end_tag);
+                    self.reconstruct_active_formatting_elements();
+                }
+                let element = self.insert_html_element(token);
+                self.push_active_formatting(element);
+            }
+            TagID::Applet | TagID::Marquee | TagID::Object => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+                self.insert_marker();
+                self.frameset_ok = false;
+            }
+            TagID::Table => {
+                // Quirks mode keeps the old behavior of tables not closing paragraphs
+                if self.document.quirks_mode != QuirksMode::Quirks {
+                    self.close_p_in_button_scope(&token);
+                }
+                self.insert_html_element(token);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            TagID::Area | TagID::Br | TagID::Embed | TagID::Img | TagID::Keygen | TagID::Wbr => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            TagID::Input => {
+                self.reconstruct_active_formatting_elements();
+                let input = self.insert_html_element(token);
+                self.pop();
+                let hidden = self.element(input).attribute(self.raw_html(), "type")
+                                                .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden"));
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            TagID::Param | TagID::Source | TagID::Track => {
+                self.insert_html_element(token);
+                self.pop();
+            }
+            TagID::Hr => {
+                self.close_p_in_button_scope(&token);
+                self.insert_html_element(token);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            TagID::Image => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                token.token_id = TagID::Img;
+                return Some(token);
+            }
+            TagID::TextArea => {
+                self.insert_html_element(token);
+                self.ignore_line_feed = true;
+                self.tokenizer.set_state(InitialState::Rcdata);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            TagID::Xmp => {
+                self.close_p_in_button_scope(&token);
+                self.reconstruct_active_formatting_elements();
+                self.frameset_ok = false;
+                self.parse_text_element(token, InitialState::Rawtext);
+            }
+            TagID::IFrame => {
+                self.frameset_ok = false;
+                self.parse_text_element(token, InitialState::Rawtext);
+            }
+            TagID::NoEmbed => self.parse_text_element(token, InitialState::Rawtext),
+            TagID::Select => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable | InsertionMode::InCaption | InsertionMode::InTableBody |
+                    InsertionMode::InRow | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            TagID::OptGroup | TagID::Option => {
+                if self.current_is(TagID::Option) {
+                    self.pop();
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+            }
+            TagID::Rb | TagID::Rtc => {
+                if self.in_scope(TagID::Ruby, Scope::Default) {
+                    self.generate_implied_end_tags();
+                    if !self.current_is(TagID::Ruby) {
+                        self.error(ErrorKind::UnexpectedStartTag, &token);
+                    }
+                }
+                self.insert_html_element(token);
+            }
+            TagID::Rp | TagID::Rt => {
+                if self.in_scope(TagID::Ruby, Scope::Default) {
+                    self.generate_implied_end_tags_except(Some(TagID::Rtc));
+                    if !self.current_is(TagID::Ruby) && !self.current_is(TagID::Rtc) {
+                        self.error(ErrorKind::UnexpectedStartTag, &token);
+                    }
+                }
+                self.insert_html_element(token);
+            }
+            TagID::Math | TagID::Svg => {
+                self.reconstruct_active_formatting_elements();
+                let self_closing = token.flags & Flags::CloseSelf as u8 != 0;
+                let namespace = if tag == TagID::Math { Namespace::MathMl } else { Namespace::Svg };
+                self.insert_foreign_element(token, namespace);
+                if self_closing {
+                    self.pop();
+                }
+            }
+            TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Frame | TagID::Head | TagID::Tbody |
+            TagID::Td | TagID::TFoot | TagID::Th | TagID::Thead | TagID::Tr => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+            }
+            _ => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(token);
+            }
+        }
+        None
+    }
+
+    fn in_body_end_tag(&mut self, tag: TagID, token: Token) -> Option<Token> {
+        match tag {
+            TagID::Template => return self.in_head(token),
+            TagID::Body | TagID::Html => {
+                if !self.in_scope(TagID::Body, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.mode = InsertionMode::AfterBody;
+                if tag == TagID::Html {
+                    return Some(token);
+                }
+            }
+            TagID::Address | TagID::Article | TagID::Aside | TagID::Blockquote | TagID::Button | TagID::Center |
+            TagID::Details | TagID::Dialog | TagID::Dir | TagID::Div | TagID::Dl | TagID::FieldSet |
+            TagID::FigCaption | TagID::Figure | TagID::Footer | TagID::Header | TagID::Hgroup | TagID::Listing |
+            TagID::Main | TagID::Menu | TagID::Nav | TagID::Ol | TagID::Pre | TagID::Section | TagID::Summary |
+            TagID::Ul => {
+                if !self.in_scope(tag, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags();
+                if !self.current_is(tag) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until(tag);
+            }
+            TagID::Form => {
+                if self.has_open(TagID::Template) {
+                    if !self.in_scope(TagID::Form, Scope::Default) {
+                        self.error(ErrorKind::UnexpectedEndTag, &token);
+                        return None;
+                    }
+                    self.generate_implied_end_tags();
+                    if !self.current_is(TagID::Form) {
+                        self.error(ErrorKind::UnexpectedEndTag, &token);
+                    }
+                    self.pop_until(TagID::Form);
+                } else {
+                    let form = self.form.take();
+                    let form = match form {
+                        Some(form) if self.node_in_scope(form, Scope::Default) => form,
+                        _ => {
+                            self.error(ErrorKind::UnexpectedEndTag, &token);
+                            return None;
+                        }
+                    };
+                    self.generate_implied_end_tags();
+                    if self.current_node() != Some(form) {
+                        self.error(ErrorKind::UnexpectedEndTag, &token);
+                    }
+                    self.remove_from_stack(form);
+                }
+            }
+            TagID::Paragraph => {
+                if !self.in_scope(TagID::Paragraph, Scope::Button) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    self.insert_implied_element(TagID::Paragraph, &token);
+                }
+                self.close_p_element(&token);
+            }
+            TagID::Li | TagID::DD | TagID::Dt => {
+                let scope = if tag == TagID::Li { Scope::ListItem } else { Scope::Default };
+                if !self.in_scope(tag, scope) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags_except(Some(tag));
+                if !self.current_is(tag) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until(tag);
+            }
+            TagID::H1 | TagID::H2 | TagID::H3 | TagID::H4 | TagID::H5 | TagID::H6 => {
+                if !self.in_scope_where(is_heading, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags();
+                if !self.current_is(tag) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until_where(is_heading);
+            }
+            _ if is_formatting(tag) => self.adoption_agency(&token),
+            TagID::Applet | TagID::Marquee | TagID::Object => {
+                if !self.in_scope(tag, Scope::Default) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags();
+                if !self.current_is(tag) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until(tag);
+                self.clear_active_formatting_to_last_marker();
+            }
+            TagID::Br => {
+                // </br> is treated as <br>
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(Token::new_no_attributes(token.start, token.end, 0, 0, TagID::Br, 0));
+                self.pop();
+                self.frameset_ok = false;
+            }
+            _ => self.any_other_end_tag(&token),
+        }
+        None
+    }
+
+    fn any_other_end_tag(&mut self, token: &Token) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if self.namespace_of(node) == Namespace::Html && self.same_name(node, token) {
+                self.generate_implied_end_tags_except(Some(token.token_id).filter(|&tag| tag != TagID::Undef));
+                if self.current_node() != Some(node) {
+                    self.error(ErrorKind::UnexpectedEndTag, token);
+                }
+                self.open_elements.truncate(index);
+                return;
+            }
+            if self.is_special(node) {
+                self.error(ErrorKind::UnexpectedEndTag, token);
+                return;
+            }
+        }
+    }
+
+    fn text(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                self.insert_text(token);
+                None
+            }
+            Kind::EndOfFile => {
+                self.error(ErrorKind::EofWithOpenElements, &token);
+                self.pop();
+                self.mode = self.original_mode;
+                Some(token)
+            }
+            _ => {
+                self.pop();
+                self.mode = self.original_mode;
+                None
+            }
+        }
+    }
+
+    fn in_table(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text if self.current_node().is_some_and(|node| {
+                self.is_html_where(node, |tag| matches!(tag, TagID::Table | TagID::Tbody | TagID::Template |
+                                                             TagID::TFoot | TagID::Thead | TagID::Tr))
+            }) => {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = InsertionMode::InTableText;
+                return Some(token);
+            }
+            Kind::Comment => self.insert_comment(token, None),
+            Kind::Doctype => self.error(ErrorKind::UnexpectedDoctype, &token),
+            Kind::StartTag(TagID::Caption) => {
+                self.clear_stack_back_to_table_context();
+                self.insert_marker();
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InCaption;
+            }
+            Kind::StartTag(TagID::ColGroup) => {
+                self.clear_stack_back_to_table_context();
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InColumnGroup;
+            }
+            Kind::StartTag(TagID::Col) => {
+                self.clear_stack_back_to_table_context();
+                self.insert_implied_element(TagID::ColGroup, &token);
+                self.mode = InsertionMode::InColumnGroup;
+                return Some(token);
+            }
+            Kind::StartTag(TagID::Tbody | TagID::TFoot | TagID::Thead) => {
+                self.clear_stack_back_to_table_context();
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InTableBody;
+            }
+            Kind::StartTag(TagID::Td | TagID::Th | TagID::Tr) => {
+                self.clear_stack_back_to_table_context();
+                self.insert_implied_element(TagID::Tbody, &token);
+                self.mode = InsertionMode::InTableBody;
+                return Some(token);
+            }
+            Kind::StartTag(TagID::Table) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                if self.in_scope(TagID::Table, Scope::Table) {
+                    self.pop_until(TagID::Table);
+                    self.reset_insertion_mode();
+                    return Some(token);
+                }
+            }
+            Kind::EndTag(TagID::Table) => {
+                if !self.in_scope(TagID::Table, Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.pop_until(TagID::Table);
+                self.reset_insertion_mode();
+            }
+            Kind::EndTag(TagID::Body | TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Html | TagID::Tbody |
+                         TagID::Td | TagID::TFoot | TagID::Th | TagID::Thead | TagID::Tr) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+            }
+            Kind::StartTag(TagID::Style | TagID::Script | TagID::Template) | Kind::EndTag(TagID::Template) => {
+                return self.in_head(token);
+            }
+            Kind::StartTag(TagID::Input) if self.is_hidden_input(&token) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                self.insert_html_element(token);
+                self.pop();
+            }
+            Kind::StartTag(TagID::Form) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                if self.has_open(TagID::Template) || self.form.is_some() {
+                    return None;
+                }
+                self.form = Some(self.insert_html_element(token));
+                self.pop();
+            }
+            Kind::EndOfFile => return self.in_body(token),
+            _ => return self.in_table_anything_else(token),
+        }
+        None
+    }
+
+    fn is_hidden_input(&self, token: &Token) -> bool {
+        let raw_html = self.raw_html();
+        token.attributes.iter().flat_map(|attributes| attributes.iter()).any(|attribute| {
+            attribute.name(token, raw_html).eq_ignore_ascii_case("type") &&
+            attribute.value(token, raw_html).eq_ignore_ascii_case("hidden")
+        })
+    }
+
+    fn in_table_anything_else(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => self.error(ErrorKind::UnexpectedCharacter, &token),
+            Kind::StartTag(_) => self.error(ErrorKind::UnexpectedStartTag, &token),
+            _ => self.error(ErrorKind::UnexpectedEndTag, &token),
+        }
+        self.foster_parenting = true;
+        let reprocess = self.in_body(token);
+        self.foster_parenting = false;
+        reprocess
+    }
+
+    /// Collects the text in a table until the next other token, since whether it stays in the
+    /// table depends on all of it being whitespace.
+    fn in_table_text(&mut self, token: Token) -> Option<Token> {
+        if kind(&token) == Kind::Text {
+            let pieces = self.split_nuls(token);
+            self.pending_table_text.extend(pieces);
+            return None;
+        }
+        let pending = std::mem::take(&mut self.pending_table_text);
+        if pending.iter().all(|text| self.is_all_whitespace(text)) {
+            for text in pending {
+                self.insert_text(text);
+            }
+        } else {
+            for text in pending {
+                self.in_table_anything_else(text);
+            }
+        }
+        self.mode = self.original_mode;
+        Some(token)
+    }
+
+    fn in_caption(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::EndTag(TagID::Caption) => {
+                self.close_caption(&token);
+                None
+            }
+            Kind::StartTag(TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Tbody | TagID::Td | TagID::TFoot |
+                           TagID::Th | TagID::Thead | TagID::Tr) | Kind::EndTag(TagID::Table) => {
+                if self.close_caption(&token) {
+                    Some(token)
+                } else {
+                    None
+                }
+            }
+            Kind::EndTag(TagID::Body | TagID::Col | TagID::ColGroup | TagID::Html | TagID::Tbody | TagID::Td |
+                         TagID::TFoot | TagID::Th | TagID::Thead | TagID::Tr) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                None
+            }
+            _ => self.in_body(token),
+        }
+    }
+
+    fn close_caption(&mut self, token: &Token) -> bool {
+        if !self.in_scope(TagID::Caption, Scope::Table) {
+            self.error(ErrorKind::UnexpectedEndTag, token);
+            return false;
+        }
+        self.generate_implied_end_tags();
+        if !self.current_is(TagID::Caption) {
+            self.error(ErrorKind::UnexpectedEndTag, token);
+        }
+        self.pop_until(TagID::Caption);
+        self.clear_active_formatting_to_last_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, Self::insert_text)?,
+            Kind::Comment => {
+                self.insert_comment(token, None);
+                return None;
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Col) => {
+                self.insert_html_element(token);
+                self.pop();
+                return None;
+            }
+            Kind::EndTag(TagID::ColGroup) => {
+                if !self.current_is(TagID::ColGroup) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.pop();
+                self.mode = InsertionMode::InTable;
+                return None;
+            }
+            Kind::EndTag(TagID::Col) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Template) | Kind::EndTag(TagID::Template) => return self.in_head(token),
+            Kind::EndOfFile => return self.in_body(token),
+            _ => token,
+        };
+        if !self.current_is(TagID::ColGroup) {
+            self.error(ErrorKind::UnexpectedStartTag, &token);
+            return None;
+        }
+        self.pop();
+        self.mode = InsertionMode::InTable;
+        Some(token)
+    }
+
+    fn in_table_body(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::StartTag(TagID::Tr) => {
+                self.clear_stack_back_to_table_body_context();
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InRow;
+                None
+            }
+            Kind::StartTag(TagID::Th | TagID::Td) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                self.clear_stack_back_to_table_body_context();
+                self.insert_implied_element(TagID::Tr, &token);
+                self.mode = InsertionMode::InRow;
+                Some(token)
+            }
+            Kind::EndTag(tag @ (TagID::Tbody | TagID::TFoot | TagID::Thead)) => {
+                if !self.in_scope(tag, Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.clear_stack_back_to_table_body_context();
+                self.pop();
+                self.mode = InsertionMode::InTable;
+                None
+            }
+            Kind::StartTag(TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Tbody | TagID::TFoot | TagID::Thead) |
+            Kind::EndTag(TagID::Table) => {
+                if !self.in_scope_where(|tag| matches!(tag, TagID::Tbody | TagID::Thead | TagID::TFoot), Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.clear_stack_back_to_table_body_context();
+                self.pop();
+                self.mode = InsertionMode::InTable;
+                Some(token)
+            }
+            Kind::EndTag(TagID::Body | TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Html | TagID::Td |
+                         TagID::Th | TagID::Tr) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                None
+            }
+            _ => self.in_table(token),
+        }
+    }
+
+    fn in_row(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::StartTag(TagID::Th | TagID::Td) => {
+                self.clear_stack_back_to_table_row_context();
+                self.insert_html_element(token);
+                self.mode = InsertionMode::InCell;
+                self.insert_marker();
+                None
+            }
+            Kind::EndTag(TagID::Tr) => {
+                self.close_row(&token);
+                None
+            }
+            Kind::StartTag(TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Tbody | TagID::TFoot | TagID::Thead |
+                           TagID::Tr) | Kind::EndTag(TagID::Table) => {
+                if self.close_row(&token) {
+                    Some(token)
+                } else {
+                    None
+                }
+            }
+            Kind::EndTag(tag @ (TagID::Tbody | TagID::TFoot | TagID::Thead)) => {
+                if !self.in_scope(tag, Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                if self.close_row(&token) {
+                    Some(token)
+                } else {
+                    None
+                }
+            }
+            Kind::EndTag(TagID::Body | TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Html | TagID::Td |
+                         TagID::Th) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                None
+            }
+            _ => self.in_table(token),
+        }
+    }
+
+    fn close_row(&mut self, token: &Token) -> bool {
+        if !self.in_scope(TagID::Tr, Scope::Table) {
+            self.error(ErrorKind::UnexpectedEndTag, token);
+            return false;
+        }
+        self.clear_stack_back_to_table_row_context();
+        self.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::EndTag(tag @ (TagID::Td | TagID::Th)) => {
+                if !self.in_scope(tag, Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.generate_implied_end_tags();
+                if !self.current_is(tag) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                self.pop_until(tag);
+                self.clear_active_formatting_to_last_marker();
+                self.mode = InsertionMode::InRow;
+                None
+            }
+            Kind::StartTag(TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Tbody | TagID::Td | TagID::TFoot |
+                           TagID::Th | TagID::Thead | TagID::Tr) => {
+                if !self.in_scope_where(|tag| matches!(tag, TagID::Td | TagID::Th), Scope::Table) {
+                    self.error(ErrorKind::UnexpectedStartTag, &token);
+                    return None;
+                }
+                self.close_cell(&token);
+                Some(token)
+            }
+            Kind::EndTag(TagID::Body | TagID::Caption | TagID::Col | TagID::ColGroup | TagID::Html) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                None
+            }
+            Kind::EndTag(tag @ (TagID::Table | TagID::Tbody | TagID::TFoot | TagID::Thead | TagID::Tr)) => {
+                if !self.in_scope(tag, Scope::Table) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.close_cell(&token);
+                Some(token)
+            }
+            _ => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self, token: &Token) {
+        self.generate_implied_end_tags();
+        if !self.current_node().is_some_and(|node| self.is_html_where(node, |tag| matches!(tag, TagID::Td | TagID::Th))) {
+            self.error(ErrorKind::UnexpectedEndTag, token);
+        }
+        self.pop_until_where(|tag| matches!(tag, TagID::Td | TagID::Th));
+        self.clear_active_formatting_to_last_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                for piece in self.split_nuls(token) {
+                    self.insert_text(piece);
+                }
+            }
+            Kind::Comment => self.insert_comment(token, None),
+            Kind::Doctype => self.error(ErrorKind::UnexpectedDoctype, &token),
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Option) => {
+                if self.current_is(TagID::Option) {
+                    self.pop();
+                }
+                self.insert_html_element(token);
+            }
+            Kind::StartTag(TagID::OptGroup) => {
+                if self.current_is(TagID::Option) {
+                    self.pop();
+                }
+                if self.current_is(TagID::OptGroup) {
+                    self.pop();
+                }
+                self.insert_html_element(token);
+            }
+            Kind::StartTag(TagID::Hr) => {
+                if self.current_is(TagID::Option) {
+                    self.pop();
+                }
+                if self.current_is(TagID::OptGroup) {
+                    self.pop();
+                }
+                self.insert_html_element(token);
+                self.pop();
+            }
+            Kind::EndTag(TagID::OptGroup) => {
+                let length = self.open_elements.len();
+                if self.current_is(TagID::Option) && length >= 2 &&
+                   self.is_html(self.open_elements[length - 2], TagID::OptGroup) {
+                    self.pop();
+                }
+                if self.current_is(TagID::OptGroup) {
+                    self.pop();
+                } else {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+            }
+            Kind::EndTag(TagID::Option) => {
+                if self.current_is(TagID::Option) {
+                    self.pop();
+                } else {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+            }
+            Kind::EndTag(TagID::Select) => {
+                if !self.in_scope(TagID::Select, Scope::Select) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.pop_until(TagID::Select);
+                self.reset_insertion_mode();
+            }
+            Kind::StartTag(TagID::Select) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                if self.in_scope(TagID::Select, Scope::Select) {
+                    self.pop_until(TagID::Select);
+                    self.reset_insertion_mode();
+                }
+            }
+            Kind::StartTag(TagID::Input | TagID::Keygen | TagID::TextArea) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                if !self.in_scope(TagID::Select, Scope::Select) {
+                    return None;
+                }
+                self.pop_until(TagID::Select);
+                self.reset_insertion_mode();
+                return Some(token);
+            }
+            Kind::StartTag(TagID::Script | TagID::Template) | Kind::EndTag(TagID::Template) => {
+                return self.in_head(token);
+            }
+            Kind::EndOfFile => return self.in_body(token),
+            Kind::StartTag(_) => self.error(ErrorKind::UnexpectedStartTag, &token),
+            Kind::EndTag(_) => self.error(ErrorKind::UnexpectedEndTag, &token),
+        }
+        None
+    }
+
+    fn in_select_in_table(&mut self, token: Token) -> Option<Token> {
+        let table_tag = |tag| matches!(tag, TagID::Caption | TagID::Table | TagID::Tbody | TagID::TFoot |
+                                            TagID::Thead | TagID::Tr | TagID::Td | TagID::Th);
+        match kind(&token) {
+            Kind::StartTag(tag) if table_tag(tag) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                self.pop_until(TagID::Select);
+                self.reset_insertion_mode();
+                Some(token)
+            }
+            Kind::EndTag(tag) if table_tag(tag) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                if !self.in_scope(tag, Scope::Table) {
+                    return None;
+                }
+                self.pop_until(TagID::Select);
+                self.reset_insertion_mode();
+                Some(token)
+            }
+            _ => self.in_select(token),
+        }
+    }
+
+    fn in_template(&mut self, token: Token) -> Option<Token> {
+        let mode = match kind(&token) {
+            Kind::Text | Kind::Comment | Kind::Doctype => return self.in_body(token),
+            Kind::StartTag(TagID::Base | TagID::BaseFont | TagID::BGSound | TagID::Link | TagID::Meta |
+                           TagID::NoFrames | TagID::Script | TagID::Style | TagID::Template | TagID::Title) |
+            Kind::EndTag(TagID::Template) => return self.in_head(token),
+            Kind::StartTag(TagID::Caption | TagID::ColGroup | TagID::Tbody | TagID::TFoot | TagID::Thead) => {
+                InsertionMode::InTable
+            }
+            Kind::StartTag(TagID::Col) => InsertionMode::InColumnGroup,
+            Kind::StartTag(TagID::Tr) => InsertionMode::InTableBody,
+            Kind::StartTag(TagID::Td | TagID::Th) => InsertionMode::InRow,
+            Kind::StartTag(_) => InsertionMode::InBody,
+            Kind::EndTag(_) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                return None;
+            }
+            Kind::EndOfFile => {
+                if !self.has_open(TagID::Template) {
+                    return self.stop();
+                }
+                self.error(ErrorKind::EofWithOpenElements, &token);
+                self.pop_until(TagID::Template);
+                self.clear_active_formatting_to_last_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                return Some(token);
+            }
+        };
+        self.template_modes.pop();
+        self.template_modes.push(mode);
+        self.mode = mode;
+        Some(token)
+    }
+
+    fn after_body(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Text => self.leading_whitespace(token, |builder, whitespace| { builder.in_body(whitespace); })?,
+            Kind::Comment => {
+                let html = self.open_elements.first().copied();
+                self.insert_comment(token, html);
+                return None;
+            }
+            Kind::Doctype => {
+                self.error(ErrorKind::UnexpectedDoctype, &token);
+                return None;
+            }
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::EndTag(TagID::Html) => {
+                if self.context.is_some() {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                } else {
+                    self.mode = InsertionMode::AfterAfterBody;
+                }
+                return None;
+            }
+            Kind::EndOfFile => return self.stop(),
+            _ => token,
+        };
+        self.error(ErrorKind::UnexpectedStartTag, &token);
+        self.mode = InsertionMode::InBody;
+        Some(token)
+    }
+
+    fn in_frameset(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                for whitespace in self.whitespace_runs(&token) {
+                    self.insert_text(whitespace);
+                }
+            }
+            Kind::Comment => self.insert_comment(token, None),
+            Kind::Doctype => self.error(ErrorKind::UnexpectedDoctype, &token),
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::StartTag(TagID::Frameset) => {
+                self.insert_html_element(token);
+            }
+            Kind::EndTag(TagID::Frameset) => {
+                if self.open_elements.len() == 1 {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                    return None;
+                }
+                self.pop();
+                if self.context.is_none() && !self.current_is(TagID::Frameset) {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Kind::StartTag(TagID::Frame) => {
+                self.insert_html_element(token);
+                self.pop();
+            }
+            Kind::StartTag(TagID::NoFrames) => return self.in_head(token),
+            Kind::EndOfFile => {
+                if self.open_elements.len() > 1 {
+                    self.error(ErrorKind::EofWithOpenElements, &token);
+                }
+                return self.stop();
+            }
+            Kind::StartTag(_) => self.error(ErrorKind::UnexpectedStartTag, &token),
+            Kind::EndTag(_) => self.error(ErrorKind::UnexpectedEndTag, &token),
+        }
+        None
+    }
+
+    fn after_frameset(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                for whitespace in self.whitespace_runs(&token) {
+                    self.insert_text(whitespace);
+                }
+            }
+            Kind::Comment => self.insert_comment(token, None),
+            Kind::Doctype => self.error(ErrorKind::UnexpectedDoctype, &token),
+            Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::EndTag(TagID::Html) => self.mode = InsertionMode::AfterAfterFrameset,
+            Kind::StartTag(TagID::NoFrames) => return self.in_head(token),
+            Kind::EndOfFile => return self.stop(),
+            Kind::StartTag(_) => self.error(ErrorKind::UnexpectedStartTag, &token),
+            Kind::EndTag(_) => self.error(ErrorKind::UnexpectedEndTag, &token),
+        }
+        None
+    }
+
+    fn after_after_body(&mut self, token: Token) -> Option<Token> {
+        let token = match kind(&token) {
+            Kind::Comment => {
+                self.insert_comment(token, Some(self.document.root()));
+                return None;
+            }
+            Kind::Doctype | Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::Text => self.leading_whitespace(token, |builder, whitespace| { builder.in_body(whitespace); })?,
+            Kind::EndOfFile => return self.stop(),
+            _ => token,
+        };
+        self.error(ErrorKind::UnexpectedStartTag, &token);
+        self.mode = InsertionMode::InBody;
+        Some(token)
+    }
+
+    fn after_after_frameset(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Comment => self.insert_comment(token, Some(self.document.root())),
+            Kind::Doctype | Kind::StartTag(TagID::Html) => return self.in_body(token),
+            Kind::Text => {
+                for whitespace in self.whitespace_runs(&token) {
+                    self.in_body(whitespace);
+                }
+            }
+            Kind::EndOfFile => return self.stop(),
+            Kind::StartTag(TagID::NoFrames) => return self.in_head(token),
+            Kind::StartTag(_) => self.error(ErrorKind::UnexpectedStartTag, &token),
+            Kind::EndTag(_) => self.error(ErrorKind::UnexpectedEndTag, &token),
+        }
+        None
+    }
+
+    /// The rules for tokens in foreign content (inside `<svg>` or `<math>`).
+    fn in_foreign_content(&mut self, token: Token) -> Option<Token> {
+        match kind(&token) {
+            Kind::Text => {
+                if !self.is_all_whitespace(&token) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(token);
+            }
+            Kind::Comment => self.insert_comment(token, None),
+            Kind::Doctype => self.error(ErrorKind::UnexpectedDoctype, &token),
+            Kind::StartTag(tag) if foreign::is_breakout_tag(tag) ||
+                                   (tag == TagID::Font && self.is_breakout_font(&token)) => {
+                self.error(ErrorKind::UnexpectedStartTag, &token);
+                self.pop_to_html_content();
+                return self.process_in(self.mode, token);
+            }
+            Kind::StartTag(_) => {
+                let namespace = self.adjusted_current_node().map_or(Namespace::Html, |node| self.namespace_of(node));
+                let self_closing = token.flags & Flags::CloseSelf as u8 != 0;
+                self.insert_foreign_element(token, namespace);
+                if self_closing {
+                    self.pop();
+                }
+            }
+            Kind::EndTag(TagID::Br | TagID::Paragraph) => {
+                self.error(ErrorKind::UnexpectedEndTag, &token);
+                self.pop_to_html_content();
+                // Integration points send end tags to foreign content, so this can't go back
+                // through the dispatcher
+                return self.process_in(self.mode, token);
+            }
+            Kind::EndTag(_) => {
+                let mut index = self.open_elements.len() - 1;
+                if !self.same_name(self.open_elements[index], &token) {
+                    self.error(ErrorKind::UnexpectedEndTag, &token);
+                }
+                while index > 0 {
+                    let node = self.open_elements[index];
+                    if self.same_name(node, &token) {
+                        self.open_elements.truncate(index);
+                        return None;
+                    }
+                    index -= 1;
+                    if self.namespace_of(self.open_elements[index]) == Namespace::Html {
+                        return self.process_in(self.mode, token);
+                    }
+                }
+            }
+            Kind::EndOfFile => return Some(token),
+        }
+        None
+    }
+
+    fn is_breakout_font(&self, token: &Token) -> bool {
+        let raw_html = self.raw_html();
+        token.attributes.iter().flat_map(|attributes| attributes.iter()).any(|attribute| {
+            let name = attribute.name(token, raw_html);
+            ["color", "face", "size"].iter().any(|candidate| name.eq_ignore_ascii_case(candidate))
+        })
+    }
+
+    fn pop_to_html_content(&mut self) {
+        while let Some(node) = self.current_node() {
+            if self.namespace_of(node) == Namespace::Html || self.is_mathml_text_integration_point(node) ||
+               self.is_html_integration_point(node) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    /// Works out the insertion mode from the stack of open elements, after something that
+    /// could have left the current one wrong (closing a table or a select).
+    fn reset_insertion_mode(&mut self) {
+        for index in (0..self.open_elements.len()).rev() {
+            let last = index == 0;
+            let node = match self.context {
+                Some(context) if last => context,
+                _ => self.open_elements[index],
+            };
+            let element = self.element(node);
+            if element.namespace != Namespace::Html {
+                if last {
+                    self.mode = InsertionMode::InBody;
+                    return;
+                }
+                continue;
+            }
+            let mode = match element.tag {
+                TagID::Select => {
+                    let in_table = !last && self.open_elements[..index].iter().rev()
+                        .take_while(|&&ancestor| !self.is_html(ancestor, TagID::Template))
+                        .any(|&ancestor| self.is_html(ancestor, TagID::Table));
+                    if in_table { InsertionMode::InSelectInTable } else { InsertionMode::InSelect }
+                }
+                TagID::Td | TagID::Th if !last => InsertionMode::InCell,
+                TagID::Tr => InsertionMode::InRow,
+                TagID::Tbody | TagID::Thead | TagID::TFoot => InsertionMode::InTableBody,
+                TagID::Caption => InsertionMode::InCaption,
+                TagID::ColGroup => InsertionMode::InColumnGroup,
+                TagID::Table => InsertionMode::InTable,
+                TagID::Template => *self.template_modes.last().unwrap_or(&InsertionMode::InTemplate),
+                TagID::Head if !last => InsertionMode::InHead,
+                TagID::Body => InsertionMode::InBody,
+                TagID::Frameset => InsertionMode::InFrameset,
+                TagID::Html => {
+                    if self.head.is_none() { InsertionMode::BeforeHead } else { InsertionMode::AfterHead }
+                }
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            self.mode = mode;
+            return;
+        }
+        self.mode = InsertionMode::InBody;
+    }
+}
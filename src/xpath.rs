@@ -0,0 +1,1384 @@
+// XPath 1.0 over the document tree. An expression is parsed once into an `XPath`, and can then be
+// evaluated with any node as the context node. The tree has everything the XPath data model
+// needs except attribute nodes, which are an element and the index of one of its attributes.
+//
+// HTML documents aren't XML, so this goes the way browsers evaluating XPath on HTML do: names of
+// HTML elements and of attributes are compared case-insensitively, and a name without a prefix
+// matches elements in any namespace. The prefixes html, svg, math, xlink and xml are bound to
+// their namespaces. There are no variables, and neither the doctype nor template contents are
+// part of the tree an expression sees.
+//
+// XPath 1.0 has no type errors at run time if the functions are known in advance: whether an
+// expression is a node-set can be told from its syntax. So all errors are reported when parsing,
+// and evaluation always gives a value.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::dom::{Document, Element, NodeData, NodeId};
+use crate::foreign::Namespace;
+use crate::tokens::{TagID, ASCII_TO_TAG_ID};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XPathErrorKind {
+    UnexpectedEnd,
+    UnexpectedCharacter,
+    UnknownAxis,
+    UnknownFunction,
+    UnknownPrefix,
+    WrongArgumentCount,
+    NotANodeSet, // A path step, predicate, union or function argument that needs a node-set
+    UnsupportedVariable,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XPathError {
+    pub kind:     XPathErrorKind,
+    pub position: usize,
+}
+
+impl XPathError {
+    pub fn new(kind: XPathErrorKind, position: usize) -> Self {
+        XPathError {
+            kind,
+            position,
+        }
+    }
+}
+
+/// A node in the XPath data model: a node of the tree, or an attribute of an element, by its
+/// index in `Element::attributes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum XPathNode {
+    Node(NodeId),
+    Attribute(NodeId, usize),
+}
+
+/// The result of an expression. Node-sets are in document order, without duplicates.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    NodeSet(Vec<XPathNode>),
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    pub fn boolean(&self) -> bool {
+        match self {
+            Value::NodeSet(nodes) => !nodes.is_empty(),
+            Value::Boolean(boolean) => *boolean,
+            Value::Number(number) => *number != 0.0 && !number.is_nan(),
+            Value::String(string) => !string.is_empty(),
+        }
+    }
+
+    pub fn number(&self, document: &Document) -> f64 {
+        match self {
+            Value::Boolean(boolean) => if *boolean { 1.0 } else { 0.0 },
+            Value::Number(number) => *number,
+            _ => parse_number(&self.string(document)),
+        }
+    }
+
+    /// The string value, which for a node-set is the string value of its first node.
+    pub fn string(&self, document: &Document) -> String {
+        match self {
+            Value::NodeSet(nodes) => nodes.first().map_or_else(String::new, |&node| string_value(document, node)),
+            Value::Boolean(boolean) => boolean.to_string(),
+            Value::Number(number) => format_number(*number),
+            Value::String(string) => string.clone(),
+        }
+    }
+}
+
+/// The string value of a node: the text of every text node under it for the root and elements,
+/// the text of text and comment nodes, and the value of attributes.
+pub fn string_value(document: &Document, node: XPathNode) -> String {
+    match node {
+        XPathNode::Node(id) => document.text(id),
+        XPathNode::Attribute(id, index) => attribute(document, id, index).map_or_else(String::new, |attribute| {
+            attribute.value(document.raw_html()).into_owned()
+        }),
+    }
+}
+
+/// Evaluates `expression` with the document's root as the context node.
+pub fn evaluate(document: &Document, expression: &str) -> Result<Value, XPathError> {
+    Ok(XPath::parse(expression)?.evaluate(document, document.root()))
+}
+
+/// The nodes `expression` selects from the document's root.
+pub fn select(document: &Document, expression: &str) -> Result<Vec<XPathNode>, XPathError> {
+    let xpath = XPath::parse(expression)?;
+    if !xpath.is_node_set() {
+        return Err(XPathError::new(XPathErrorKind::NotANodeSet, 0));
+    }
+    Ok(xpath.select(document, document.root()))
+}
+
+/// A parsed XPath expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XPath {
+    expr: Expr,
+}
+
+impl XPath {
+    pub fn parse(expression: &str) -> Result<Self, XPathError> {
+        let mut parser = Parser {
+            lexemes: lex(expression)?,
+            index:   0,
+            end:     expression.len(),
+        };
+        let expr = parser.expr()?;
+        match parser.peek() {
+            None => Ok(XPath { expr }),
+            Some(_) => Err(parser.error(XPathErrorKind::UnexpectedCharacter)),
+        }
+    }
+
+    /// Whether the expression evaluates to a node-set, as opposed to a boolean, number or string.
+    pub fn is_node_set(&self) -> bool {
+        is_node_set(&self.expr)
+    }
+
+    pub fn evaluate(&self, document: &Document, context: NodeId) -> Value {
+        let evaluator = Evaluator::new(document);
+        evaluator.eval(&self.expr, Context { node: XPathNode::Node(context), position: 1, size: 1 })
+    }
+
+    /// The nodes the expression selects, or none if it isn't a node-set.
+    pub fn select(&self, document: &Document, context: NodeId) -> Vec<XPathNode> {
+        match self.evaluate(document, context) {
+            Value::NodeSet(nodes) => nodes,
+            _ => Vec::new(),
+        }
+    }
+
+    /// The expression's value converted to a string, as the `string()` function does.
+    pub fn string(&self, document: &Document, context: NodeId) -> String {
+        self.evaluate(document, context).string(document)
+    }
+}
+
+// Syntax
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Arithmetic(Arithmetic, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Path(bool, Vec<Step>),         // Whether it's absolute, and its steps
+    Filter(Box<Expr>, Vec<Expr>),  // A primary expression and its predicates
+    Steps(Box<Expr>, Vec<Step>),   // Steps from the nodes of a filter expression
+    Literal(String),
+    Number(f64),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    axis:       Axis,
+    test:       NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    Self_,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "namespace" => Axis::Namespace,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::Self_,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NodeTest {
+    Name(NameTest), // Only matches the axis' principal node type, attributes or elements
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction, // HTML has none, so it never matches
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct NameTest {
+    namespace: Option<Namespace>,
+    local:     Option<String>, // None for "*" and "prefix:*"
+    tag:       TagID,          // The local name's tag, for comparing HTML elements without the input
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Last,
+    Position,
+    Count,
+    Id,
+    LocalName,
+    NamespaceUri,
+    Name,
+    String,
+    Concat,
+    StartsWith,
+    Contains,
+    SubstringBefore,
+    SubstringAfter,
+    Substring,
+    StringLength,
+    NormalizeSpace,
+    Translate,
+    Boolean,
+    Not,
+    True,
+    False,
+    Lang,
+    Number,
+    Sum,
+    Floor,
+    Ceiling,
+    Round,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "last" => Function::Last,
+            "position" => Function::Position,
+            "count" => Function::Count,
+            "id" => Function::Id,
+            "local-name" => Function::LocalName,
+            "namespace-uri" => Function::NamespaceUri,
+            "name" => Function::Name,
+            "string" => Function::String,
+            "concat" => Function::Concat,
+            "starts-with" => Function::StartsWith,
+            "contains" => Function::Contains,
+            "substring-before" => Function::SubstringBefore,
+            "substring-after" => Function::SubstringAfter,
+            "substring" => Function::Substring,
+            "string-length" => Function::StringLength,
+            "normalize-space" => Function::NormalizeSpace,
+            "translate" => Function::Translate,
+            "boolean" => Function::Boolean,
+            "not" => Function::Not,
+            "true" => Function::True,
+            "false" => Function::False,
+            "lang" => Function::Lang,
+            "number" => Function::Number,
+            "sum" => Function::Sum,
+            "floor" => Function::Floor,
+            "ceiling" => Function::Ceiling,
+            "round" => Function::Round,
+            _ => return None,
+        })
+    }
+
+    /// The least and most arguments the function takes.
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Function::Last | Function::Position | Function::True | Function::False => (0, 0),
+            Function::LocalName | Function::NamespaceUri | Function::Name | Function::String |
+            Function::StringLength | Function::NormalizeSpace | Function::Number => (0, 1),
+            Function::Count | Function::Id | Function::Boolean | Function::Not | Function::Lang | Function::Sum |
+            Function::Floor | Function::Ceiling | Function::Round => (1, 1),
+            Function::StartsWith | Function::Contains | Function::SubstringBefore | Function::SubstringAfter => (2, 2),
+            Function::Substring => (2, 3),
+            Function::Translate => (3, 3),
+            Function::Concat => (2, usize::MAX),
+        }
+    }
+
+    fn takes_node_set(self) -> bool {
+        matches!(self, Function::Count | Function::Sum | Function::LocalName | Function::NamespaceUri | Function::Name)
+    }
+}
+
+fn is_node_set(expr: &Expr) -> bool {
+    match expr {
+        Expr::Union(..) | Expr::Path(..) | Expr::Steps(..) | Expr::Call(Function::Id, _) => true,
+        Expr::Filter(primary, _) => is_node_set(primary),
+        _ => false,
+    }
+}
+
+fn namespace_for_prefix(prefix: &str) -> Option<Namespace> {
+    Some(match prefix {
+        "html" => Namespace::Html,
+        "svg" => Namespace::Svg,
+        "math" => Namespace::MathMl,
+        "xlink" => Namespace::XLink,
+        "xml" => Namespace::Xml,
+        "xmlns" => Namespace::Xmlns,
+        _ => return None,
+    })
+}
+
+// Lexing
+
+#[derive(Clone, Debug, PartialEq)]
+enum Lexeme {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    Star,                          // The name test that matches any name
+    Name(Option<String>, String),  // A prefix and a local name, which is "*" in "prefix:*"
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl Lexeme {
+    fn is_operator(&self) -> bool {
+        matches!(self, Lexeme::And | Lexeme::Or | Lexeme::Mod | Lexeme::Div | Lexeme::Multiply | Lexeme::Slash |
+                       Lexeme::DoubleSlash | Lexeme::Pipe | Lexeme::Plus | Lexeme::Minus | Lexeme::Equal |
+                       Lexeme::NotEqual | Lexeme::Less | Lexeme::LessOrEqual | Lexeme::Greater |
+                       Lexeme::GreaterOrEqual)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-' || c == '.'
+}
+
+fn is_xml_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+fn lex(input: &str) -> Result<Vec<(Lexeme, usize)>, XPathError> {
+    let mut lexemes: Vec<(Lexeme, usize)> = Vec::new();
+    let mut position = 0;
+    let name_end = |start: usize| input[start..].find(|c| !is_name_char(c)).map_or(input.len(), |offset| start + offset);
+    while let Some(c) = input[position..].chars().next() {
+        if is_xml_whitespace(c) {
+            position += 1;
+            continue;
+        }
+        let start = position;
+        let rest = &input[position..];
+        let next = rest[c.len_utf8()..].chars().next();
+        // "*" and names are operators when they follow something that an operator can follow
+        let operator_allowed = lexemes.last().is_some_and(|(previous, _)| {
+            !matches!(previous, Lexeme::At | Lexeme::ColonColon | Lexeme::LeftParen | Lexeme::LeftBracket | Lexeme::Comma)
+            && !previous.is_operator()
+        });
+        let (lexeme, length) = match c {
+            '(' => (Lexeme::LeftParen, 1),
+            ')' => (Lexeme::RightParen, 1),
+            '[' => (Lexeme::LeftBracket, 1),
+            ']' => (Lexeme::RightBracket, 1),
+            '@' => (Lexeme::At, 1),
+            ',' => (Lexeme::Comma, 1),
+            '|' => (Lexeme::Pipe, 1),
+            '+' => (Lexeme::Plus, 1),
+            '-' => (Lexeme::Minus, 1),
+            '=' => (Lexeme::Equal, 1),
+            ':' if next == Some(':') => (Lexeme::ColonColon, 2),
+            '/' if next == Some('/') => (Lexeme::DoubleSlash, 2),
+            '/' => (Lexeme::Slash, 1),
+            '!' if next == Some('=') => (Lexeme::NotEqual, 2),
+            '<' if next == Some('=') => (Lexeme::LessOrEqual, 2),
+            '<' => (Lexeme::Less, 1),
+            '>' if next == Some('=') => (Lexeme::GreaterOrEqual, 2),
+            '>' => (Lexeme::Greater, 1),
+            '*' if operator_allowed => (Lexeme::Multiply, 1),
+            '*' => (Lexeme::Star, 1),
+            '.' if next == Some('.') => (Lexeme::DotDot, 2),
+            '.' | '0'..='9' if c != '.' || next.is_some_and(|next| next.is_ascii_digit()) => {
+                let digits = |from: usize| rest[from..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |offset| from + offset);
+                let mut length = digits(0);
+                if rest[length..].starts_with('.') {
+                    length = digits(length + 1);
+                }
+                (Lexeme::Number(rest[..length].parse().unwrap()), length)
+            }
+            '.' => (Lexeme::Dot, 1),
+            '"' | '\'' => match rest[1..].find(c) {
+                Some(end) => (Lexeme::Literal(rest[1..end + 1].to_string()), end + 2),
+                None => return Err(XPathError::new(XPathErrorKind::UnexpectedEnd, input.len())),
+            },
+            '$' => {
+                let end = name_end(position + 1);
+                (Lexeme::Variable(input[position + 1..end].to_string()), end - position)
+            }
+            c if is_name_start(c) => {
+                let end = name_end(position);
+                let name = &input[position..end];
+                if operator_allowed {
+                    let operator = match name {
+                        "and" => Lexeme::And,
+                        "or" => Lexeme::Or,
+                        "mod" => Lexeme::Mod,
+                        "div" => Lexeme::Div,
+                        _ => return Err(XPathError::new(XPathErrorKind::UnexpectedCharacter, start)),
+                    };
+                    (operator, name.len())
+                } else {
+                    let after = &input[end..];
+                    if after.starts_with(':') && !after.starts_with("::") {
+                        match after[1..].chars().next() {
+                            Some('*') => (Lexeme::Name(Some(name.to_string()), "*".to_string()), name.len() + 2),
+                            Some(c) if is_name_start(c) => {
+                                let local_end = name_end(end + 1);
+                                let local = input[end + 1..local_end].to_string();
+                                (Lexeme::Name(Some(name.to_string()), local), local_end - position)
+                            }
+                            _ => return Err(XPathError::new(XPathErrorKind::UnexpectedCharacter, end + 1)),
+                        }
+                    } else {
+                        (Lexeme::Name(None, name.to_string()), name.len())
+                    }
+                }
+            }
+            _ => return Err(XPathError::new(XPathErrorKind::UnexpectedCharacter, start)),
+        };
+        lexemes.push((lexeme, start));
+        position += length;
+    }
+    Ok(lexemes)
+}
+
+// Parsing
+
+struct Parser {
+    lexemes: Vec<(Lexeme, usize)>,
+    index:   usize,
+    end:     usize, // The length of the input, where errors at the end are
+}
+
+impl Parser {
+    fn error(&self, kind: XPathErrorKind) -> XPathError {
+        let kind = if kind == XPathErrorKind::UnexpectedCharacter && self.peek().is_none() {
+            XPathErrorKind::UnexpectedEnd
+        } else {
+            kind
+        };
+        XPathError::new(kind, self.position())
+    }
+
+    fn position(&self) -> usize {
+        self.lexemes.get(self.index).map_or(self.end, |(_, position)| *position)
+    }
+
+    fn peek(&self) -> Option<&Lexeme> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Lexeme> {
+        self.lexemes.get(self.index + offset).map(|(lexeme, _)| lexeme)
+    }
+
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.index)?.0.clone();
+        self.index += 1;
+        Some(lexeme)
+    }
+
+    fn eat(&mut self, expected: &Lexeme) -> bool {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: &Lexeme) -> Result<(), XPathError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(XPathErrorKind::UnexpectedCharacter))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.and_expr()?;
+        while self.eat(&Lexeme::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.equality_expr()?;
+        while self.eat(&Lexeme::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.equality_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.relational_expr()?;
+        loop {
+            let comparison = match self.peek() {
+                Some(Lexeme::Equal) => Comparison::Equal,
+                Some(Lexeme::NotEqual) => Comparison::NotEqual,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Compare(comparison, Box::new(expr), Box::new(self.relational_expr()?));
+        }
+    }
+
+    fn relational_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.additive_expr()?;
+        loop {
+            let comparison = match self.peek() {
+                Some(Lexeme::Less) => Comparison::Less,
+                Some(Lexeme::LessOrEqual) => Comparison::LessOrEqual,
+                Some(Lexeme::Greater) => Comparison::Greater,
+                Some(Lexeme::GreaterOrEqual) => Comparison::GreaterOrEqual,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Compare(comparison, Box::new(expr), Box::new(self.additive_expr()?));
+        }
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.multiplicative_expr()?;
+        loop {
+            let operation = match self.peek() {
+                Some(Lexeme::Plus) => Arithmetic::Add,
+                Some(Lexeme::Minus) => Arithmetic::Subtract,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Arithmetic(operation, Box::new(expr), Box::new(self.multiplicative_expr()?));
+        }
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.unary_expr()?;
+        loop {
+            let operation = match self.peek() {
+                Some(Lexeme::Multiply) => Arithmetic::Multiply,
+                Some(Lexeme::Div) => Arithmetic::Divide,
+                Some(Lexeme::Mod) => Arithmetic::Modulo,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Arithmetic(operation, Box::new(expr), Box::new(self.unary_expr()?));
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, XPathError> {
+        if self.eat(&Lexeme::Minus) {
+            return Ok(Expr::Negate(Box::new(self.unary_expr()?)));
+        }
+        self.union_expr()
+    }
+
+    fn union_expr(&mut self) -> Result<Expr, XPathError> {
+        let start = self.position();
+        let mut expr = self.path_expr()?;
+        while self.eat(&Lexeme::Pipe) {
+            let right_start = self.position();
+            let right = self.path_expr()?;
+            if !is_node_set(&expr) {
+                return Err(XPathError::new(XPathErrorKind::NotANodeSet, start));
+            }
+            if !is_node_set(&right) {
+                return Err(XPathError::new(XPathErrorKind::NotANodeSet, right_start));
+            }
+            expr = Expr::Union(Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn path_expr(&mut self) -> Result<Expr, XPathError> {
+        let start = self.position();
+        let filter = match (self.peek(), self.peek_at(1)) {
+            (Some(Lexeme::Literal(_) | Lexeme::Number(_) | Lexeme::LeftParen | Lexeme::Variable(_)), _) => true,
+            // A name before a parenthesis is a function call, unless it's a node type test
+            (Some(Lexeme::Name(prefix, name)), Some(Lexeme::LeftParen)) => prefix.is_some() || !is_node_type(name),
+            _ => false,
+        };
+        if !filter {
+            return self.location_path();
+        }
+        let primary = self.primary_expr()?;
+        let predicates = self.predicates()?;
+        let expr = if predicates.is_empty() {
+            primary
+        } else if is_node_set(&primary) {
+            Expr::Filter(Box::new(primary), predicates)
+        } else {
+            return Err(XPathError::new(XPathErrorKind::NotANodeSet, start));
+        };
+        if !matches!(self.peek(), Some(Lexeme::Slash | Lexeme::DoubleSlash)) {
+            return Ok(expr);
+        }
+        if !is_node_set(&expr) {
+            return Err(XPathError::new(XPathErrorKind::NotANodeSet, start));
+        }
+        let mut steps = Vec::new();
+        self.relative_location_path(&mut steps, true)?;
+        Ok(Expr::Steps(Box::new(expr), steps))
+    }
+
+    fn location_path(&mut self) -> Result<Expr, XPathError> {
+        let mut steps = Vec::new();
+        match self.peek() {
+            Some(Lexeme::Slash) => {
+                self.index += 1;
+                // "/" on its own is the root
+                if self.starts_step() {
+                    self.relative_location_path(&mut steps, false)?;
+                }
+                Ok(Expr::Path(true, steps))
+            }
+            Some(Lexeme::DoubleSlash) => {
+                self.relative_location_path(&mut steps, true)?;
+                Ok(Expr::Path(true, steps))
+            }
+            _ => {
+                self.relative_location_path(&mut steps, false)?;
+                Ok(Expr::Path(false, steps))
+            }
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        matches!(self.peek(), Some(Lexeme::Dot | Lexeme::DotDot | Lexeme::At | Lexeme::Star | Lexeme::Name(..)))
+    }
+
+    /// Parses steps separated by "/" or "//", starting with one of them if `separated`.
+    fn relative_location_path(&mut self, steps: &mut Vec<Step>, separated: bool) -> Result<(), XPathError> {
+        let mut separated = separated;
+        loop {
+            if separated {
+                match self.next() {
+                    Some(Lexeme::Slash) => {}
+                    Some(Lexeme::DoubleSlash) => steps.push(Step {
+                        axis:       Axis::DescendantOrSelf,
+                        test:       NodeTest::Node,
+                        predicates: Vec::new(),
+                    }),
+                    _ => {
+                        self.index -= 1;
+                        return Ok(());
+                    }
+                }
+            }
+            steps.push(self.step()?);
+            separated = matches!(self.peek(), Some(Lexeme::Slash | Lexeme::DoubleSlash));
+            if !separated {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<Step, XPathError> {
+        let abbreviated = |axis| Step { axis, test: NodeTest::Node, predicates: Vec::new() };
+        if self.eat(&Lexeme::Dot) {
+            return Ok(abbreviated(Axis::Self_));
+        }
+        if self.eat(&Lexeme::DotDot) {
+            return Ok(abbreviated(Axis::Parent));
+        }
+        let axis = match (self.peek(), self.peek_at(1)) {
+            (Some(Lexeme::At), _) => {
+                self.index += 1;
+                Axis::Attribute
+            }
+            (Some(Lexeme::Name(None, name)), Some(Lexeme::ColonColon)) => {
+                let axis = Axis::from_name(name).ok_or_else(|| self.error(XPathErrorKind::UnknownAxis))?;
+                self.index += 2;
+                axis
+            }
+            _ => Axis::Child,
+        };
+        let test = self.node_test()?;
+        let predicates = self.predicates()?;
+        Ok(Step { axis, test, predicates })
+    }
+
+    fn node_test(&mut self) -> Result<NodeTest, XPathError> {
+        let start = self.position();
+        match self.next() {
+            Some(Lexeme::Star) => Ok(NodeTest::Name(NameTest { namespace: None, local: None, tag: TagID::Undef })),
+            Some(Lexeme::Name(None, name)) if is_node_type(&name) && self.peek() == Some(&Lexeme::LeftParen) => {
+                self.index += 1;
+                let test = match name.as_str() {
+                    "node" => NodeTest::Node,
+                    "text" => NodeTest::Text,
+                    "comment" => NodeTest::Comment,
+                    _ => {
+                        // processing-instruction() can name the target it's for
+                        if let Some(Lexeme::Literal(_)) = self.peek() {
+                            self.index += 1;
+                        }
+                        NodeTest::ProcessingInstruction
+                    }
+                };
+                self.expect(&Lexeme::RightParen)?;
+                Ok(test)
+            }
+            Some(Lexeme::Name(prefix, local)) => {
+                let namespace = match prefix {
+                    Some(prefix) => match namespace_for_prefix(&prefix) {
+                        Some(namespace) => Some(namespace),
+                        None => return Err(XPathError::new(XPathErrorKind::UnknownPrefix, start)),
+                    },
+                    None => None,
+                };
+                let local = (local != "*").then_some(local);
+                let tag = local.as_ref()
+                               .and_then(|local| ASCII_TO_TAG_ID.get(local.to_ascii_lowercase().as_bytes()).copied())
+                               .unwrap_or(TagID::Undef);
+                Ok(NodeTest::Name(NameTest { namespace, local, tag }))
+            }
+            None => Err(XPathError::new(XPathErrorKind::UnexpectedEnd, start)),
+            Some(_) => Err(XPathError::new(XPathErrorKind::UnexpectedCharacter, start)),
+        }
+    }
+
+    fn predicates(&mut self) -> Result<Vec<Expr>, XPathError> {
+        let mut predicates = Vec::new();
+        while self.eat(&Lexeme::LeftBracket) {
+            predicates.push(self.expr()?);
+            self.expect(&Lexeme::RightBracket)?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, XPathError> {
+        let start = self.position();
+        match self.next() {
+            Some(Lexeme::Variable(_)) => Err(XPathError::new(XPathErrorKind::UnsupportedVariable, start)),
+            Some(Lexeme::LeftParen) => {
+                let expr = self.expr()?;
+                self.expect(&Lexeme::RightParen)?;
+                Ok(expr)
+            }
+            Some(Lexeme::Literal(literal)) => Ok(Expr::Literal(literal)),
+            Some(Lexeme::Number(number)) => Ok(Expr::Number(number)),
+            Some(Lexeme::Name(prefix, name)) => {
+                let function = match prefix {
+                    None => Function::from_name(&name),
+                    Some(_) => None,
+                };
+                let function = function.ok_or_else(|| XPathError::new(XPathErrorKind::UnknownFunction, start))?;
+                self.expect(&Lexeme::LeftParen)?;
+                let mut arguments = Vec::new();
+                if !self.eat(&Lexeme::RightParen) {
+                    loop {
+                        let argument_start = self.position();
+                        let argument = self.expr()?;
+                        if function.takes_node_set() && !is_node_set(&argument) {
+                            return Err(XPathError::new(XPathErrorKind::NotANodeSet, argument_start));
+                        }
+                        arguments.push(argument);
+                        if !self.eat(&Lexeme::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect(&Lexeme::RightParen)?;
+                }
+                let (least, most) = function.arity();
+                if arguments.len() < least || arguments.len() > most {
+                    return Err(XPathError::new(XPathErrorKind::WrongArgumentCount, start));
+                }
+                Ok(Expr::Call(function, arguments))
+            }
+            _ => unreachable!("path_expr only parses primary expressions where one starts"),
+        }
+    }
+}
+
+fn is_node_type(name: &str) -> bool {
+    matches!(name, "node" | "text" | "comment" | "processing-instruction")
+}
+
+// Evaluation
+
+#[derive(Clone, Copy, Debug)]
+struct Context {
+    node:     XPathNode,
+    position: usize,
+    size:     usize,
+}
+
+struct Evaluator<'d, 'a> {
+    document: &'d Document<'a>,
+    order:    &'d [u32], // The document's, so it's only built once however often it's evaluated
+}
+
+impl<'d, 'a> Evaluator<'d, 'a> {
+    fn new(document: &'d Document<'a>) -> Self {
+        Evaluator { document, order: document.document_order() }
+    }
+
+    fn eval(&self, expr: &Expr, context: Context) -> Value {
+        match expr {
+            Expr::Or(left, right) => {
+                Value::Boolean(self.eval(left, context).boolean() || self.eval(right, context).boolean())
+            }
+            Expr::And(left, right) => {
+                Value::Boolean(self.eval(left, context).boolean() && self.eval(right, context).boolean())
+            }
+            Expr::Compare(comparison, left, right) => {
+                Value::Boolean(self.compare(*comparison, &self.eval(left, context), &self.eval(right, context)))
+            }
+            Expr::Arithmetic(operation, left, right) => {
+                let left = self.eval(left, context).number(self.document);
+                let right = self.eval(right, context).number(self.document);
+                Value::Number(match operation {
+                    Arithmetic::Add => left + right,
+                    Arithmetic::Subtract => left - right,
+                    Arithmetic::Multiply => left * right,
+                    Arithmetic::Divide => left / right,
+                    Arithmetic::Modulo => left % right,
+                })
+            }
+            Expr::Negate(expr) => Value::Number(-self.eval(expr, context).number(self.document)),
+            Expr::Union(left, right) => {
+                let mut nodes = self.nodes(left, context);
+                nodes.extend(self.nodes(right, context));
+                self.sort(&mut nodes);
+                Value::NodeSet(nodes)
+            }
+            Expr::Path(absolute, steps) => {
+                let start = if *absolute { self.root(context.node) } else { context.node };
+                Value::NodeSet(self.steps(vec![start], steps))
+            }
+            Expr::Filter(primary, predicates) => {
+                let mut nodes = self.nodes(primary, context);
+                for predicate in predicates {
+                    nodes = self.filter(nodes, predicate);
+                }
+                Value::NodeSet(nodes)
+            }
+            Expr::Steps(filter, steps) => Value::NodeSet(self.steps(self.nodes(filter, context), steps)),
+            Expr::Literal(literal) => Value::String(literal.clone()),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Call(function, arguments) => self.call(*function, arguments, context),
+        }
+    }
+
+    /// Evaluates an expression that the parser made sure is a node-set.
+    fn nodes(&self, expr: &Expr, context: Context) -> Vec<XPathNode> {
+        match self.eval(expr, context) {
+            Value::NodeSet(nodes) => nodes,
+            _ => Vec::new(),
+        }
+    }
+
+    fn root(&self, node: XPathNode) -> XPathNode {
+        let id = match node {
+            XPathNode::Node(id) | XPathNode::Attribute(id, _) => id,
+        };
+        XPathNode::Node(self.document.ancestors(id).last().unwrap_or(id))
+    }
+
+    fn sort(&self, nodes: &mut Vec<XPathNode>) {
+        nodes.sort_by_key(|&node| match node {
+            XPathNode::Node(id) => (self.order[id.index()], 0),
+            // Attributes come after their element, and before its children
+            XPathNode::Attribute(id, index) => (self.order[id.index()], index + 1),
+        });
+        nodes.dedup();
+    }
+
+    fn steps(&self, mut nodes: Vec<XPathNode>, steps: &[Step]) -> Vec<XPathNode> {
+        for step in steps {
+            if step.predicates.is_empty() {
+                nodes = self.covering(step.axis, nodes);
+            }
+            let mut selected = Vec::new();
+            for &node in &nodes {
+                let mut candidates: Vec<XPathNode> = self.axis(step.axis, node).into_iter()
+                                                         .filter(|&candidate| self.node_test(step, candidate))
+                                                         .collect();
+                // Positions in predicates go in the direction of the axis
+                for predicate in &step.predicates {
+                    candidates = self.filter(candidates, predicate);
+                }
+                selected.extend(candidates);
+            }
+            self.sort(&mut selected);
+            nodes = selected;
+        }
+        nodes
+    }
+
+    /// Leaves out the context nodes (in document order) whose nodes along `axis` are all along it
+    /// from another one too, so `//p/following-sibling::p` over many siblings only walks them once.
+    /// Without predicates, which count positions from each context node, the result is the same.
+    fn covering(&self, axis: Axis, nodes: Vec<XPathNode>) -> Vec<XPathNode> {
+        let document = self.document;
+        let element = |node: XPathNode| match node {
+            XPathNode::Node(id) | XPathNode::Attribute(id, _) => id,
+        };
+        match axis {
+            // The first sibling has the following siblings of the others, the last the preceding
+            Axis::FollowingSibling | Axis::PrecedingSibling => {
+                let mut parents = HashSet::new();
+                let covering = |node: &XPathNode| match *node {
+                    XPathNode::Node(id) => document.parent(id).is_some_and(|parent| parents.insert(parent)),
+                    XPathNode::Attribute(..) => false,
+                };
+                match axis {
+                    Axis::FollowingSibling => nodes.into_iter().filter(covering).collect(),
+                    _ => nodes.into_iter().rev().filter(covering).collect(),
+                }
+            }
+            // Everything under a node is under its ancestors too
+            Axis::Descendant | Axis::DescendantOrSelf => {
+                let mut kept = HashSet::new();
+                nodes.into_iter().filter(|&node| match node {
+                    XPathNode::Node(id) => {
+                        let covered = document.ancestors(id).any(|ancestor| kept.contains(&ancestor));
+                        !covered && kept.insert(id)
+                    }
+                    XPathNode::Attribute(..) => axis == Axis::DescendantOrSelf,
+                }).collect()
+            }
+            // What follows a node is everything after it in its tree, less what's under it: the
+            // nodes of later context nodes outside it, and fewer than those of ones inside it
+            Axis::Following => {
+                let mut kept: HashMap<XPathNode, XPathNode> = HashMap::new();
+                for node in nodes {
+                    let root = self.root(node);
+                    match kept.get(&root) {
+                        Some(&XPathNode::Node(earlier)) => {
+                            let id = element(node);
+                            if id == earlier || document.ancestors(id).any(|ancestor| ancestor == earlier) {
+                                kept.insert(root, node);
+                            }
+                        }
+                        Some(XPathNode::Attribute(..)) => {}
+                        None => {
+                            kept.insert(root, node);
+                        }
+                    }
+                }
+                kept.into_values().collect()
+            }
+            // What precedes the last node in a tree precedes it as well as the earlier ones
+            Axis::Preceding => {
+                let mut roots = HashSet::new();
+                nodes.into_iter().rev().filter(|&node| roots.insert(self.root(node))).collect()
+            }
+            _ => nodes,
+        }
+    }
+
+    fn filter(&self, nodes: Vec<XPathNode>, predicate: &Expr) -> Vec<XPathNode> {
+        let size = nodes.len();
+        nodes.into_iter().enumerate().filter(|&(index, node)| {
+            match self.eval(predicate, Context { node, position: index + 1, size }) {
+                // A number is short for a position
+                Value::Number(number) => number == (index + 1) as f64,
+                value => value.boolean(),
+            }
+        }).map(|(_, node)| node).collect()
+    }
+
+    /// The nodes along an axis, in the axis' direction: reverse document order for the ancestor
+    /// and preceding axes, document order for the others.
+    fn axis(&self, axis: Axis, node: XPathNode) -> Vec<XPathNode> {
+        let document = self.document;
+        let (id, is_attribute) = match node {
+            XPathNode::Node(id) => (id, false),
+            XPathNode::Attribute(id, _) => (id, true),
+        };
+        let visible = |id: &NodeId| !matches!(document[*id].data, NodeData::Doctype(_));
+        let nodes = |ids: &mut dyn Iterator<Item = NodeId>| -> Vec<XPathNode> {
+            ids.filter(visible).map(XPathNode::Node).collect()
+        };
+        let following_siblings = |id: NodeId| std::iter::successors(document[id].next_sibling, |&sibling| document[sibling].next_sibling);
+        let preceding_siblings = |id: NodeId| std::iter::successors(document[id].prev_sibling, |&sibling| document[sibling].prev_sibling);
+        let mut result = Vec::new();
+        match axis {
+            Axis::Self_ => result.push(node),
+            Axis::Namespace => {}
+            Axis::Attribute => {
+                if let (false, Some(element)) = (is_attribute, document.element(id)) {
+                    result.extend((0..element.attributes().count()).map(|index| XPathNode::Attribute(id, index)));
+                }
+            }
+            Axis::Child if !is_attribute => result = nodes(&mut document.children(id)),
+            Axis::Descendant if !is_attribute => result = nodes(&mut document.descendants(id).skip(1)),
+            Axis::DescendantOrSelf if !is_attribute => result = nodes(&mut document.descendants(id)),
+            Axis::DescendantOrSelf => result.push(node),
+            Axis::Child | Axis::Descendant => {}
+            Axis::Parent | Axis::Ancestor | Axis::AncestorOrSelf => {
+                if axis == Axis::AncestorOrSelf {
+                    result.push(node);
+                }
+                let mut ancestors = document.ancestors(id);
+                let parent = if is_attribute { Some(id) } else { ancestors.next() };
+                result.extend(parent.map(XPathNode::Node));
+                if axis != Axis::Parent {
+                    result.extend(ancestors.map(XPathNode::Node));
+                }
+            }
+            Axis::FollowingSibling if !is_attribute => result = nodes(&mut following_siblings(id)),
+            Axis::PrecedingSibling if !is_attribute => result = nodes(&mut preceding_siblings(id)),
+            Axis::FollowingSibling | Axis::PrecedingSibling => {}
+            Axis::Following => {
+                // An attribute's element comes before it, but the element's children don't
+                if is_attribute {
+                    result = nodes(&mut document.descendants(id).skip(1));
+                }
+                let mut current = Some(id);
+                while let Some(ancestor) = current {
+                    for sibling in following_siblings(ancestor) {
+                        result.extend(nodes(&mut document.descendants(sibling)));
+                    }
+                    current = document.parent(ancestor);
+                }
+            }
+            Axis::Preceding => {
+                let mut current = Some(id);
+                while let Some(ancestor) = current {
+                    for sibling in preceding_siblings(ancestor) {
+                        let mut subtree = nodes(&mut document.descendants(sibling));
+                        subtree.reverse();
+                        result.extend(subtree);
+                    }
+                    current = document.parent(ancestor);
+                }
+            }
+        }
+        result
+    }
+
+    fn node_test(&self, step: &Step, node: XPathNode) -> bool {
+        let raw_html = self.document.raw_html();
+        match (&step.test, node) {
+            (NodeTest::Node, _) => true,
+            (NodeTest::ProcessingInstruction, _) => false,
+            (NodeTest::Text, XPathNode::Node(id)) => matches!(self.document[id].data, NodeData::Text(_)),
+            (NodeTest::Comment, XPathNode::Node(id)) => matches!(self.document[id].data, NodeData::Comment(_)),
+            (NodeTest::Text | NodeTest::Comment, XPathNode::Attribute(..)) => false,
+            // The attribute axis is the only one whose principal node type is the attribute
+            (NodeTest::Name(test), XPathNode::Attribute(id, index)) if step.axis == Axis::Attribute => {
+                let attribute = match attribute(self.document, id, index) {
+                    Some(attribute) => attribute,
+                    None => return false,
+                };
+                match test.namespace {
+                    Some(namespace) => {
+                        let name = attribute.qualified_name(raw_html);
+                        name.namespace == Some(namespace) &&
+                        test.local.as_ref().is_none_or(|local| name.local.eq_ignore_ascii_case(local))
+                    }
+                    None => test.local.as_ref().is_none_or(|local| attribute.name(raw_html).eq_ignore_ascii_case(local)),
+                }
+            }
+            (NodeTest::Name(test), XPathNode::Node(id)) if step.axis != Axis::Attribute => {
+                self.document.element(id).is_some_and(|element| self.element_matches(test, element))
+            }
+            (NodeTest::Name(_), _) => false,
+        }
+    }
+
+    fn element_matches(&self, test: &NameTest, element: &Element) -> bool {
+        if test.namespace.is_some_and(|namespace| namespace != element.namespace) {
+            return false;
+        }
+        let local = match &test.local {
+            Some(local) => local,
+            None => return true,
+        };
+        if element.namespace == Namespace::Html {
+            element.tag == test.tag &&
+            (element.tag != TagID::Undef || element.name(self.document.raw_html()).eq_ignore_ascii_case(local))
+        } else {
+            element.name(self.document.raw_html()) == local.as_str()
+        }
+    }
+
+    /// Compares two values the way `=`, `!=`, `<` and friends do. Node-sets compare true if any
+    /// of their nodes does.
+    fn compare(&self, comparison: Comparison, left: &Value, right: &Value) -> bool {
+        let document = self.document;
+        let strings = |nodes: &[XPathNode]| -> Vec<Value> {
+            nodes.iter().map(|&node| Value::String(string_value(document, node))).collect()
+        };
+        match (left, right) {
+            (Value::NodeSet(left), Value::NodeSet(right)) => {
+                let right = strings(right);
+                strings(left).iter().any(|left| right.iter().any(|right| self.compare_atoms(comparison, left, right)))
+            }
+            (Value::NodeSet(nodes), Value::Boolean(_)) => {
+                self.compare_atoms(comparison, &Value::Boolean(!nodes.is_empty()), right)
+            }
+            (Value::Boolean(_), Value::NodeSet(nodes)) => {
+                self.compare_atoms(comparison, left, &Value::Boolean(!nodes.is_empty()))
+            }
+            (Value::NodeSet(nodes), _) => strings(nodes).iter().any(|left| self.compare_atoms(comparison, left, right)),
+            (_, Value::NodeSet(nodes)) => strings(nodes).iter().any(|right| self.compare_atoms(comparison, left, right)),
+            _ => self.compare_atoms(comparison, left, right),
+        }
+    }
+
+    fn compare_atoms(&self, comparison: Comparison, left: &Value, right: &Value) -> bool {
+        let document = self.document;
+        let equal = || match (left, right) {
+            (Value::Boolean(_), _) | (_, Value::Boolean(_)) => left.boolean() == right.boolean(),
+            (Value::Number(_), _) | (_, Value::Number(_)) => left.number(document) == right.number(document),
+            _ => left.string(document) == right.string(document),
+        };
+        let (left, right) = (left.number(document), right.number(document));
+        match comparison {
+            Comparison::Equal => equal(),
+            Comparison::NotEqual => !equal(),
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+
+    fn call(&self, function: Function, arguments: &[Expr], context: Context) -> Value {
+        let document = self.document;
+        let argument = |index: usize| self.eval(&arguments[index], context);
+        let string = |index: usize| match arguments.get(index) {
+            Some(argument) => self.eval(argument, context).string(document),
+            None => string_value(document, context.node),
+        };
+        let number = |index: usize| argument(index).number(document);
+        // The first node of the argument, or the context node without one
+        let first_node = || match arguments.first() {
+            Some(argument) => self.nodes(argument, context).first().copied(),
+            None => Some(context.node),
+        };
+        match function {
+            Function::Last => Value::Number(context.size as f64),
+            Function::Position => Value::Number(context.position as f64),
+            Function::Count => Value::Number(self.nodes(&arguments[0], context).len() as f64),
+            Function::Id => Value::NodeSet(self.id(argument(0), context)),
+            Function::LocalName => Value::String(first_node().map_or_else(String::new, |node| self.name(node, true))),
+            Function::Name => Value::String(first_node().map_or_else(String::new, |node| self.name(node, false))),
+            Function::NamespaceUri => {
+                let namespace = first_node().and_then(|node| match node {
+                    XPathNode::Node(id) => document.element(id).map(|element| element.namespace),
+                    XPathNode::Attribute(id, index) => {
+                        attribute(document, id, index)?.qualified_name(document.raw_html()).namespace
+                    }
+                });
+                Value::String(namespace.map_or("", Namespace::url).to_string())
+            }
+            Function::String => Value::String(string(0)),
+            Function::Concat => Value::String((0..arguments.len()).map(string).collect()),
+            Function::StartsWith => Value::Boolean(string(0).starts_with(&string(1))),
+            Function::Contains => Value::Boolean(string(0).contains(&string(1))),
+            Function::SubstringBefore => {
+                let (haystack, needle) = (string(0), string(1));
+                Value::String(haystack.find(&needle).map_or("", |index| &haystack[..index]).to_string())
+            }
+            Function::SubstringAfter => {
+                let (haystack, needle) = (string(0), string(1));
+                Value::String(haystack.find(&needle).map_or("", |index| &haystack[index + needle.len()..]).to_string())
+            }
+            Function::Substring => {
+                // Characters at positions p with round(start) <= p < round(start) + round(length)
+                let first = round(number(1));
+                let last = if arguments.len() == 3 { first + round(number(2)) } else { f64::INFINITY };
+                Value::String(string(0).chars().enumerate().filter(|&(index, _)| {
+                    let position = (index + 1) as f64;
+                    position >= first && position < last
+                }).map(|(_, c)| c).collect())
+            }
+            Function::StringLength => Value::Number(string(0).chars().count() as f64),
+            Function::NormalizeSpace => {
+                Value::String(string(0).split(is_xml_whitespace).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" "))
+            }
+            Function::Translate => {
+                let (from, to): (Vec<char>, Vec<char>) = (string(1).chars().collect(), string(2).chars().collect());
+                Value::String(string(0).chars().filter_map(|c| match from.iter().position(|&from| from == c) {
+                    Some(index) => to.get(index).copied(),
+                    None => Some(c),
+                }).collect())
+            }
+            Function::Boolean => Value::Boolean(argument(0).boolean()),
+            Function::Not => Value::Boolean(!argument(0).boolean()),
+            Function::True => Value::Boolean(true),
+            Function::False => Value::Boolean(false),
+            Function::Lang => Value::Boolean(self.lang(&string(0), context.node)),
+            Function::Number => Value::Number(match arguments.first() {
+                Some(_) => number(0),
+                None => parse_number(&string_value(document, context.node)),
+            }),
+            Function::Sum => Value::Number(self.nodes(&arguments[0], context).into_iter()
+                                               .map(|node| parse_number(&string_value(document, node)))
+                                               .sum()),
+            Function::Floor => Value::Number(number(0).floor()),
+            Function::Ceiling => Value::Number(number(0).ceil()),
+            Function::Round => Value::Number(round(number(0))),
+        }
+    }
+
+    /// The elements with one of the whitespace-separated ids in `ids`, or in the string values
+    /// of a node-set.
+    fn id(&self, ids: Value, context: Context) -> Vec<XPathNode> {
+        let document = self.document;
+        let ids = match ids {
+            Value::NodeSet(nodes) => nodes.into_iter().map(|node| string_value(document, node)).collect::<Vec<_>>().join(" "),
+            value => value.string(document),
+        };
+        let ids: Vec<&str> = ids.split(is_xml_whitespace).filter(|id| !id.is_empty()).collect();
+        let root = match self.root(context.node) {
+            XPathNode::Node(root) | XPathNode::Attribute(root, _) => root,
+        };
+        document.descendants(root).filter(|&id| {
+            document.element(id).and_then(|element| element.attribute(document.raw_html(), "id"))
+                                .is_some_and(|value| ids.contains(&value.as_ref()))
+        }).map(XPathNode::Node).collect()
+    }
+
+    fn name(&self, node: XPathNode, local: bool) -> String {
+        let raw_html = self.document.raw_html();
+        match node {
+            XPathNode::Node(id) => self.document.element(id).map_or_else(String::new, |element| {
+                element.name(raw_html).into_owned()
+            }),
+            XPathNode::Attribute(id, index) => attribute(self.document, id, index).map_or_else(String::new, |attribute| {
+                let name = attribute.qualified_name(raw_html);
+                match name.prefix {
+                    Some(prefix) if !local => format!("{}:{}", prefix, name.local),
+                    _ => name.local.into_owned(),
+                }
+            }),
+        }
+    }
+
+    /// Whether the language of `node`, from the nearest `xml:lang` or `lang` attribute, is
+    /// `language` or one of its sublanguages.
+    fn lang(&self, language: &str, node: XPathNode) -> bool {
+        let document = self.document;
+        let id = match node {
+            XPathNode::Node(id) | XPathNode::Attribute(id, _) => id,
+        };
+        let declared = std::iter::once(id).chain(document.ancestors(id)).find_map(|id| {
+            let element = document.element(id)?;
+            element.attribute(document.raw_html(), "xml:lang").or_else(|| element.attribute(document.raw_html(), "lang"))
+        });
+        declared.is_some_and(|declared| {
+            declared.eq_ignore_ascii_case(language) ||
+            declared.get(..language.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(language)) &&
+            declared[language.len()..].starts_with('-')
+        })
+    }
+}
+
+fn attribute<'d>(document: &'d Document, id: NodeId, index: usize) -> Option<crate::dom::AttributeRef<'d>> {
+    document.element(id)?.attributes().nth(index)
+}
+
+/// XPath's round(): to the nearest integer, halves towards positive infinity.
+fn round(number: f64) -> f64 {
+    if number.is_nan() || number.is_infinite() {
+        number
+    } else if (-0.5..0.0).contains(&number) {
+        -0.0
+    } else {
+        (number + 0.5).floor()
+    }
+}
+
+/// A string as a number: optional whitespace, an optional minus sign, and digits with an
+/// optional decimal point. Anything else is NaN.
+fn parse_number(string: &str) -> f64 {
+    let trimmed = string.trim_matches(is_xml_whitespace);
+    let digits = trimmed.strip_prefix('-').unwrap_or(trimmed);
+    let valid = !digits.is_empty() && digits != "." && digits.bytes().filter(|&byte| byte == b'.').count() <= 1 &&
+                digits.bytes().all(|byte| byte.is_ascii_digit() || byte == b'.');
+    if valid {
+        trimmed.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+/// A number as a string: integers without a decimal point, and never an exponent.
+fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        "NaN".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if number == 0.0 {
+        "0".to_string()
+    } else {
+        number.to_string()
+    }
+}
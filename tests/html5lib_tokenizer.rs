@@ -0,0 +1,303 @@
+// Runs the html5lib-tests tokenizer suite (tests/html5lib/tokenizer/*.test, and the project's own
+// cases in tests/html5lib/local/tokenizer/) through Tokenizer.
+//
+// Tests listed in tests/html5lib/known-failures.txt are expected to fail. Any other failure, or
+// a listed test that starts passing, fails the run so the list stays accurate.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use fasthtml::tokenizer::{InitialState, Tokenizer};
+use fasthtml::tokens::{Flags, TagID, Token};
+use serde_json::{json, Value};
+
+// The upstream files, vendored unmodified (see tests/html5lib/README.md), and this project's own
+// cases, whose families are prefixed with "local/"
+const UPSTREAM_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/tokenizer");
+const LOCAL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/local/tokenizer");
+const KNOWN_FAILURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/known-failures.txt");
+
+struct TestCase {
+    id: String,
+    family: String,
+    input: String,
+    last_start_tag: Option<String>,
+    output: Vec<Value>,
+    errors: Vec<String>,
+}
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skip,
+}
+
+/// html5lib strings with `doubleEscaped` set have their `\uXXXX` escapes escaped once more.
+fn unescape(text: &str) -> Option<String> {
+    let mut units = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&'u') {
+            chars.next();
+            let hex: String = chars.by_ref().take(4).collect();
+            units.push(u16::from_str_radix(&hex, 16).ok()?);
+        } else {
+            let mut buffer = [0u16; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buffer));
+        }
+    }
+    // Lone surrogates can't be put in a &str, so such tests are skipped
+    String::from_utf16(&units).ok()
+}
+
+fn unescape_value(value: &Value) -> Option<Value> {
+    Some(match value {
+        Value::String(text) => Value::String(unescape(text)?),
+        Value::Array(items) => Value::Array(items.iter().map(unescape_value).collect::<Option<_>>()?),
+        Value::Object(map) => {
+            let mut unescaped = serde_json::Map::new();
+            for (key, value) in map {
+                unescaped.insert(unescape(key)?, unescape_value(value)?);
+            }
+            Value::Object(unescaped)
+        }
+        other => other.clone(),
+    })
+}
+
+/// html5lib allows a run of characters to be split over several "Character" entries.
+fn merge_characters(output: Vec<Value>) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::with_capacity(output.len());
+    for token in output {
+        if token[0] == "Character" {
+            if let Some(last) = merged.last_mut() {
+                if last[0] == "Character" {
+                    let text = format!("{}{}", last[1].as_str().unwrap(), token[1].as_str().unwrap());
+                    last[1] = Value::String(text);
+                    continue;
+                }
+            }
+        }
+        merged.push(token);
+    }
+    merged
+}
+
+fn lowercase(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+fn to_html5lib(token: &Token, raw: &str) -> Option<Value> {
+    let text = token.text(raw);
+    let attributes = token.attributes.as_deref().unwrap_or(&[]);
+    match token.token_id {
+        TagID::EndOfFile | TagID::EmTrivia => None,
+        TagID::Text => Some(json!(["Character", text])),
+        TagID::EmComment => Some(json!(["Comment", text])),
+        TagID::EmDoctype => {
+            let name = if text.is_empty() { Value::Null } else { Value::String(lowercase(&text)) };
+            let mut public = Value::Null;
+            let mut system = Value::Null;
+            for attribute in attributes {
+                let identifier = Value::String(attribute.value(token, raw).into_owned());
+                if attribute.name(token, raw).eq_ignore_ascii_case("public") {
+                    public = identifier;
+                } else {
+                    system = identifier;
+                }
+            }
+            let correct = token.flags & Flags::ForceQuirks as u8 == 0;
+            Some(json!(["DOCTYPE", name, public, system, correct]))
+        }
+        _ if token.flags & Flags::Close as u8 != 0 => Some(json!(["EndTag", lowercase(&text)])),
+        _ => {
+            let mut map = serde_json::Map::new();
+            for attribute in attributes {
+                map.insert(lowercase(attribute.name(token, raw)),
+                           Value::String(attribute.value(token, raw).into_owned()));
+            }
+            let mut start_tag = vec![json!("StartTag"), json!(lowercase(&text)), Value::Object(map)];
+            if token.flags & Flags::CloseSelf as u8 != 0 {
+                start_tag.push(json!(true));
+            }
+            Some(Value::Array(start_tag))
+        }
+    }
+}
+
+fn initial_state(name: &str) -> Option<InitialState> {
+    match name {
+        "Data state" => Some(InitialState::Data),
+        "RCDATA state" => Some(InitialState::Rcdata),
+        "RAWTEXT state" => Some(InitialState::Rawtext),
+        "Script data state" => Some(InitialState::ScriptData),
+        "PLAINTEXT state" => Some(InitialState::Plaintext),
+        "CDATA section state" => Some(InitialState::CdataSection),
+        _ => None,
+    }
+}
+
+fn tokenize<'a>(case: &'a TestCase, state: InitialState, lossless: bool) -> Tokenizer<'a> {
+    let mut tokenizer = Tokenizer::new(&case.input);
+    tokenizer.set_state(state);
+    tokenizer.set_lossless(lossless);
+    if let Some(last_start_tag) = &case.last_start_tag {
+        tokenizer.set_last_start_tag(last_start_tag);
+    }
+    tokenizer.tokenize();
+    tokenizer
+}
+
+fn run(case: &TestCase, state_name: &str) -> Outcome {
+    let state = match initial_state(state_name) {
+        Some(state) => state,
+        None => return Outcome::Skip,
+    };
+
+    let tokenizer = tokenize(case, state, false);
+
+    let output = merge_characters(tokenizer.tokens.iter()
+                                                  .filter_map(|token| to_html5lib(token, &case.input))
+                                                  .collect());
+    let errors: Vec<String> = tokenizer.errors.iter().map(|error| error.kind.code().to_string()).collect();
+
+    if output != case.output {
+        Outcome::Fail(format!("expected {}\n      got {}", Value::Array(case.output.clone()),
+                              Value::Array(output)))
+    } else if errors != case.errors {
+        Outcome::Fail(format!("expected errors {:?}\n      got {:?}", case.errors, errors))
+    } else {
+        // Every test doubles as a check that lossless tokens add up to the input
+        let lossless = tokenize(case, state, true);
+        let spans: String = lossless.tokens.iter()
+                                           .map(|token| &case.input[token.start as usize..token.end as usize])
+                                           .collect();
+        if spans != case.input {
+            Outcome::Fail(format!("lossless tokens add up to {:?}", spans))
+        } else {
+            Outcome::Pass
+        }
+    }
+}
+
+fn load_cases(path: &Path, prefix: &str) -> Vec<(TestCase, Vec<String>)> {
+    let file_name = format!("{}{}", prefix, path.file_stem().unwrap().to_string_lossy());
+    let suite: Value = serde_json::from_str(&fs::read_to_string(path).unwrap())
+        .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+
+    let mut cases = Vec::new();
+    for test in suite["tests"].as_array().into_iter().flatten() {
+        let description = test["description"].as_str().unwrap_or("");
+        let mut test = test.clone();
+        if test["doubleEscaped"] == true {
+            match unescape_value(&test) {
+                Some(unescaped) => test = unescaped,
+                None => continue,
+            }
+        }
+
+        let initial_states = match test["initialStates"].as_array() {
+            Some(states) => states.iter().map(|state| state.as_str().unwrap().to_string()).collect(),
+            None => vec!["Data state".to_string()],
+        };
+        let output = merge_characters(test["output"].as_array().cloned().unwrap_or_default());
+        let errors = test["errors"].as_array()
+                                   .into_iter()
+                                   .flatten()
+                                   .map(|error| error["code"].as_str().unwrap().to_string())
+                                   .collect();
+
+        cases.push((TestCase {
+            id: format!("{}: {}", file_name, description),
+            family: file_name.clone(),
+            input: test["input"].as_str().unwrap().to_string(),
+            last_start_tag: test["lastStartTag"].as_str().map(String::from),
+            output,
+            errors,
+        }, initial_states));
+    }
+    cases
+}
+
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+fn run_suite(dir: &str, prefix: &str) {
+    let known_failures: HashSet<String> = fs::read_to_string(KNOWN_FAILURES)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    let entries = fs::read_dir(dir).unwrap_or_else(|_| panic!("{} is missing, see tests/html5lib/README.md", dir));
+    let mut paths: Vec<_> = entries.map(|entry| entry.unwrap().path())
+                                   .filter(|path| path.extension().is_some_and(|ext| ext == "test"))
+                                   .collect();
+    paths.sort();
+
+    let mut families: BTreeMap<String, Summary> = BTreeMap::new();
+    let mut states: BTreeMap<String, Summary> = BTreeMap::new();
+    let mut unexpected = Vec::new();
+
+    for path in paths {
+        for (case, initial_states) in load_cases(&path, prefix) {
+            for initial_state in initial_states {
+                let id = format!("{} [{}]", case.id, initial_state);
+                let outcome = run(&case, &initial_state);
+                let family = families.entry(case.family.clone()).or_default();
+                let state = states.entry(initial_state.clone()).or_default();
+                match outcome {
+                    Outcome::Pass => {
+                        family.passed += 1;
+                        state.passed += 1;
+                        if known_failures.contains(&id) {
+                            unexpected.push(format!("{}\n      passes but is listed as a known failure", id));
+                        }
+                    }
+                    Outcome::Fail(reason) => {
+                        family.failed += 1;
+                        state.failed += 1;
+                        if !known_failures.contains(&id) {
+                            unexpected.push(format!("{}\n      {}", id, reason));
+                        }
+                    }
+                    Outcome::Skip => {
+                        family.skipped += 1;
+                        state.skipped += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{:<40} {:>7} {:>7} {:>7}", "", "passed", "failed", "skipped");
+    for (name, summary) in families.iter() {
+        println!("{:<40} {:>7} {:>7} {:>7}", name, summary.passed, summary.failed, summary.skipped);
+    }
+    println!();
+    for (name, summary) in states.iter() {
+        println!("{:<40} {:>7} {:>7} {:>7}", name, summary.passed, summary.failed, summary.skipped);
+    }
+
+    if !unexpected.is_empty() {
+        panic!("{} unexpected result(s):\n  {}", unexpected.len(), unexpected.join("\n  "));
+    }
+}
+
+#[test]
+#[ignore = "html5lib-tests isn't vendored yet, see tests/html5lib/README.md"]
+fn html5lib_tokenizer() {
+    run_suite(UPSTREAM_DIR, "");
+}
+
+#[test]
+fn html5lib_tokenizer_local() {
+    run_suite(LOCAL_DIR, "local/");
+}
@@ -0,0 +1,251 @@
+// Runs the html5lib-tests tree construction suite (tests/html5lib/tree-construction/*.dat, and
+// the project's own cases in tests/html5lib/local/tree-construction/) through
+// the tree builder and compares the trees in the suite's "| <html>" format.
+//
+// Tests listed in tests/html5lib/tree-construction-known-failures.txt are expected to fail. Any
+// other failure, or a listed test that starts passing, fails the run so the list stays accurate.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use fasthtml::dom::{Document, NodeData, NodeId};
+use fasthtml::foreign::Namespace;
+use fasthtml::tokens::ASCII_TO_TAG_ID;
+
+// The upstream files, vendored unmodified (see tests/html5lib/README.md), and this project's own
+// cases, whose files are prefixed with "local/"
+const UPSTREAM_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/tree-construction");
+const LOCAL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/local/tree-construction");
+const KNOWN_FAILURES: &str = concat!(env!("CARGO_MANIFEST_DIR"),
+                                     "/tests/html5lib/tree-construction-known-failures.txt");
+
+const SECTIONS: &[&str] = &["#data", "#errors", "#new-errors", "#document-fragment", "#script-on", "#script-off",
+                            "#document"];
+
+struct TestCase {
+    id: String,
+    family: String,
+    data: String,
+    fragment_context: Option<String>,
+    scripting: Option<bool>,
+    document: String,
+}
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skip,
+}
+
+fn namespace_prefix(namespace: Namespace) -> &'static str {
+    match namespace {
+        Namespace::Html => "html",
+        Namespace::Svg => "svg",
+        Namespace::MathMl => "math",
+        Namespace::XLink => "xlink",
+        Namespace::Xml => "xml",
+        Namespace::Xmlns => "xmlns",
+    }
+}
+
+fn serialize_node(document: &Document, id: NodeId, depth: usize, out: &mut String) {
+    let raw = document.raw_html();
+    let indent = format!("| {}", "  ".repeat(depth));
+    match &document[id].data {
+        NodeData::Document => {}
+        NodeData::TemplateContents(_) => out.push_str(&format!("{}content\n", indent)),
+        NodeData::Doctype(token) => {
+            let name = token.text(raw).to_ascii_lowercase();
+            let mut public = None;
+            let mut system = None;
+            for attribute in token.attributes.as_deref().unwrap_or(&[]) {
+                let identifier = attribute.value(token, raw).into_owned();
+                if attribute.name(token, raw).eq_ignore_ascii_case("public") {
+                    public = Some(identifier);
+                } else {
+                    system = Some(identifier);
+                }
+            }
+            if public.is_some() || system.is_some() {
+                out.push_str(&format!("{}<!DOCTYPE {} \"{}\" \"{}\">\n", indent, name,
+                                      public.unwrap_or_default(), system.unwrap_or_default()));
+            } else {
+                out.push_str(&format!("{}<!DOCTYPE {}>\n", indent, name));
+            }
+        }
+        NodeData::Element(element) => {
+            let name = element.name(raw);
+            match element.namespace {
+                Namespace::Html => out.push_str(&format!("{}<{}>\n", indent, name)),
+                namespace => out.push_str(&format!("{}<{} {}>\n", indent, namespace_prefix(namespace), name)),
+            }
+            let mut attributes: Vec<(String, String)> = element.attributes().map(|attribute| {
+                let name = attribute.qualified_name(raw);
+                let name = match name.namespace {
+                    Some(namespace) => format!("{} {}", namespace_prefix(namespace), name.local),
+                    None => name.local.into_owned(),
+                };
+                (name, attribute.value(raw).into_owned())
+            }).collect();
+            attributes.sort();
+            for (name, value) in attributes {
+                out.push_str(&format!("{}  {}=\"{}\"\n", indent, name, value));
+            }
+        }
+        NodeData::Text(_) => out.push_str(&format!("{}\"{}\"\n", indent, document.text(id))),
+        NodeData::Comment(token) => out.push_str(&format!("{}<!-- {} -->\n", indent, token.text(raw))),
+    }
+    let depth = if id == document.root() { depth } else { depth + 1 };
+    if let Some(contents) = document.template_contents(id) {
+        serialize_node(document, contents, depth, out);
+    }
+    for child in document.children(id) {
+        serialize_node(document, child, depth, out);
+    }
+}
+
+fn serialize(document: &Document) -> String {
+    let mut out = String::new();
+    serialize_node(document, document.root(), 0, &mut out);
+    out
+}
+
+fn run(case: &TestCase) -> Outcome {
+    // The builder always parses with scripting off
+    if case.scripting == Some(true) {
+        return Outcome::Skip;
+    }
+    let document = match &case.fragment_context {
+        // Only HTML context elements can be given, not "svg path" and the like
+        Some(context) => match ASCII_TO_TAG_ID.get(context.as_bytes()) {
+            Some(&context) => Document::parse_fragment(&case.data, context),
+            None => return Outcome::Skip,
+        },
+        None => Document::parse(&case.data),
+    };
+    let tree = serialize(&document);
+    if tree.trim_end() != case.document.trim_end() {
+        Outcome::Fail(format!("for {:?}\n    expected\n{}\n    got\n{}", case.data, case.document.trim_end(),
+                              tree.trim_end()))
+    } else {
+        Outcome::Pass
+    }
+}
+
+fn load_cases(path: &Path, prefix: &str) -> Vec<TestCase> {
+    let file_name = format!("{}{}", prefix, path.file_name().unwrap().to_string_lossy());
+    let family = format!("{}{}", prefix, path.file_stem().unwrap().to_string_lossy());
+    let contents = fs::read_to_string(path).unwrap();
+
+    let mut cases = Vec::new();
+    let mut sections: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut flush = |sections: &mut Vec<(String, Vec<&str>)>| {
+        if sections.is_empty() {
+            return;
+        }
+        let section = |name: &str| {
+            sections.iter().find(|(section, _)| section == name).map(|(_, lines)| lines.join("\n"))
+        };
+        cases.push(TestCase {
+            id: format!("{}: {}", file_name, cases.len() + 1),
+            family: family.clone(),
+            data: section("#data").unwrap_or_default(),
+            fragment_context: section("#document-fragment"),
+            scripting: if section("#script-on").is_some() {
+                Some(true)
+            } else if section("#script-off").is_some() {
+                Some(false)
+            } else {
+                None
+            },
+            document: section("#document").unwrap_or_default(),
+        });
+        sections.clear();
+    };
+
+    for line in contents.split('\n') {
+        if line == "#data" {
+            flush(&mut sections);
+        }
+        if SECTIONS.contains(&line) {
+            sections.push((line.to_string(), Vec::new()));
+        } else if let Some((_, lines)) = sections.last_mut() {
+            lines.push(line);
+        }
+    }
+    flush(&mut sections);
+
+    // The blank line between tests belongs to neither of them
+    for case in cases.iter_mut() {
+        case.document = case.document.trim_end_matches('\n').to_string();
+    }
+    cases
+}
+
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+fn run_suite(dir: &str, prefix: &str) {
+    let known_failures: HashSet<String> = fs::read_to_string(KNOWN_FAILURES)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    let entries = fs::read_dir(dir).unwrap_or_else(|_| panic!("{} is missing, see tests/html5lib/README.md", dir));
+    let mut paths: Vec<_> = entries.map(|entry| entry.unwrap().path())
+                                   .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+                                   .collect();
+    paths.sort();
+
+    let mut families: BTreeMap<String, Summary> = BTreeMap::new();
+    let mut unexpected = Vec::new();
+
+    for path in paths {
+        for case in load_cases(&path, prefix) {
+            let family = families.entry(case.family.clone()).or_default();
+            match run(&case) {
+                Outcome::Pass => {
+                    family.passed += 1;
+                    if known_failures.contains(&case.id) {
+                        unexpected.push(format!("{}\n      passes but is listed as a known failure", case.id));
+                    }
+                }
+                Outcome::Fail(reason) => {
+                    family.failed += 1;
+                    if !known_failures.contains(&case.id) {
+                        unexpected.push(format!("{}\n      {}", case.id, reason));
+                    }
+                }
+                Outcome::Skip => family.skipped += 1,
+            }
+        }
+    }
+
+    println!("{:<40} {:>7} {:>7} {:>7}", "", "passed", "failed", "skipped");
+    for (name, summary) in families.iter() {
+        println!("{:<40} {:>7} {:>7} {:>7}", name, summary.passed, summary.failed, summary.skipped);
+    }
+
+    if !unexpected.is_empty() {
+        panic!("{} unexpected result(s):\n  {}", unexpected.len(), unexpected.join("\n  "));
+    }
+}
+
+#[test]
+#[ignore = "html5lib-tests isn't vendored yet, see tests/html5lib/README.md"]
+fn html5lib_tree_construction() {
+    run_suite(UPSTREAM_DIR, "");
+}
+
+#[test]
+fn html5lib_tree_construction_local() {
+    run_suite(LOCAL_DIR, "local/");
+}
@@ -0,0 +1,103 @@
+// Line and column numbers of offsets, tokens and attributes.
+
+use fasthtml::lines::{LineCol, LineIndex};
+use fasthtml::tokenizer::Tokenizer;
+use fasthtml::tokens::TagID;
+
+fn at(line: u32, column: u32) -> LineCol {
+    LineCol { line, column }
+}
+
+#[test]
+fn line_ends() {
+    let index = LineIndex::new("a\nb\r\nc\rd");
+    assert_eq!(index.line_count(), 4);
+    assert_eq!(index.line_col(0), at(1, 1));
+    assert_eq!(index.line_col(1), at(1, 2));
+    assert_eq!(index.line_col(2), at(2, 1));
+    // Both bytes of "\r\n" belong to the line they end
+    assert_eq!(index.line_col(3), at(2, 2));
+    assert_eq!(index.line_col(4), at(2, 3));
+    assert_eq!(index.line_col(5), at(3, 1));
+    assert_eq!(index.line_col(6), at(3, 2));
+    assert_eq!(index.line_col(7), at(4, 1));
+    assert_eq!(index.line_col(8), at(4, 2));
+
+    // "\n\r" is two line ends, not one
+    assert_eq!(LineIndex::new("a\n\rb").line_col(3), at(3, 1));
+}
+
+#[test]
+fn first_and_last_lines() {
+    let index = LineIndex::new("");
+    assert_eq!(index.line_count(), 1);
+    assert_eq!(index.line_col(0), at(1, 1));
+
+    let index = LineIndex::new("ab\n");
+    assert_eq!(index.line_count(), 2);
+    assert_eq!(index.line_col(3), at(2, 1));
+
+    let index = LineIndex::new("ab\ncd");
+    assert_eq!(index.line_col(5), at(2, 3));
+    // Past the end is the end
+    assert_eq!(index.line_col(999), at(2, 3));
+    assert_eq!(index.line_col_utf16(999), at(2, 3));
+}
+
+#[test]
+fn utf16_columns() {
+    // "é" is 2 bytes and 1 UTF-16 unit, "😀" 4 bytes and a surrogate pair
+    let index = LineIndex::new("é<p>\n😀x<b>");
+    assert_eq!(index.line_col(2), at(1, 3));
+    assert_eq!(index.line_col_utf16(2), at(1, 2));
+    assert_eq!(index.line_col(10), at(2, 5));
+    assert_eq!(index.line_col_utf16(10), at(2, 3));
+
+    // Offsets inside a character are where the character starts
+    assert_eq!(index.line_col(1), at(1, 1));
+    assert_eq!(index.line_col_utf16(1), at(1, 1));
+    assert_eq!(index.line_col(8), at(2, 1));
+    assert_eq!(index.line_col_utf16(8), at(2, 1));
+}
+
+#[test]
+fn ranges_across_lines() {
+    let html = "<!--a\nbé-->";
+    let mut tokenizer = Tokenizer::new(html);
+    tokenizer.tokenize();
+    let comment = &tokenizer.tokens[0];
+    assert_eq!(comment.token_id, TagID::EmComment);
+    let index = LineIndex::new(html);
+    assert_eq!(index.token(comment), at(1, 5)..at(2, 4));
+    assert_eq!(index.token_utf16(comment), at(1, 5)..at(2, 3));
+
+    let html = "<p title=\"a\nb😀\" hidden>";
+    let mut tokenizer = Tokenizer::new(html);
+    tokenizer.tokenize();
+    let p = &tokenizer.tokens[0];
+    let attributes = p.attributes.as_deref().unwrap();
+    let index = LineIndex::new(html);
+    // A value ends before its closing quote
+    assert_eq!(index.attribute(p, &attributes[0]), at(1, 4)..at(2, 6));
+    assert_eq!(index.attribute_utf16(p, &attributes[0]), at(1, 4)..at(2, 4));
+    // One without a value ends with its name
+    assert_eq!(index.attribute(p, &attributes[1]), at(2, 8)..at(2, 14));
+    assert_eq!(index.attribute_utf16(p, &attributes[1]), at(2, 6)..at(2, 12));
+}
+
+#[test]
+fn long_single_line() {
+    // Minified pages are one long line, counting it for every column takes seconds
+    let html = "<p class=\"é\">😀 text é</p>".repeat(8_000);
+    let mut tokenizer = Tokenizer::new(&html);
+    tokenizer.tokenize();
+    let index = LineIndex::new(&html);
+    let started = std::time::Instant::now();
+    let ranges: Vec<_> = tokenizer.tokens.iter().map(|token| index.token_utf16(token)).collect();
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+
+    for (token, range) in tokenizer.tokens.iter().zip(&ranges).step_by(997) {
+        let column = |offset: u32| html[..offset as usize].encode_utf16().count() as u32 + 1;
+        assert_eq!(*range, at(1, column(token.start))..at(1, column(token.end)));
+    }
+}
@@ -0,0 +1,161 @@
+// Rewrites documents with element and text handlers, and checks that whatever the handlers leave
+// alone comes out byte for byte as it went in.
+
+use std::cell::RefCell;
+use std::fs;
+
+use fasthtml::rewriter::{ContentType, Rewriter};
+use fasthtml::selectors::SelectorErrorKind;
+use fasthtml::tokens::TagID;
+
+const TEST_DIRS: &[&str] = &[concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/tree-construction"),
+                             concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/local/tree-construction")];
+
+fn rewrite<'h>(html: &str, setup: impl FnOnce(&mut Rewriter<'h>)) -> String {
+    let mut rewriter = Rewriter::new();
+    setup(&mut rewriter);
+    rewriter.rewrite(html)
+}
+
+#[test]
+fn rewriting_links() {
+    let html = "<p><A HREF='/a' class=x>a</A> <a href=\"http://other.example/b\" title=t>b</a> <a name=n>c</a></p>";
+    let out = rewrite(html, |rewriter| {
+        rewriter.on_element("a[href]", |element| {
+            let href = element.attribute("href").unwrap().into_owned();
+            if href.starts_with('/') {
+                element.set_attribute("href", &format!("https://proxy.example{}", href));
+            } else {
+                element.set_attribute("href", &format!("https://proxy.example/?url={}&x=\"1\"", href));
+                element.set_attribute("rel", "noopener");
+            }
+        }).unwrap();
+    });
+    assert_eq!(out, "<p><A HREF=\"https://proxy.example/a\" class=x>a</A> \
+                     <a href=\"https://proxy.example/?url=http://other.example/b&amp;x=&quot;1&quot;\" title=t rel=\"noopener\">b</a> \
+                     <a name=n>c</a></p>");
+}
+
+#[test]
+fn attributes() {
+    let out = rewrite("<img src=a.png alt='x' hidden/><br><input value=1 disabled>", |rewriter| {
+        rewriter.on_element("img", |element| {
+            assert_eq!(element.tag(), TagID::Img);
+            assert_eq!(element.name(), "img");
+            element.remove_attribute("ALT");
+            element.set_attribute("loading", "lazy");
+            assert!(!element.has_attribute("alt"));
+            assert_eq!(element.attribute("loading").as_deref(), Some("lazy"));
+            assert_eq!(element.attribute("src").as_deref(), Some("a.png"));
+        }).unwrap();
+        rewriter.on_element("input", |element| {
+            element.set_attribute("value", "2");
+            element.remove_attribute("missing");
+        }).unwrap();
+    });
+    assert_eq!(out, "<img src=a.png hidden loading=\"lazy\" /><br><input value=\"2\" disabled>");
+}
+
+#[test]
+fn inserting_content() {
+    let out = rewrite("<div><p>x</p><br></div>", |rewriter| {
+        rewriter.on_element("p", |element| {
+            element.before("<hr>", ContentType::Html);
+            element.after("<i>after</i>", ContentType::Html);
+            element.prepend("1 < ", ContentType::Text);
+            element.append("!", ContentType::Text);
+        }).unwrap();
+        rewriter.on_element("br", |element| {
+            element.before("[", ContentType::Text);
+            element.after("]", ContentType::Text);
+        }).unwrap();
+    });
+    assert_eq!(out, "<div><hr><p>1 &lt; x!</p><i>after</i>[<br>]</div>");
+}
+
+#[test]
+fn replacing_and_removing() {
+    let html = "<ul><li class=ad>buy <b>now</b><li>keep<li class=x>swap</ul><span>a<b>b</b></span><script>var x = '<p class=ad>';</script>";
+    let out = rewrite(html, |rewriter| {
+        rewriter.on_element(".ad", |element| element.remove()).unwrap();
+        rewriter.on_element("li.x", |element| element.replace("<li>swapped", ContentType::Html)).unwrap();
+        rewriter.on_element("span", |element| element.remove_and_keep_content()).unwrap();
+        rewriter.on_element("script", |element| element.set_inner_content("/* gone */", ContentType::Html)).unwrap();
+        // Never called: the <b> inside a removed element
+        rewriter.on_element("li b", |element| element.set_attribute("x", "y")).unwrap();
+    });
+    assert_eq!(out, "<ul><li>keep<li>swapped</ul>a<b>b</b><script>/* gone */</script>");
+}
+
+#[test]
+fn rewriting_text() {
+    let html = "<p id=a>Hello &amp; welcome</p><div>not <p>this one</p> here</div><style>a{}</style>";
+    let seen = RefCell::new(Vec::new());
+    let out = rewrite(html, |rewriter| {
+        rewriter.on_text("#a, style", |chunk| {
+            seen.borrow_mut().push((chunk.as_raw().to_string(), chunk.text().to_string()));
+            if chunk.text().contains('&') {
+                chunk.replace("Goodbye & farewell", ContentType::Text);
+                chunk.after("!", ContentType::Html);
+            }
+        }).unwrap();
+        rewriter.on_text("div", |chunk| {
+            if chunk.text() == " here" {
+                chunk.remove();
+            }
+        }).unwrap();
+    });
+    assert_eq!(out, "<p id=a>Goodbye &amp; farewell!</p><div>not <p>this one</p></div><style>a{}</style>");
+    assert_eq!(*seen.borrow(), [("Hello &amp; welcome".to_string(), "Hello & welcome".to_string()),
+                                ("a{}".to_string(), "a{}".to_string())]);
+}
+
+#[test]
+fn implied_and_missing_end_tags() {
+    let out = rewrite("<ul><li>one<li>two</ul><p>para<div>block", |rewriter| {
+        rewriter.on_element("li, p, div", |element| {
+            element.append("|", ContentType::Html);
+            element.after("$", ContentType::Html);
+        }).unwrap();
+    });
+    assert_eq!(out, "<ul><li>one|$<li>two|$</ul><p>para|$<div>block|$");
+}
+
+#[test]
+fn streaming_in_chunks() {
+    let html = "<!DOCTYPE html><title>t</title><a href=/x>x</a><!-- c -->";
+    let mut chunks = Vec::new();
+    let mut rewriter = Rewriter::new();
+    rewriter.on_element("a", |element| element.set_attribute("href", "/y")).unwrap();
+    rewriter.rewrite_with(html, |chunk| chunks.push(chunk.to_string()));
+    assert!(chunks.len() > 1);
+    assert_eq!(chunks.concat(), "<!DOCTYPE html><title>t</title><a href=\"/y\">x</a><!-- c -->");
+}
+
+#[test]
+fn selectors_must_be_streamable() {
+    let mut rewriter = Rewriter::new();
+    assert_eq!(rewriter.on_element("p + p", |_| {}).unwrap_err().kind, SelectorErrorKind::NotStreamable);
+    assert_eq!(rewriter.on_text("p:first-child", |_| {}).unwrap_err().kind, SelectorErrorKind::NotStreamable);
+}
+
+#[test]
+fn untouched_input_is_unchanged() {
+    let mut paths: Vec<_> = TEST_DIRS.iter().filter_map(|dir| fs::read_dir(dir).ok()).flatten()
+                                     .map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    let mut rewriter = Rewriter::new();
+    rewriter.on_element("*", |element| {
+        let _ = element.attribute("id");
+    }).unwrap();
+    rewriter.on_text("*", |chunk| {
+        let _ = chunk.text();
+    }).unwrap();
+    for path in paths {
+        let contents = fs::read_to_string(&path).unwrap();
+        for data in contents.split("#data\n").skip(1) {
+            let html = &data[..data.find("\n#").unwrap_or(data.len())];
+            assert_eq!(rewriter.rewrite(html), html);
+        }
+    }
+}
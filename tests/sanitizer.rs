@@ -0,0 +1,127 @@
+// Cleans hostile and ordinary fragments with the default policy and with custom ones, and checks
+// that cleaning what was cleaned changes nothing.
+
+use std::fs;
+
+use fasthtml::sanitizer::{clean, Sanitizer};
+use fasthtml::tokens::TagID;
+
+const TEST_DIRS: &[&str] = &[concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/tree-construction"),
+                             concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/local/tree-construction")];
+
+const CASES: &[(&str, &str)] = &[
+    ("<b>bold</b> <i>and</i> <u>more</u>", "<b>bold</b> <i>and</i> <u>more</u>"),
+    ("plain & <simple>", "plain &amp; "),
+    ("<p>unclosed<p>paragraphs", "<p>unclosed</p><p>paragraphs</p>"),
+    // Scripts and styles go with their content, other elements leave theirs behind
+    ("<script>alert(1)</script>x", "x"),
+    ("<style>body { display: none }</style>x", "x"),
+    ("<noscript><p title=\"</noscript><img src=x onerror=alert(1)>\"></noscript>", ""),
+    ("<form action=/x><input name=a><button>go</button></form>", "go"),
+    ("<custom-thing>inside</custom-thing>", "inside"),
+    ("<xmp><b>&amp;</xmp>", "&lt;b&gt;&amp;amp;"),
+    // Attributes
+    ("<img src=a.png onerror=alert(1) OnLoad=x alt=pic class=c>", "<img src=\"a.png\" alt=\"pic\">"),
+    ("<p title='a \"b\" <c>' id=x lang=en>t</p>", "<p title=\"a &quot;b&quot; &lt;c&gt;\" lang=\"en\">t</p>"),
+    ("<div style=\"color: red\">x</div>", "<div>x</div>"),
+    // URLs
+    ("<a href=\"https://example.com/\">x</a>", "<a href=\"https://example.com/\" rel=\"noopener noreferrer\">x</a>"),
+    ("<a href=\"/relative?q=1#f\" rel=nofollow>x</a>", "<a href=\"/relative?q=1#f\" rel=\"noopener noreferrer\">x</a>"),
+    ("<a href=\"javascript:alert(1)\">x</a>", "<a>x</a>"),
+    ("<a href=\" JaVaScRiPt:alert(1)\">x</a>", "<a>x</a>"),
+    ("<a href=\"java&#x09;script&colon;alert(1)\">x</a>", "<a>x</a>"),
+    ("<a href=\"&#1;javascript:alert(1)\">x</a>", "<a>x</a>"),
+    ("<a href=\"vbscript:msgbox\">x</a>", "<a>x</a>"),
+    ("<a href=\"data:text/html,<script>alert(1)</script>\">x</a>", "<a>x</a>"),
+    ("<a href=\"mailto:a@example.com\">x</a>", "<a href=\"mailto:a@example.com\" rel=\"noopener noreferrer\">x</a>"),
+    ("<img src=\"unknown:thing\"><img src=\"a/b:c\">", "<img><img src=\"a/b:c\">"),
+    ("<blockquote cite=\"javascript:x\">q</blockquote>", "<blockquote>q</blockquote>"),
+    // Foreign content and templates
+    ("<svg><a xlink:href=\"javascript:alert(1)\"><text>t</text></a><script>alert(1)</script></svg>", "t"),
+    ("<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>", "<table></table>"),
+    ("<template><b>inert</b></template>after", "after"),
+    // Comments
+    ("a<!-- <script>alert(1)</script> -->b", "ab"),
+    // Tables keep their structure
+    ("<table><tr><td colspan=2 onclick=x>cell</table>", "<table><tbody><tr><td colspan=\"2\">cell</td></tr></tbody></table>"),
+];
+
+#[test]
+fn default_policy() {
+    for (html, expected) in CASES {
+        assert_eq!(clean(html), *expected, "for {:?}", html);
+    }
+}
+
+#[test]
+fn custom_policies() {
+    let mut sanitizer = Sanitizer::empty();
+    assert_eq!(sanitizer.clean("<p><b>only</b> text<script>x</script></p>"), "only text");
+
+    sanitizer.tags([TagID::A, TagID::Paragraph, TagID::Template, TagID::Style])
+             .add_tag_attributes(TagID::A, ["HREF", "target"])
+             .add_generic_attributes(["class"])
+             .add_url_schemes(["https"])
+             .add_clean_content_tags([TagID::Script]);
+    assert_eq!(sanitizer.clean("<a href=\"https://x/\" target=_blank class=c>a</a><a href=\"/rel\">b</a>"),
+               "<a href=\"https://x/\" target=\"_blank\" class=\"c\">a</a><a>b</a>");
+    assert_eq!(sanitizer.clean("<template><p class=x onclick=y>t</p><i>i</i></template>"),
+               "<template><p class=\"x\">t</p>i</template>");
+    assert_eq!(sanitizer.clean("<style>a > b { color: red }</style><script>x</script>"),
+               "<style>a > b { color: red }</style>");
+
+    sanitizer.url_relative(true).link_rel(Some("nofollow")).strip_comments(false);
+    assert_eq!(sanitizer.clean("<a href=\"/rel\">b</a><!--c-->"), "<a href=\"/rel\" rel=\"nofollow\">b</a><!--c-->");
+    sanitizer.rm_url_schemes(["https"]).rm_tag_attributes(TagID::A, ["target"]).rm_generic_attributes(["class"]);
+    assert_eq!(sanitizer.clean("<a href=\"https://x/\" target=t class=c>a</a>"), "<a>a</a>");
+
+    // Script can't be allowed, and neither can handlers or javascript: URLs
+    sanitizer.add_tags([TagID::Script, TagID::Img])
+             .rm_clean_content_tags([TagID::Script])
+             .add_tag_attributes(TagID::Img, ["src", "onerror"])
+             .add_url_schemes(["javascript"]);
+    assert_eq!(sanitizer.clean("<script>alert(1)</script><img src=javascript:alert(1) onerror=alert(1)>"), "<img>");
+
+    // Content removed even when the element is allowed
+    sanitizer.add_clean_content_tags([TagID::Paragraph]);
+    assert_eq!(sanitizer.clean("<p>gone</p>kept"), "kept");
+    sanitizer.rm_tags([TagID::A]);
+    assert_eq!(sanitizer.clean("<a href=/x>unwrapped</a>"), "unwrapped");
+}
+
+#[test]
+fn srcset() {
+    let mut sanitizer = Sanitizer::new();
+    sanitizer.add_tag_attributes(TagID::Img, ["srcset"]);
+    assert_eq!(sanitizer.clean("<img srcset=\"a.png 1x, https://x/b.png 2x\">"),
+               "<img srcset=\"a.png 1x, https://x/b.png 2x\">");
+    assert_eq!(sanitizer.clean("<img srcset=\"a.png 1x, javascript:alert(1) 2x\">"), "<img>");
+}
+
+#[test]
+fn attributes_longer_than_64_kib() {
+    let value = "é".repeat(40_000);
+    let html = format!("<p title=\"{}\" class=a onclick=x>hi</p><a href=\"/{}\">x</a>", value, value);
+    let expected = format!("<p title=\"{}\">hi</p><a href=\"/{}\" rel=\"noopener noreferrer\">x</a>", value, value);
+    assert_eq!(clean(&html), expected);
+}
+
+#[test]
+fn cleaning_is_stable() {
+    let mut paths: Vec<_> = TEST_DIRS.iter().filter_map(|dir| fs::read_dir(dir).ok()).flatten()
+                                     .map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    let mut unstable = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(&path).unwrap();
+        for data in contents.split("#data\n").skip(1) {
+            let html = &data[..data.find("\n#").unwrap_or(data.len())];
+            let once = clean(html);
+            let twice = clean(&once);
+            if once != twice {
+                unstable.push(format!("{:?}\n      {:?}\n      {:?}", html, once, twice));
+            }
+        }
+    }
+    assert!(unstable.is_empty(), "{} unstable:\n  {}", unstable.len(), unstable.join("\n  "));
+}
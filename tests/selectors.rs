@@ -0,0 +1,146 @@
+// Runs CSS selectors against a small document and checks which elements they match, by id.
+
+use fasthtml::dom::Document;
+use fasthtml::selectors::{select, SelectorErrorKind};
+
+const HTML: &str = r#"<!DOCTYPE html>
+<html id=root>
+<body id=body>
+  <div id=a1 class="article featured">
+    <p id=p1>First <a id=l1 href="http://example.com/">link</a></p>
+    <p id=p2 lang=en-US>Second <a id=l2 href="/local" data-x="Tag Value">local</a></p>
+    <span id=s1></span>
+    <p id=p3><!-- just a comment --></p>
+  </div>
+  <div id=a2 class=article>
+    <h2 id=h1>Title</h2>
+    <p id=p4>Text<em id=e1>emphasis</em></p>
+    <ul id=list><li id=i1>1<li id=i2>2<li id=i3>3<li id=i4>4<li id=i5>5</ul>
+  </div>
+  <svg id=svg1><foreignObject id=fo1><p id=p5>in svg</p></foreignObject></svg>
+  <template id=t1><p id=hidden>inert</p></template>
+  <custom-element id=c1></custom-element>
+</body>
+</html>"#;
+
+const CASES: &[(&str, &str)] = &[
+    // Type, class, id and universal selectors
+    ("p", "p1 p2 p3 p4 p5"),
+    ("P", "p1 p2 p3 p4 p5"),
+    ("custom-element", "c1"),
+    (".article", "a1 a2"),
+    (".featured.article", "a1"),
+    ("#l2", "l2"),
+    ("div#a2", "a2"),
+    ("*#e1", "e1"),
+    ("foreignObject", "fo1"),
+    // Attributes
+    ("[lang]", "p2"),
+    ("a[href^=http]", "l1"),
+    ("a[href$='/local']", "l2"),
+    ("a[href*=example]", "l1"),
+    ("[lang|=en]", "p2"),
+    ("[data-x~=Value]", "l2"),
+    ("[data-x~=value]", ""),
+    ("[data-x~=value i]", "l2"),
+    ("[data-x=\"Tag Value\"]", "l2"),
+    ("[href^='']", ""),
+    // Combinators
+    ("div.article > p:first-child a[href^=http]", "l1"),
+    ("div p", "p1 p2 p3 p4"),
+    ("body > p", ""),
+    ("h2 + p", "p4"),
+    ("#p1 ~ p", "p2 p3"),
+    ("#p1 ~ *", "p2 s1 p3"),
+    ("div>p>a", "l1 l2"),
+    ("svg p", "p5"),
+    // Structural pseudo-classes
+    ("li:first-child", "i1"),
+    ("li:last-child", "i5"),
+    ("li:nth-child(2n+1)", "i1 i3 i5"),
+    ("li:nth-child(odd)", "i1 i3 i5"),
+    ("li:nth-child(even)", "i2 i4"),
+    ("li:nth-child(-n + 2)", "i1 i2"),
+    ("li:nth-child(3)", "i3"),
+    ("li:nth-last-child(2)", "i4"),
+    ("#a1 > :nth-of-type(2)", "p2"),
+    ("#a1 > p:nth-last-of-type(1)", "p3"),
+    ("#a1 > :last-of-type", "s1 p3"),
+    ("#a1 > :first-of-type", "p1 s1"),
+    ("#a1 > :only-of-type", "s1"),
+    ("p > :only-child", "l1 l2 e1"),
+    ("body :empty", "s1 p3 t1 c1"),
+    (":root", "root"),
+    // Logical pseudo-classes
+    ("p:not(#p1, #p2)", "p3 p4 p5"),
+    (":is(h2, em)", "h1 e1"),
+    (":where(#a2) p", "p4"),
+    ("div:has(> h2)", "a2"),
+    ("div:has(a[href^=http])", "a1"),
+    ("p:has(+ span)", "p2"),
+    ("p:has(~ p em)", ""),
+    ("h2:has(~ p em)", "h1"),
+    ("div:not(:has(em))", "a1"),
+];
+
+fn ids(document: &Document, selector: &str) -> String {
+    let raw_html = document.raw_html();
+    select(document, selector).unwrap_or_else(|error| panic!("{:?}: {:?}", selector, error))
+                              .into_iter()
+                              .map(|node| document.element(node).unwrap().attribute(raw_html, "id").unwrap_or_default())
+                              .collect::<Vec<_>>()
+                              .join(" ")
+}
+
+#[test]
+fn matches() {
+    let document = Document::parse(HTML);
+    let failures: Vec<String> = CASES.iter().filter_map(|&(selector, expected)| {
+        let found = ids(&document, selector);
+        (found != expected).then(|| format!("{:?}\n      expected {:?}\n      got {:?}", selector, expected, found))
+    }).collect();
+    assert!(failures.is_empty(), "{} selector(s) failed:\n  {}", failures.len(), failures.join("\n  "));
+}
+
+#[test]
+fn deep_nesting() {
+    // Retrying every ancestor for every descendant combinator takes seconds on this
+    let html = format!("{}<span id=s>x</span>", "<div>".repeat(1000));
+    let document = Document::parse(&html);
+    let started = std::time::Instant::now();
+    assert_eq!(ids(&document, "p div div span"), "");
+    assert_eq!(ids(&document, "p > div div div span"), "");
+    assert_eq!(ids(&document, "div ~ div div div span"), "");
+    assert_eq!(ids(&document, "body div div div span"), "s");
+    assert_eq!(ids(&document, "body > div div > div span"), "s");
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn quirks_mode_ignores_class_case() {
+    let document = Document::parse("<p id=x class=Intro>");
+    assert_eq!(ids(&document, ".intro"), "x");
+    let document = Document::parse("<!DOCTYPE html><p id=x class=Intro>");
+    assert_eq!(ids(&document, ".intro"), "");
+}
+
+#[test]
+fn invalid_selectors() {
+    let document = Document::parse(HTML);
+    let cases = [
+        ("", SelectorErrorKind::EmptySelector),
+        ("p,", SelectorErrorKind::EmptySelector),
+        ("p >", SelectorErrorKind::EmptySelector),
+        ("p:hover", SelectorErrorKind::UnknownPseudoClass),
+        ("p::before", SelectorErrorKind::UnsupportedPseudoElement),
+        ("li:nth-child(2x)", SelectorErrorKind::InvalidNth),
+        ("[href", SelectorErrorKind::UnexpectedEnd),
+        ("p)", SelectorErrorKind::UnexpectedCharacter),
+    ];
+    for (selector, kind) in cases {
+        match select(&document, selector) {
+            Ok(_) => panic!("{:?} parsed", selector),
+            Err(error) => assert_eq!(error.kind, kind, "{:?}", selector),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Serializes trees and token streams, and checks that serializing is stable: parsing the output
+// of every html5lib tree construction input again gives the same output.
+
+use std::fs;
+
+use fasthtml::dom::Document;
+use fasthtml::serialize::{inner_html, outer_html, serialize, serialize_tokens};
+use fasthtml::tokenizer::Tokenizer;
+use fasthtml::tokens::TagID;
+
+const TEST_DIRS: &[&str] = &[concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/tree-construction"),
+                             concat!(env!("CARGO_MANIFEST_DIR"), "/tests/html5lib/local/tree-construction")];
+
+const CASES: &[(&str, &str)] = &[
+    ("<!DOCTYPE html><p>Hi", "<!DOCTYPE html><html><head></head><body><p>Hi</p></body></html>"),
+    ("<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\">", "<!DOCTYPE html><html><head></head><body></body></html>"),
+    // Void elements
+    ("<br><img src=a.png><input disabled>x", "<html><head></head><body><br><img src=\"a.png\"><input disabled=\"\">x</body></html>"),
+    ("<p>a</br>b", "<html><head></head><body><p>a<br>b</p></body></html>"),
+    // Escaping
+    ("<p title='a \"b\" &amp; c&nbsp;<d>'>1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;</p>",
+     "<html><head></head><body><p title=\"a &quot;b&quot; &amp; c&nbsp;<d>\">1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;</p></body></html>"),
+    ("<p>'single' \"double\"</p>", "<html><head></head><body><p>'single' \"double\"</p></body></html>"),
+    // Raw text
+    ("<script>if (a < b && c) {}</script><style>a > b {}</style>",
+     "<html><head><script>if (a < b && c) {}</script><style>a > b {}</style></head><body></body></html>"),
+    ("<xmp><b>&amp;</xmp><textarea><b>&amp;</textarea><title>&lt;</title>",
+     "<html><head></head><body><xmp><b>&amp;</xmp><textarea>&lt;b&gt;&amp;</textarea><title>&lt;</title></body></html>"),
+    ("<noscript><b>x</b></noscript>", "<html><head><noscript></noscript></head><body><b>x</b></body></html>"),
+    ("<pre>\n\nx</pre><textarea>\ny</textarea><pre>z</pre>",
+     "<html><head></head><body><pre>\n\nx</pre><textarea>y</textarea><pre>z</pre></body></html>"),
+    // Names and foreign content
+    ("<DIV CLASS=x><Custom-Thing>", "<html><head></head><body><div class=\"x\"><custom-thing></custom-thing></div></body></html>"),
+    ("<svg viewbox='0 0 1 1'><foreignobject/><a xlink:href=#x /></svg><math><mi definitionurl=u /></math>",
+     "<html><head></head><body><svg viewBox=\"0 0 1 1\"><foreignObject></foreignObject><a xlink:href=\"#x\"></a></svg><math><mi definitionURL=\"u\"></mi></math></body></html>"),
+    ("<svg><script>a &lt; b</script></svg>", "<html><head></head><body><svg><script>a &lt; b</script></svg></body></html>"),
+    // Comments and templates
+    ("<!-- a -- b --><p><!---->", "<!-- a -- b --><html><head></head><body><p><!----></p></body></html>"),
+    ("<template><p>in</p><template>deeper</template></template>",
+     "<html><head><template><p>in</p><template>deeper</template></template></head><body></body></html>"),
+];
+
+#[test]
+fn documents() {
+    for (html, expected) in CASES {
+        assert_eq!(serialize(&Document::parse(html)), *expected, "for {:?}", html);
+    }
+}
+
+#[test]
+fn inner_and_outer_html() {
+    let html = "<ul><li class=a>one<li>two &amp; three</ul><template id=t><i>x</i></template><img id=i>";
+    let document = Document::parse(html);
+    let find = |tag: TagID| document.descendants(document.root()).find(|&node| document[node].is_element(tag)).unwrap();
+    assert_eq!(inner_html(&document, find(TagID::Ul)), "<li class=\"a\">one</li><li>two &amp; three</li>");
+    assert_eq!(outer_html(&document, find(TagID::Li)), "<li class=\"a\">one</li>");
+    assert_eq!(inner_html(&document, find(TagID::Template)), "<i>x</i>");
+    assert_eq!(outer_html(&document, find(TagID::Template)), "<template id=\"t\"><i>x</i></template>");
+    assert_eq!(inner_html(&document, find(TagID::Img)), "");
+    assert_eq!(outer_html(&document, find(TagID::Img)), "<img id=\"i\">");
+    assert_eq!(outer_html(&document, document.root()), serialize(&document));
+
+    let fragment = Document::parse_fragment("<td>a<td>b", TagID::Tr);
+    assert_eq!(serialize(&fragment), "<td>a</td><td>b</td>");
+}
+
+#[test]
+fn token_streams() {
+    let cases = [
+        ("<P Class=A>x &amp; y<BR/></p>", "<p class=\"A\">x &amp; y<br></p>"),
+        ("<a title='1>0'>link</A></br>", "<a title=\"1>0\">link</a><br>"),
+        ("<svg><Rect/><foreignObject/></svg>", "<svg><rect/><foreignObject/></svg>"),
+        ("<!DOCTYPE html><!--c--><b>", "<!DOCTYPE html><!--c--><b>"),
+    ];
+    for (html, expected) in cases {
+        let mut tokenizer = Tokenizer::new(html);
+        tokenizer.tokenize();
+        assert_eq!(serialize_tokens(html, &tokenizer.tokens), expected, "for {:?}", html);
+    }
+}
+
+#[test]
+fn serializing_is_stable() {
+    let mut paths: Vec<_> = TEST_DIRS.iter().filter_map(|dir| fs::read_dir(dir).ok()).flatten()
+                                     .map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    let mut unstable = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(&path).unwrap();
+        // Every "#data" section's lines, up to the next section
+        for data in contents.split("#data\n").skip(1) {
+            let html = &data[..data.find("\n#").unwrap_or(data.len())];
+            let document = Document::parse(html);
+            let once = serialize(&document);
+            let reparsed = Document::parse(&once);
+            // Doctypes lose their identifiers, and with them what made the document quirky
+            if reparsed.quirks_mode != document.quirks_mode {
+                continue;
+            }
+            let twice = serialize(&reparsed);
+            if once != twice {
+                unstable.push(format!("{:?}\n      {:?}\n      {:?}", html, once, twice));
+            }
+        }
+    }
+    assert!(unstable.is_empty(), "{} unstable:\n  {}", unstable.len(), unstable.join("\n  "));
+}
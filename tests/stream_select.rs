@@ -0,0 +1,137 @@
+// Matches selectors against token streams, and checks them against the tree selectors on markup
+// where the open elements and the tree agree.
+
+use fasthtml::dom::Document;
+use fasthtml::selectors::{select, SelectorErrorKind};
+use fasthtml::stream_select::StreamSelector;
+
+const HTML: &str = r#"<!DOCTYPE html>
+<html id=root>
+<body id=body>
+  <div id=a1 class="article featured">
+    <p id=p1>First <a id=l1 href="http://example.com/?a=1&amp;b=2">link</a></p>
+    <p id=p2 lang=en-US>Second <a id=l2 href="/local" data-x="Tag > Value">local</a><br id=br1></p>
+    <span id=s1></span>
+  </div>
+  <div id=a2 class=article>
+    <h2 id=h1>Title</h2>
+    <p id=p4>Text<em id=e1>emphasis</em></p>
+    <ul id=list><li id=i1>1</li><li id=i2>2</li></ul>
+  </div>
+  <svg id=svg1><rect id=r1 /><foreignObject id=fo1><p id=p5>in svg</p></foreignObject></svg>
+  <custom-element id=c1></custom-element>
+  <script id=js1>if (a <p) document.write("<p id=fake>")</script>
+</body>
+</html>"#;
+
+const CASES: &[&str] = &[
+    "p",
+    "P",
+    "custom-element",
+    ".article",
+    ".featured.article",
+    "#l2",
+    "div#a2",
+    "*#e1",
+    "foreignObject",
+    "rect",
+    "br",
+    "[lang]",
+    "a[href^=http]",
+    "[data-x~=Value]",
+    "[data-x~=value i]",
+    "div.article > p a[href^=http]",
+    "div p",
+    "body > p",
+    "div>p>a",
+    "svg p",
+    "html li, #h1",
+];
+
+fn stream_ids(selectors: &str, html: &str) -> Vec<String> {
+    let selector = StreamSelector::parse(selectors).unwrap();
+    let mut matches: Vec<_> = selector.matches(html).collect();
+    matches.sort_by_key(|found| found.span.start);
+    matches.iter().map(|found| found.attribute(html, "id").unwrap_or_default().into_owned()).collect()
+}
+
+#[test]
+fn agrees_with_the_tree() {
+    let document = Document::parse(HTML);
+    for selectors in CASES {
+        let expected: Vec<String> = select(&document, selectors).unwrap().into_iter()
+            .map(|node| document.element(node).unwrap().attribute(HTML, "id").unwrap_or_default().into_owned())
+            .collect();
+        assert_eq!(stream_ids(selectors, HTML), expected, "for {:?}", selectors);
+    }
+}
+
+#[test]
+fn spans_and_text() {
+    let html = "<ul><li class=x>one <b>two</b></li><li class=x title='a>b'>three &amp; four</ul><img class=x src=a.png>";
+    let selector = StreamSelector::parse(".x").unwrap();
+    let matches: Vec<_> = selector.matches(html).collect();
+    let found: Vec<(String, &str, &str)> = matches.iter()
+        .map(|found| (found.name(html).into_owned(), &html[found.span.clone()], found.text.as_str()))
+        .collect();
+    assert_eq!(found, [
+        ("li".to_string(), "<li class=x>one <b>two</b></li>", "one two"),
+        ("li".to_string(), "<li class=x title='a>b'>three &amp; four", "three & four"),
+        ("img".to_string(), "<img class=x src=a.png>", ""),
+    ]);
+}
+
+#[test]
+fn nested_matches_end_first() {
+    let html = "<div class=a>1<div class=a>2</div>3</div>";
+    let selector = StreamSelector::parse("div").unwrap();
+    let texts: Vec<String> = selector.matches(html).map(|found| found.text).collect();
+    assert_eq!(texts, ["2", "123"]);
+}
+
+#[test]
+fn implied_end_tags() {
+    let html = "<p id=a>one<p id=b>two<div><p id=c>three</div><table><tr><td id=d>x<td id=e>y</table>";
+    let selector = StreamSelector::parse("p, td").unwrap();
+    let found: Vec<(String, String)> = selector.matches(html).map(|found| {
+        (found.attribute(html, "id").unwrap().into_owned(), html[found.span].to_string())
+    }).collect();
+    assert_eq!(found, [
+        ("a".to_string(), "<p id=a>one".to_string()),
+        ("b".to_string(), "<p id=b>two".to_string()),
+        ("c".to_string(), "<p id=c>three".to_string()),
+        ("d".to_string(), "<td id=d>x".to_string()),
+        ("e".to_string(), "<td id=e>y".to_string()),
+    ]);
+}
+
+#[test]
+fn deep_nesting() {
+    // Retrying every open ancestor for every descendant combinator takes seconds on this
+    let html = format!("{}<a>x</a>", "<div>".repeat(1000));
+    let started = std::time::Instant::now();
+    for (selector, count) in [("p div div a", 0), ("p > div div div a", 0), ("div div > div div a", 1)] {
+        assert_eq!(StreamSelector::parse(selector).unwrap().matches(&html).count(), count, "for {:?}", selector);
+    }
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn unclosed_elements_end_with_the_input() {
+    let html = "<div><span>text";
+    let selector = StreamSelector::parse("div > span").unwrap();
+    let matches: Vec<_> = selector.matches(html).collect();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].span, 5..html.len());
+    assert_eq!(matches[0].text, "text");
+}
+
+#[test]
+fn only_the_streamable_subset_parses() {
+    for (selectors, position) in [("p:first-child", 1), ("h2 + p", 3), ("a ~ b", 2), ("div :not(p)", 4)] {
+        let error = StreamSelector::parse(selectors).unwrap_err();
+        assert_eq!(error.kind, SelectorErrorKind::NotStreamable, "for {:?}", selectors);
+        assert_eq!(error.position, position, "for {:?}", selectors);
+    }
+    assert_eq!(StreamSelector::parse("p,").unwrap_err().kind, SelectorErrorKind::EmptySelector);
+}
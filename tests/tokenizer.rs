@@ -0,0 +1,250 @@
+// Token-level tests for what the html5lib formats can't express: switches that a tree builder
+// sets from the outside, and what the tokenizer works out by itself.
+
+use fasthtml::dom::Document;
+use fasthtml::foreign::Namespace;
+use fasthtml::serialize::serialize;
+use fasthtml::tokenizer::{InitialState, Tokenizer};
+use fasthtml::tokens::{Flags, QualifiedName, TagID, Token};
+
+/// Tokens as "name" for tags ("/name" for end tags), "#text" and "#comment" with their
+/// decoded text, then the error codes.
+fn describe(raw_html: &str, tokens: &[Token]) -> Vec<String> {
+    tokens.iter().filter(|token| token.token_id != TagID::EndOfFile).map(|token| match token.token_id {
+        TagID::Text => format!("#text {:?}", token.text(raw_html)),
+        TagID::EmComment => format!("#comment {:?}", token.text(raw_html)),
+        TagID::EmDoctype => format!("#doctype {:?}", token.text(raw_html)),
+        _ if token.flags & Flags::Close as u8 != 0 => format!("/{}", token.name(raw_html)),
+        _ => token.name(raw_html).into_owned(),
+    }).collect()
+}
+
+fn tokenize(raw_html: &str) -> (Vec<String>, Vec<&'static str>) {
+    let mut tokenizer = Tokenizer::new(raw_html);
+    tokenizer.tokenize();
+    (describe(raw_html, &tokenizer.tokens), tokenizer.errors.iter().map(|error| error.kind.code()).collect())
+}
+
+#[test]
+fn end_of_file_in_every_kind_of_markup() {
+    let cases: &[(&str, &[&str], &[&str])] = &[
+        // 0x05 used to be the end of file, it's an ordinary character now
+        ("a\u{5}b", &["#text \"a\\u{5}b\""], &[]),
+        ("a<", &["#text \"a<\""], &["eof-before-tag-name"]),
+        ("a</", &["#text \"a</\""], &["eof-before-tag-name"]),
+        // An unfinished tag is dropped, whatever part of it the input stops in
+        ("a<p", &["#text \"a\""], &["eof-in-tag"]),
+        ("<p class", &[], &["eof-in-tag"]),
+        ("<p class=", &[], &["eof-in-tag"]),
+        ("<p class='a", &[], &["eof-in-tag"]),
+        ("<p class=a", &[], &["eof-in-tag"]),
+        ("<p/", &[], &["eof-in-tag"]),
+        ("</p", &[], &["eof-in-tag"]),
+        // Comments and doctypes are emitted as far as they got
+        ("<!--a", &["#comment \"a\""], &["eof-in-comment"]),
+        ("<!--a-", &["#comment \"a\""], &["eof-in-comment"]),
+        ("<!--", &["#comment \"\""], &["eof-in-comment"]),
+        ("<!a", &["#comment \"a\""], &["incorrectly-opened-comment"]),
+        ("<!", &["#comment \"\""], &["incorrectly-opened-comment"]),
+        ("<!DOCTYPE html", &["#doctype \"html\""], &["eof-in-doctype"]),
+        ("<!DOCTYPE", &["#doctype \"\""], &["eof-in-doctype"]),
+    ];
+    for (html, tokens, errors) in cases {
+        assert_eq!(tokenize(html), (tokens.iter().map(|token| token.to_string()).collect(), errors.to_vec()),
+                   "for {:?}", html);
+    }
+}
+
+#[test]
+fn end_of_file_token() {
+    for html in ["", "a", "<p>", "<p", "<!--a", "<!DOCTYPE html"] {
+        let mut tokenizer = Tokenizer::new(html);
+        tokenizer.tokenize();
+        let (last, rest) = tokenizer.tokens.split_last().unwrap();
+        assert_eq!(last.token_id, TagID::EndOfFile, "for {:?}", html);
+        assert_eq!((last.start, last.end), (html.len() as u32, html.len() as u32), "for {:?}", html);
+        assert!(rest.iter().all(|token| token.token_id != TagID::EndOfFile), "for {:?}", html);
+    }
+
+    // Only a doctype cut short by the end of file forces quirks mode
+    let mut tokenizer = Tokenizer::new("<!DOCTYPE html><!DOCTYPE html");
+    tokenizer.tokenize();
+    let quirks: Vec<bool> = tokenizer.tokens[..2].iter().map(|token| token.flags & Flags::ForceQuirks as u8 != 0).collect();
+    assert_eq!(quirks, [false, true]);
+}
+
+#[test]
+fn error_positions() {
+    let positions = |html: &str| -> Vec<(&'static str, u32)> {
+        let mut tokenizer = Tokenizer::new(html);
+        tokenizer.tokenize();
+        tokenizer.errors.iter().map(|error| (error.kind.code(), error.position)).collect()
+    };
+    assert_eq!(positions("<p a=1 a=2>"), [("duplicate-attribute", 8)]);
+    assert_eq!(positions("a\0<p\0>"), [("unexpected-null-character", 1), ("unexpected-null-character", 4)]);
+    assert_eq!(positions("</p a>"), [("end-tag-with-attributes", 5)]);
+    assert_eq!(positions("<p class"), [("eof-in-tag", 8)]);
+    assert_eq!(positions("<!--a"), [("eof-in-comment", 5)]);
+}
+
+#[test]
+fn cdata_sections_in_foreign_content() {
+    let cases: &[(&str, &[&str], &[&str])] = &[
+        ("<svg><![CDATA[a<b]]></svg>", &["svg", "#text \"a<b\"", "/svg"], &[]),
+        ("<math><![CDATA[<p>&amp;]]></math>", &["math", "#text \"<p>&amp;\"", "/math"], &[]),
+        // Only "]]>" ends the section, other brackets are text
+        ("<svg><![CDATA[x]]]>", &["svg", "#text \"x]\""], &[]),
+        ("<svg><![CDATA[x]]]]>", &["svg", "#text \"x]]\""], &[]),
+        ("<svg><![CDATA[a]]b]>c] ]>]]>", &["svg", "#text \"a]]b]>c] ]>\""], &[]),
+        ("<svg><![CDATA[]]>", &["svg"], &[]),
+        ("<svg><![CDATA[a]]", &["svg", "#text \"a]]\""], &["eof-in-cdata"]),
+        ("<svg><![CDATA[a]", &["svg", "#text \"a]\""], &["eof-in-cdata"]),
+        ("<svg><![CDATA[", &["svg"], &["eof-in-cdata"]),
+        // In HTML content it's a bogus comment
+        ("<![CDATA[a<b]]>", &["#comment \"[CDATA[a<b]]\""], &["cdata-in-html-content"]),
+        ("<svg></svg><![CDATA[x]]>", &["svg", "/svg", "#comment \"[CDATA[x]]\""], &["cdata-in-html-content"]),
+        // Right inside an integration point the current node is still foreign, not inside HTML in it
+        ("<math><mi><![CDATA[x]]></mi></math>", &["math", "mi", "#text \"x\"", "/mi", "/math"], &[]),
+        ("<svg><foreignObject><![CDATA[x]]><p><![CDATA[y]]></p><![CDATA[z]]>",
+         &["svg", "foreignObject", "#text \"x\"", "p", "#comment \"[CDATA[y]]\"", "/p", "#text \"z\""],
+         &["cdata-in-html-content"]),
+    ];
+    for (html, tokens, errors) in cases {
+        assert_eq!(tokenize(html), (tokens.iter().map(|token| token.to_string()).collect(), errors.to_vec()),
+                   "for {:?}", html);
+    }
+}
+
+#[test]
+fn foreign_content_set_from_outside() {
+    // With namespace tracking off, only `set_foreign_content` decides
+    let html = "<svg><![CDATA[a]]><![CDATA[b]]>";
+    let mut tokenizer = Tokenizer::new(html);
+    tokenizer.set_track_namespaces(false);
+    tokenizer.tokenize();
+    assert_eq!(describe(html, &tokenizer.tokens), ["svg", "#comment \"[CDATA[a]]\"", "#comment \"[CDATA[b]]\""]);
+
+    let mut tokenizer = Tokenizer::new(html);
+    tokenizer.set_track_namespaces(false);
+    let mut tokens = vec![tokenizer.next_token().unwrap()];
+    tokenizer.set_foreign_content(true);
+    tokens.push(tokenizer.next_token().unwrap());
+    tokenizer.set_foreign_content(false);
+    tokens.extend(std::iter::from_fn(|| tokenizer.next_token()));
+    assert_eq!(describe(html, &tokens), ["svg", "#text \"a\"", "#comment \"[CDATA[b]]\""]);
+    assert_eq!(tokenizer.errors.iter().map(|error| error.kind.code()).collect::<Vec<_>>(), ["cdata-in-html-content"]);
+}
+
+#[test]
+fn nuls_are_replaced_outside_the_data_state() {
+    let cases: &[(InitialState, &str, &[&str])] = &[
+        // Data state text and CDATA sections keep them for the tree builder
+        (InitialState::Data, "a\0b", &["#text \"a\\0b\""]),
+        (InitialState::Data, "<svg><![CDATA[a\0b]]>", &["svg", "#text \"a\\0b\""]),
+        (InitialState::Rcdata, "a\0b&amp;", &["#text \"a\u{FFFD}b&\""]),
+        (InitialState::Rcdata, "<\0", &["#text \"<\u{FFFD}\""]),
+        (InitialState::Rawtext, "a\0b", &["#text \"a\u{FFFD}b\""]),
+        (InitialState::ScriptData, "a\0<!--<script>\0</script>-->", &["#text \"a\u{FFFD}<!--<script>\u{FFFD}</script>-->\""]),
+        (InitialState::Plaintext, "a\0b", &["#text \"a\u{FFFD}b\""]),
+        (InitialState::Data, "<A\0b>", &["a\u{FFFD}b"]),
+        (InitialState::Data, "<!--a\0b-->", &["#comment \"a\u{FFFD}b\""]),
+        (InitialState::Data, "<!DOCTYPE a\0b>", &["#doctype \"a\u{FFFD}b\""]),
+    ];
+    for (state, html, tokens) in cases {
+        let mut tokenizer = Tokenizer::new(html);
+        tokenizer.set_state(*state);
+        tokenizer.tokenize();
+        assert_eq!(describe(html, &tokenizer.tokens), tokens.iter().map(|token| token.to_string()).collect::<Vec<_>>(),
+                   "for {:?}", html);
+    }
+
+    // The tree builder switches states itself, and drops the NULs of data state text in the body
+    let document = Document::parse("<title>a\0b</title><p>c\0d");
+    assert_eq!(serialize(&document), "<html><head><title>a\u{FFFD}b</title></head><body><p>cd</p></body></html>");
+}
+
+/// The tags of `raw_html` as "name" or "/name", with the namespace the tokenizer tracked.
+fn namespaces(raw_html: &str) -> Vec<(String, Namespace)> {
+    let mut tokenizer = Tokenizer::new(raw_html);
+    tokenizer.tokenize();
+    let tokens = tokenizer.tokens.iter().filter(|token| !matches!(token.token_id, TagID::Text | TagID::EndOfFile));
+    tokens.map(|token| (describe(raw_html, std::slice::from_ref(token)).remove(0), token.namespace())).collect()
+}
+
+#[test]
+fn namespaces_of_tags() {
+    use Namespace::{Html, MathMl, Svg};
+    let cases: &[(&str, &[(&str, Namespace)])] = &[
+        ("<p><svg><g></g></svg><g>", &[("p", Html), ("svg", Svg), ("g", Svg), ("/g", Svg), ("/svg", Svg), ("g", Html)]),
+        ("<math><mrow/></math><mrow>", &[("math", MathMl), ("mrow", MathMl), ("/math", MathMl), ("mrow", Html)]),
+        ("<svg/><g>", &[("svg", Svg), ("g", Html)]),
+        // Breakout tags leave foreign content, `font` only with one of its presentational attributes
+        ("<svg><g><p><g>", &[("svg", Svg), ("g", Svg), ("p", Html), ("g", Html)]),
+        ("<math><b>x</b><mi>", &[("math", MathMl), ("b", Html), ("/b", Html), ("mi", Html)]),
+        ("<svg><font><g>", &[("svg", Svg), ("font", Svg), ("g", Svg)]),
+        ("<svg><font color=red><g>", &[("svg", Svg), ("font", Html), ("g", Html)]),
+        ("<svg></p><g>", &[("svg", Svg), ("/p", Html), ("g", Html)]),
+        // HTML integration points hold HTML until they end
+        ("<svg><foreignObject><foo><g></g></foo></foreignObject><g>",
+         &[("svg", Svg), ("foreignObject", Svg), ("foo", Html), ("g", Html), ("/g", Html), ("/foo", Html),
+           ("/foreignObject", Svg), ("g", Svg)]),
+        ("<math><annotation-xml encoding=text/html><foo></foo></annotation-xml><mi>",
+         &[("math", MathMl), ("annotation-xml", MathMl), ("foo", Html), ("/foo", Html), ("/annotation-xml", MathMl),
+           ("mi", MathMl)]),
+        ("<math><annotation-xml><foo>", &[("math", MathMl), ("annotation-xml", MathMl), ("foo", MathMl)]),
+        // So do MathML text integration points, except for `mglyph` and `malignmark`
+        ("<math><mi><foo></foo><mglyph>", &[("math", MathMl), ("mi", MathMl), ("foo", Html), ("/foo", Html), ("mglyph", MathMl)]),
+        // An `svg` inside HTML inside SVG starts over
+        ("<svg><foreignObject><svg><g>", &[("svg", Svg), ("foreignObject", Svg), ("svg", Svg), ("g", Svg)]),
+    ];
+    for (html, expected) in cases {
+        let expected: Vec<(String, Namespace)> = expected.iter().map(|(name, namespace)| (name.to_string(), *namespace)).collect();
+        assert_eq!(namespaces(html), expected, "for {:?}", html);
+    }
+}
+
+#[test]
+fn foreign_names_are_adjusted() {
+    let html = "<svg viewbox='0 0 1 1' XLINK:HREF=#a><foreignobject definitionurl=x></foreignobject></svg>\
+                <math definitionurl=x xlink:href=#a><p viewbox=x>";
+    let mut tokenizer = Tokenizer::new(html);
+    tokenizer.tokenize();
+    let tags: Vec<&Token> = tokenizer.tokens.iter().filter(|token| token.token_id != TagID::Text).collect();
+    let attributes = |token: &Token| -> Vec<QualifiedName> {
+        token.attributes.iter().flat_map(|attributes| attributes.iter())
+             .map(|attribute| attribute.qualified_name(token, html)).collect()
+    };
+    let name = |local: &str| QualifiedName { prefix: None, local: local.to_string().into(), namespace: None };
+    let xlink_href = QualifiedName { prefix: Some("xlink"), local: "href".into(), namespace: Some(Namespace::XLink) };
+
+    assert_eq!(tags[0].name(html), "svg");
+    assert_eq!(attributes(tags[0]), [name("viewBox"), xlink_href.clone()]);
+    assert_eq!(tags[1].name(html), "foreignObject");
+    // `definitionURL` is only adjusted on MathML elements
+    assert_eq!(attributes(tags[1]), [name("definitionurl")]);
+    assert_eq!(tags[2].name(html), "foreignObject");
+    assert_eq!(tags[4].name(html), "math");
+    assert_eq!(attributes(tags[4]), [name("definitionURL"), xlink_href]);
+    // Nothing is adjusted on HTML elements
+    assert_eq!(tags[5].name(html), "p");
+    assert_eq!(attributes(tags[5]), [name("viewbox")]);
+}
+
+#[test]
+fn attributes_longer_than_64_kib() {
+    let value = "é".repeat(40_000);
+    let html = format!("<p title=\"{}&amp;\" class=a>hi</p><!DOCTYPE html PUBLIC \"{}\">", value, value);
+    let mut tokenizer = Tokenizer::new(&html);
+    tokenizer.tokenize();
+    let p = &tokenizer.tokens[0];
+    let attributes = p.attributes.as_deref().unwrap();
+    assert_eq!(attributes[0].name(p, &html), "title");
+    assert_eq!(attributes[0].value(p, &html), value.clone() + "&");
+    assert_eq!(attributes[1].name(p, &html), "class");
+    assert_eq!(attributes[1].value(p, &html), "a");
+    assert_eq!(tokenizer.tokens[1].text(&html), "hi");
+
+    let doctype = &tokenizer.tokens[3];
+    assert_eq!(doctype.token_id, TagID::EmDoctype);
+    assert_eq!(doctype.doctype_identifiers(&html).0.as_deref(), Some(value.as_str()));
+}
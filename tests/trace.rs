@@ -0,0 +1,106 @@
+// Runs the tokenizer with an observer that records what it's told, and checks that the demo
+// binary only prints a trace when it's asked to.
+
+use std::cell::RefCell;
+use std::process::Command;
+use std::rc::Rc;
+
+use fasthtml::errors::ParseError;
+use fasthtml::tokenizer::{InitialState, State, Tokenizer};
+use fasthtml::tokens::Token;
+use fasthtml::trace::Observer;
+
+#[derive(Clone, Default)]
+struct Recorder {
+    events: Rc<RefCell<Vec<String>>>,
+}
+
+impl Observer for Recorder {
+    fn state_transition(&mut self, from: State, to: State) {
+        self.events.borrow_mut().push(format!("{:?} -> {:?}", from, to));
+    }
+
+    fn token_emitted(&mut self, token: &Token, raw_html: &[u8]) {
+        let span = String::from_utf8_lossy(&raw_html[token.start as usize..token.end as usize]).into_owned();
+        self.events.borrow_mut().push(format!("token {:?} {:?}", token.token_id, span));
+    }
+
+    fn parse_error(&mut self, error: &ParseError) {
+        self.events.borrow_mut().push(format!("error {} at {}", error.kind.code(), error.position));
+    }
+}
+
+#[test]
+fn observer_sees_everything_in_order() {
+    let html = "<p a=1>x</p><!-- c -->\0";
+    let recorder = Recorder::default();
+    let mut tokenizer = Tokenizer::with_observer(html, recorder.clone());
+    tokenizer.tokenize();
+
+    let events = recorder.events.borrow();
+    let expected = [
+        "Data -> TagOpen",
+        "TagOpen -> TagName",
+        "TagName -> BeforeAttributeName",
+        "BeforeAttributeName -> AttributeName",
+        "AttributeName -> BeforeAttributeValue",
+        "BeforeAttributeValue -> AttributeValueUnquoted",
+        "token Paragraph \"p\"",
+        "AttributeValueUnquoted -> Data",
+        "Data -> TagOpen",
+        "TagOpen -> EndTagOpen",
+        "token Text \"x\"",
+        "EndTagOpen -> TagName",
+        "token Paragraph \"p\"",
+        "TagName -> Data",
+        "Data -> TagOpen",
+        "TagOpen -> MarkupDeclarationOpen",
+        "MarkupDeclarationOpen -> CommentStart",
+        "CommentStart -> Comment",
+        "Comment -> CommentEndDash",
+        "CommentEndDash -> CommentEnd",
+        "token EmComment \" c \"",
+        "CommentEnd -> Data",
+        "error unexpected-null-character at 22",
+        "token Text \"\\0\"",
+        "token EndOfFile \"\"",
+    ];
+    assert_eq!(*events, expected);
+
+    // Every transition starts from the state the previous one went to
+    let transitions: Vec<_> = events.iter().filter_map(|event| event.split_once(" -> ")).collect();
+    for pair in transitions.windows(2) {
+        assert_eq!(pair[0].1, pair[1].0, "in {:?}", pair);
+    }
+    // And the observer saw exactly the tokens and errors the tokenizer kept
+    let tokens = events.iter().filter(|event| event.starts_with("token ")).count();
+    let errors = events.iter().filter(|event| event.starts_with("error ")).count();
+    assert_eq!((tokens, errors), (tokenizer.tokens.len(), tokenizer.errors.len()));
+}
+
+#[test]
+fn set_state_is_reported() {
+    let recorder = Recorder::default();
+    let mut tokenizer = Tokenizer::with_observer("a", recorder.clone());
+    tokenizer.set_state(InitialState::Rawtext);
+    tokenizer.set_state(InitialState::Rawtext);
+    tokenizer.tokenize();
+    assert_eq!(*recorder.events.borrow(), ["Data -> Rawtext", "token Text \"a\"", "token EndOfFile \"\""]);
+}
+
+#[test]
+fn tracing_is_opt_in() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_fasthtml")).args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let quiet = run(&[]);
+    let traced = run(&["--trace"]);
+    assert!(!quiet.is_empty());
+    assert!(!quiet.lines().any(|line| line.starts_with("State:") || line.starts_with("Token:") || line.starts_with("Error:")));
+    assert!(traced.lines().any(|line| line.starts_with("State: Data -> TagOpen")));
+    assert!(traced.lines().any(|line| line.starts_with("Token:")));
+    // With tracing off, the output is just what's printed after tokenizing
+    assert!(traced.ends_with(&quiet));
+}
@@ -0,0 +1,237 @@
+// Evaluates XPath expressions against a small document. Node-sets are compared as the ids of
+// their elements, and as "@name" or "'text'" for attributes and text nodes.
+
+use fasthtml::dom::{Document, NodeData};
+use fasthtml::xpath::{evaluate, select, string_value, Value, XPath, XPathErrorKind, XPathNode};
+
+const HTML: &str = r#"<!DOCTYPE html>
+<html id=root lang=en>
+<body id=body>
+  <div id=a1 class="article featured">
+    <p id=p1>First <a id=l1 href="http://example.com/">link</a></p>
+    <p id=p2 lang=en-US>Second <a id=l2 href="/local" data-x="Tag Value">local</a></p>
+    <p id=p3><!--note--></p>
+  </div>
+  <div id=a2 class=article>
+    <h2 id=h1>  Title   text </h2>
+    <ul id=list><li id=i1>1</li><li id=i2>2</li><li id=i3>3.5</li></ul>
+  </div>
+  <svg id=svg1><a id=sa xlink:href=#x /><foreignObject id=fo1><p id=p4>in svg</p></foreignObject></svg>
+  <template id=t1><p id=hidden>inert</p></template>
+</body>
+</html>"#;
+
+fn describe(document: &Document, value: Value) -> String {
+    let nodes = match value {
+        Value::NodeSet(nodes) => nodes,
+        value => return value.string(document),
+    };
+    nodes.into_iter().map(|node| match node {
+        XPathNode::Attribute(..) => format!("@{}", string_value(document, node)),
+        XPathNode::Node(id) => match &document[id].data {
+            NodeData::Element(element) => element.attribute(HTML, "id").unwrap_or_default().into_owned(),
+            NodeData::Comment(_) => format!("<!--{}-->", document.text(id)),
+            NodeData::Document => "/".to_string(),
+            _ => format!("'{}'", document.text(id)),
+        },
+    }).collect::<Vec<_>>().join(" ")
+}
+
+const CASES: &[(&str, &str)] = &[
+    // Location paths and abbreviations
+    ("/html/body/div", "a1 a2"),
+    ("//p", "p1 p2 p3 p4"),
+    ("//P", "p1 p2 p3 p4"),
+    ("//div[2]/h2", "h1"),
+    ("//ul/li[last()]", "i3"),
+    ("//li[position() > 1]", "i2 i3"),
+    ("//a/..", "p1 p2 svg1"),
+    ("//li[2]/.", "i2"),
+    ("/", "/"),
+    ("//*[@id='l1']/@href", "@http://example.com/"),
+    ("//a/@*", "@l1 @http://example.com/ @l2 @/local @Tag Value @sa @#x"),
+    ("//p[a]", "p1 p2"),
+    ("//div[@class='article']", "a2"),
+    ("(//p)[1]", "p1"),
+    ("(//li)[last()]/text()", "'3.5'"),
+    ("//p[1]", "p1 p4"),
+    ("//p/comment()", "<!--note-->"),
+    ("//p[@id='p1']/text()", "'First '"),
+    ("//template/p", ""),
+    ("//div | //ul", "a1 a2 list"),
+    // Axes
+    ("//li[1]/following-sibling::li", "i2 i3"),
+    ("//li[3]/preceding-sibling::li[1]", "i2"),
+    ("//a[@id='l1']/ancestor::*", "root body a1 p1"),
+    ("//a[@id='l1']/ancestor::*[1]", "p1"),
+    ("//a[@id='l1']/ancestor-or-self::*[2]", "p1"),
+    ("//h2/following::li", "i1 i2 i3"),
+    ("//h2/preceding::p[1]", "p3"),
+    ("//ul/descendant::text()", "'1' '2' '3.5'"),
+    ("//li[1]/self::li", "i1"),
+    ("//li[1]/self::p", ""),
+    ("//a[@id='l1']/@href/parent::*", "l1"),
+    ("//a[@id='l1']/@href/following::a[1]", "l2"),
+    ("//p[@id='p1']/attribute::id", "@p1"),
+    ("//li/following-sibling::li", "i2 i3"),
+    ("//li/preceding-sibling::*", "i1 i2"),
+    ("//p/following-sibling::*", "p2 p3"),
+    ("//*/following::h2", "h1"),
+    ("//a/following::p", "p2 p3 p4"),
+    ("//a/@href/following::*[@id='p2' or @id='l2']", "p2 l2"),
+    ("//li/preceding::h2", "h1"),
+    ("//a/preceding::p", "p1 p2 p3"),
+    ("//div/descendant::a", "l1 l2"),
+    ("//*/descendant-or-self::li", "i1 i2 i3"),
+    ("//a/@*/descendant-or-self::node()", "@l1 @http://example.com/ @l2 @/local @Tag Value @sa @#x"),
+    // Namespaces
+    ("//svg:a", "sa"),
+    ("//html:a", "l1 l2"),
+    ("//foreignObject/*", "p4"),
+    ("//svg:*[@xlink:href]", "sa"),
+    ("namespace-uri(//svg:a)", "http://www.w3.org/2000/svg"),
+    ("name(//svg:a/@*[2])", "xlink:href"),
+    ("local-name(//svg:a/@*[2])", "href"),
+    // Functions
+    ("count(//li)", "3"),
+    ("sum(//li)", "6.5"),
+    ("string(//h2)", "  Title   text "),
+    ("normalize-space(//h2)", "Title text"),
+    ("concat(//li[1], '-', //li[2], '-', 3)", "1-2-3"),
+    ("substring('12345', 1.5, 2.6)", "234"),
+    ("substring('12345', 0, 3)", "12"),
+    ("substring('12345', 0 div 0, 3)", ""),
+    ("substring('12345', -42, 1 div 0)", "12345"),
+    ("substring-before('1999/04/01', '/')", "1999"),
+    ("substring-after('1999/04/01', '/')", "04/01"),
+    ("translate('--aaa--', 'abc-', 'ABC')", "AAA"),
+    ("string-length('héllo')", "5"),
+    ("starts-with(//a[1]/@href, 'http')", "true"),
+    ("//a[contains(@href, 'example')]", "l1"),
+    ("//*[starts-with(name(), 'h')][@id]", "root h1"),
+    ("local-name(//div)", "div"),
+    ("name(//foreignObject)", "foreignObject"),
+    ("id('l2 i1')", "l2 i1"),
+    ("id(//a/@id)", "l1 l2 sa"),
+    ("//p[lang('en')]", "p1 p2 p3 p4"),
+    ("//*[lang('en-us')]", "p2 l2"),
+    ("boolean(//table)", "false"),
+    ("not(//table)", "true"),
+    ("number('  12.5 ')", "12.5"),
+    ("number('1e3')", "NaN"),
+    ("number(true())", "1"),
+    ("floor(-1.5)", "-2"),
+    ("ceiling(1.2)", "2"),
+    ("round(2.5)", "3"),
+    ("round(-2.5)", "-2"),
+    ("round(-0.2)", "0"),
+    // Operators and comparisons
+    ("1 + 2 * 3", "7"),
+    ("7 div 2", "3.5"),
+    ("7 mod -2", "1"),
+    ("-(1 - 3)", "2"),
+    ("1 div 0", "Infinity"),
+    ("0 div 0", "NaN"),
+    ("//li = '2'", "true"),
+    ("//li != '2'", "true"),
+    ("//li > 3", "true"),
+    ("//li > 4", "false"),
+    ("//li = //p", "false"),
+    ("//li[. = 2]", "i2"),
+    ("//li[. < 2 or . > 3]", "i1 i3"),
+    ("//li[. >= 2 and . <= 3]", "i2"),
+    ("//table = false()", "true"),
+    ("'1' = 1.0", "true"),
+    ("true() = 'x'", "true"),
+    ("count(//p[a][2])", "1"),
+    ("2*2", "4"),
+    ("//div[1]/p[1]/a/@href = 'http://example.com/'", "true"),
+];
+
+#[test]
+fn expressions() {
+    let document = Document::parse(HTML);
+    let mut failures = Vec::new();
+    for (expression, expected) in CASES {
+        let got = match evaluate(&document, expression) {
+            Ok(value) => describe(&document, value),
+            Err(error) => format!("error {:?}", error),
+        };
+        if got != *expected {
+            failures.push(format!("{:?}: expected {:?}, got {:?}", expression, expected, got));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn relative_to_a_context_node() {
+    let document = Document::parse(HTML);
+    let list = select(&document, "//ul").unwrap()[0];
+    let list = match list {
+        XPathNode::Node(id) => id,
+        XPathNode::Attribute(..) => unreachable!(),
+    };
+    let xpath = XPath::parse("li[2]").unwrap();
+    assert_eq!(describe(&document, xpath.evaluate(&document, list)), "i2");
+    assert_eq!(XPath::parse("string(li[last()])").unwrap().string(&document, list), "3.5");
+    assert_eq!(describe(&document, XPath::parse("/html").unwrap().evaluate(&document, list)), "root");
+    assert_eq!(describe(&document, XPath::parse("../h2").unwrap().evaluate(&document, list)), "h1");
+}
+
+#[test]
+fn many_context_nodes() {
+    // Each of these walks the same siblings again for every <p> unless it leaves the covered ones out
+    let html = "<p>x</p>".repeat(10_000);
+    let document = Document::parse(&html);
+    let started = std::time::Instant::now();
+    assert_eq!(select(&document, "//p/following-sibling::p").unwrap().len(), 9_999);
+    assert_eq!(select(&document, "//p/preceding-sibling::p").unwrap().len(), 9_999);
+    assert_eq!(select(&document, "//p/following::text()").unwrap().len(), 9_999);
+    assert_eq!(select(&document, "//p/preceding::p").unwrap().len(), 9_999);
+    assert_eq!(select(&document, "//body//p").unwrap().len(), 10_000);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn evaluating_per_row() {
+    // The document order is built once, not again for every row
+    let html = "<table>".to_string() + &"<tr><td>a</td><td>b</td></tr>".repeat(10_000) + "</table>";
+    let document = Document::parse(&html);
+    let rows: Vec<_> = select(&document, "//tr").unwrap().into_iter().map(|row| match row {
+        XPathNode::Node(id) => id,
+        XPathNode::Attribute(..) => unreachable!(),
+    }).collect();
+    let xpath = XPath::parse("td[2]").unwrap();
+    let started = std::time::Instant::now();
+    assert!(rows.iter().all(|&row| xpath.string(&document, row) == "b"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        ("//p[", XPathErrorKind::UnexpectedEnd, 4),
+        ("//p]", XPathErrorKind::UnexpectedCharacter, 3),
+        ("/child::", XPathErrorKind::UnexpectedEnd, 8),
+        ("sideways::p", XPathErrorKind::UnknownAxis, 0),
+        ("upper-case('a')", XPathErrorKind::UnknownFunction, 0),
+        ("foo:p", XPathErrorKind::UnknownPrefix, 0),
+        ("count()", XPathErrorKind::WrongArgumentCount, 0),
+        ("concat('a')", XPathErrorKind::WrongArgumentCount, 0),
+        ("count('a')", XPathErrorKind::NotANodeSet, 6),
+        ("'a' | //p", XPathErrorKind::NotANodeSet, 0),
+        ("'a'[1]", XPathErrorKind::NotANodeSet, 0),
+        ("string(.)/p", XPathErrorKind::NotANodeSet, 0),
+        ("$x", XPathErrorKind::UnsupportedVariable, 0),
+        ("'unterminated", XPathErrorKind::UnexpectedEnd, 13),
+        ("p foo", XPathErrorKind::UnexpectedCharacter, 2),
+        ("1 +", XPathErrorKind::UnexpectedEnd, 3),
+    ];
+    for (expression, kind, position) in cases {
+        let error = XPath::parse(expression).unwrap_err();
+        assert_eq!((error.kind, error.position), (kind, position), "for {:?}", expression);
+    }
+    let document = Document::parse(HTML);
+    assert_eq!(select(&document, "count(//p)").unwrap_err().kind, XPathErrorKind::NotANodeSet);
+}